@@ -0,0 +1 @@
+curl http://127.0.0.1:8001/v1/ast-status
@@ -0,0 +1,6 @@
+curl http://127.0.0.1:8001/v1/settings
+
+curl http://127.0.0.1:8001/v1/settings -X POST -H 'Content-Type: application/json' -d '{
+  "ast": true,
+  "vecdb": false
+}'
@@ -0,0 +1,54 @@
+# pip install pylspclient
+import pylspclient
+import socket
+import termcolor
+
+
+hello_py = "def hello_world(names):\n    for i in range(len(names)):\n        print(\"hello\", names[i])\n"
+
+
+def main():
+    s = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    s.connect(("127.0.0.1", 8002))
+    pipein, pipeout = s.makefile("wb", buffering=0), s.makefile("rb", buffering=0)
+    json_rpc_endpoint = pylspclient.JsonRpcEndpoint(pipein, pipeout)
+    lsp_endpoint = pylspclient.LspEndpoint(json_rpc_endpoint)
+    lsp_client = pylspclient.LspClient(lsp_endpoint)
+    capabilities = {}
+    root_uri = 'file:///workspace'
+    workspace_folders = [{'name': 'workspace', 'uri': root_uri}]
+    lsp_client.initialize(1337, None, root_uri, None, capabilities, "off", workspace_folders)
+    uri = "file:///workspace/hello.py"
+    languageId = pylspclient.lsp_structs.LANGUAGE_IDENTIFIER.PYTHON
+    lsp_client.didOpen(pylspclient.lsp_structs.TextDocumentItem(uri, languageId, version=1, text=hello_py))
+
+    # the whole function is selected, the toolbox commands that need a selection show up
+    selection = {"start": {"line": 0, "character": 0}, "end": {"line": 3, "character": 0}}
+    actions = lsp_client.lsp_endpoint.call_method(
+        "textDocument/codeAction",
+        textDocument=pylspclient.lsp_structs.TextDocumentIdentifier(uri),
+        range=selection,
+        context={"diagnostics": []},
+    )
+    for action in actions:
+        print("%-10s %s" % (action["data"]["toolbox_command"], action["title"]))
+    shorter = [x for x in actions if x["data"]["toolbox_command"] == "shorter"][0]
+
+    # the model runs only when the user picks the action
+    resolved = lsp_client.lsp_endpoint.call_method("codeAction/resolve", **shorter)
+    edit = resolved["edit"]["changes"][uri][0]
+    print("edit range:", edit["range"])
+    try:
+        lsp_client.shutdown()
+    except Exception:
+        pass
+    lsp_endpoint.join()
+
+    print("%s%s" % (
+        termcolor.colored(hello_py, "red"),
+        termcolor.colored(edit["newText"], "green")
+    ))
+
+
+if __name__ == "__main__":
+    main()
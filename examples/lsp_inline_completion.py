@@ -0,0 +1,53 @@
+# pip install pylspclient
+import pylspclient
+import socket
+import termcolor
+
+
+hello_py = "def hello_world():\n    "
+
+
+def main():
+    s = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    s.connect(("127.0.0.1", 8002))
+    pipein, pipeout = s.makefile("wb", buffering=0), s.makefile("rb", buffering=0)
+    json_rpc_endpoint = pylspclient.JsonRpcEndpoint(pipein, pipeout)
+    lsp_endpoint = pylspclient.LspEndpoint(json_rpc_endpoint)
+    lsp_client = pylspclient.LspClient(lsp_endpoint)
+    capabilities = {}
+    root_uri = 'file:///workspace'
+    workspace_folders = [{'name': 'workspace', 'uri': root_uri}]
+    lsp_client.initialize(1337, None, root_uri, None, capabilities, "off", workspace_folders)
+    uri = "file:///workspace/hello.py"
+    languageId = pylspclient.lsp_structs.LANGUAGE_IDENTIFIER.PYTHON
+    lsp_client.didOpen(pylspclient.lsp_structs.TextDocumentItem(uri, languageId, version=1, text=hello_py))
+
+    res = lsp_client.lsp_endpoint.call_method(
+        "textDocument/inlineCompletion",
+        textDocument=pylspclient.lsp_structs.TextDocumentIdentifier(uri),
+        position=pylspclient.lsp_structs.Position(1, 4),
+        context={"triggerKind": 1},   # 1 invoked, 2 automatic
+    )
+    print("inline completion result:", res)
+    item = res["items"][0]
+    # the client runs the item's command when the user accepts it
+    accepted = lsp_client.lsp_endpoint.call_method(
+        "workspace/executeCommand",
+        command=item["command"]["command"],
+        arguments=item["command"]["arguments"],
+    )
+    print("accepted:", accepted)
+    try:
+        lsp_client.shutdown()
+    except Exception:
+        pass
+    lsp_endpoint.join()
+
+    print("%s%s" % (
+        termcolor.colored(hello_py, "green"),
+        termcolor.colored(item["insertText"], "magenta")
+    ))
+
+
+if __name__ == "__main__":
+    main()
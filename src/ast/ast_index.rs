@@ -1,6 +1,7 @@
 use std::cmp::max;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
+use std::sync::Mutex as StdMutex;
 
 use fst::{Set, set, Streamer};
 use rayon::current_num_threads;
@@ -10,11 +11,13 @@ use strsim::jaro_winkler;
 use tracing::{debug, info};
 use tree_sitter::Range;
 
+use crate::ast::comments_wrapper::get_language_id_by_filename;
 use crate::ast::fst_extra_automation::Substring;
-use crate::ast::structs::SymbolsSearchResultStruct;
+use crate::ast::structs::{CallGraphDirection, SymbolsSearchResultStruct};
+use crate::ast::treesitter::document_trees::{DocumentTrees, parse_document_tree};
 use crate::ast::treesitter::language_id::LanguageId;
 use crate::ast::treesitter::parsers::get_parser_by_filename;
-use crate::ast::treesitter::structs::{SymbolDeclarationStruct, UsageSymbolInfo};
+use crate::ast::treesitter::structs::{ImportInfo, SymbolDeclarationStruct, SymbolType, UsageSymbolInfo};
 use crate::files_in_workspace::DocumentInfo;
 
 #[derive(Debug)]
@@ -23,8 +26,12 @@ pub struct AstIndex {
     declarations_search_index: HashMap<PathBuf, Set<Vec<u8>>>,
     usages: HashMap<String, Vec<Box<dyn UsageSymbolInfo>>>,
     usages_search_index: HashMap<PathBuf, Set<Vec<u8>>>,
+    imports: HashMap<PathBuf, Vec<ImportInfo>>,
+    usage_locations: HashMap<PathBuf, Vec<(String, Option<String>, Range)>>,  // usage name, meta_path of a call, where it is, for the references
 }
 
+pub type ParsedDocument = (HashMap<String, SymbolDeclarationStruct>, Vec<Box<dyn UsageSymbolInfo>>, Vec<ImportInfo>);
+
 
 fn make_a_query(
     nodes_indexes: &HashMap<PathBuf, Set<Vec<u8>>>,
@@ -60,26 +67,42 @@ impl AstIndex {
             declarations_search_index: HashMap::new(),
             usages: HashMap::new(),
             usages_search_index: HashMap::new(),
+            imports: HashMap::new(),
+            usage_locations: HashMap::new(),
         }
     }
 
-    pub fn get_declarations_and_usages(doc: &DocumentInfo)
-                                       -> Result<(HashMap<String, SymbolDeclarationStruct>, Vec<Box<dyn UsageSymbolInfo>>), String> {
+    pub fn get_declarations_and_usages(doc: &DocumentInfo) -> Result<ParsedDocument, String> {
+        let text = match doc.read_file_blocked() {
+            Ok(s) => s,
+            Err(e) => return Err(e.to_string())
+        };
+        AstIndex::get_declarations_and_usages_from_text(doc, &text, None)
+    }
+
+    pub fn get_declarations_and_usages_from_text(
+        doc: &DocumentInfo,
+        text: &String,
+        document_trees: Option<&StdMutex<DocumentTrees>>,  // for the documents open in IDE, the previous tree is reused
+    ) -> Result<ParsedDocument, String> {
         let path = doc.get_path();
-        let mut parser = match get_parser_by_filename(&doc.get_path()) {
+        let mut parser = match get_parser_by_filename(&path) {
             Ok(parser) => parser,
             Err(err) => {
                 return Err(err.message);
             }
         };
-        let text = match doc.read_file_blocked() {
-            Ok(s) => s,
-            Err(e) => return Err(e.to_string())
-        };
 
-        // Parse the text and get the declarations and usages
+        let t_tree = std::time::Instant::now();
+        let tree = match document_trees {
+            Some(document_trees) => parse_document_tree(document_trees, &doc.uri, parser.as_mut(), text.as_str()),
+            None => parser.parse_tree(text.as_str(), None),
+        }.map_err(|e| format!("Error parsing {}: {}", path.display(), e))?;
+        let t_tree_elapsed = t_tree.elapsed();
+
+        // Get the declarations and usages from the tree
         let t_declarations = std::time::Instant::now();
-        let declarations = match parser.parse_declarations(text.as_str(), &path) {
+        let declarations = match parser.parse_declarations_from_tree(&tree, text.as_str(), &path) {
             Ok(declarations) => declarations,
             Err(e) => {
                 return Err(format!("Error parsing {}: {}", path.display(), e));
@@ -88,7 +111,7 @@ impl AstIndex {
         let t_declarations_elapsed = t_declarations.elapsed();
 
         let t_usages = std::time::Instant::now();
-        let mut usages = match parser.parse_usages(text.as_str(), false) {
+        let mut usages = match parser.parse_usages_from_tree(&tree, text.as_str(), false) {
             Ok(usages) => usages,
             Err(e) => {
                 return Err(format!("Error parsing {}: {}", path.display(), e));
@@ -96,19 +119,28 @@ impl AstIndex {
         };
         link_declarations_to_usages(&declarations, &mut usages);
         let t_usages_elapsed = t_usages.elapsed();
+        let imports = match parser.parse_imports_from_tree(&tree, text.as_str()) {
+            Ok(imports) => imports,
+            Err(e) => {
+                // declarations and usages are still good, calls just won't resolve through the imports
+                info!("cannot parse imports of {}: {}", path.display(), e);
+                vec![]
+            }
+        };
         info!(
             "parsed {},  {} definitions, {} usages, \
-            took {:.3}s to parse decls, took {:.3}s to parse refs",
+            took {:.3}s to parse, {:.3}s to find decls, {:.3}s to find refs",
             crate::nicer_logs::last_n_chars(&path.display().to_string(), 30),
             declarations.len(), usages.len(),
-            t_declarations_elapsed.as_secs_f32(), t_usages_elapsed.as_secs_f32()
+            t_tree_elapsed.as_secs_f32(), t_declarations_elapsed.as_secs_f32(), t_usages_elapsed.as_secs_f32()
         );
-        Ok((declarations, usages))
+        Ok((declarations, usages, imports))
     }
 
     pub fn add_or_update_declarations_and_usages(&mut self, doc: &DocumentInfo,
                                                  declarations: HashMap<String, SymbolDeclarationStruct>,
-                                                 usages: Vec<Box<dyn UsageSymbolInfo>>) -> Result<(), String> {
+                                                 usages: Vec<Box<dyn UsageSymbolInfo>>,
+                                                 imports: Vec<ImportInfo>) -> Result<(), String> {
         let path = doc.get_path();
         // Remove old data from all search indexes
         match self.remove(&doc) {
@@ -130,6 +162,9 @@ impl AstIndex {
 
         // Insert new data to the usages search index
         let mut usages_meta_names: SortedVec<String> = SortedVec::new();
+        self.usage_locations.insert(path.clone(), usages.iter()
+            .map(|x| (x.name(), Some(x.meta_path()).filter(|_| x.type_str() == "function_call_info"), x.get_range()))
+            .collect());
         for usage in usages {
             usages_meta_names.push(usage.meta_path());
             self.usages.entry(usage.meta_path()).or_default().push(usage);
@@ -139,12 +174,15 @@ impl AstIndex {
             Err(e) => return Err(format!("Error creating set: {}", e)),
         };
         self.usages_search_index.insert(path.clone(), meta_names_set);
+        if !imports.is_empty() {
+            self.imports.insert(path.clone(), imports);
+        }
         Ok(())
     }
 
     pub fn add_or_update(&mut self, doc: &DocumentInfo) -> Result<(), String> {
-        let (declarations, usages) = AstIndex::get_declarations_and_usages(doc)?;
-        self.add_or_update_declarations_and_usages(doc, declarations, usages)
+        let (declarations, usages, imports) = AstIndex::get_declarations_and_usages(doc)?;
+        self.add_or_update_declarations_and_usages(doc, declarations, usages, imports)
     }
 
     pub fn remove(&mut self, doc: &DocumentInfo) -> Result<(), String> {
@@ -173,6 +211,62 @@ impl AstIndex {
                 self.usages.remove(&name);
             }
         }
+        self.imports.remove(&path);
+        self.usage_locations.remove(&path);
+        Ok(())
+    }
+
+    pub fn rename(&mut self, old_doc: &DocumentInfo, new_doc: &DocumentInfo) -> Result<(), String> {
+        // the file did not change, only the path in its symbols did, no need to parse it again
+        let old_path = old_doc.get_path();
+        let new_path = new_doc.get_path();
+        self.remove(new_doc)?;
+
+        let mut meta_names: SortedVec<String> = SortedVec::new();
+        if let Some(old_meta_names) = self.declarations_search_index.remove(&old_path) {
+            let mut stream = old_meta_names.stream();
+            while let Some(name_vec) = stream.next() {
+                let name = match String::from_utf8(name_vec.to_vec()) {
+                    Ok(name) => name,
+                    Err(_) => {
+                        continue;
+                    }
+                };
+                if let Some(mut declaration) = self.declarations.remove(&name) {
+                    rename_declaration(&mut declaration, &old_path, &new_path);
+                    meta_names.push(declaration.meta_path.clone());
+                    self.declarations.insert(declaration.meta_path.clone(), declaration);
+                }
+            }
+            let meta_names_set = match Set::from_iter(meta_names.iter()) {
+                Ok(set) => set,
+                Err(e) => return Err(format!("Error creating set: {}", e)),
+            };
+            self.declarations_search_index.insert(new_path.clone(), meta_names_set);
+        }
+
+        // the usages are keyed by their own names, only the declarations they point to have moved
+        if let Some(usages_meta_names) = self.usages_search_index.remove(&old_path) {
+            let mut stream = usages_meta_names.stream();
+            while let Some(name_vec) = stream.next() {
+                let name = match String::from_utf8(name_vec.to_vec()) {
+                    Ok(name) => name,
+                    Err(_) => {
+                        continue;
+                    }
+                };
+                for usage in self.usages.get_mut(&name).into_iter().flatten() {
+                    rename_usage(usage.as_mut(), &old_path, &new_path);
+                }
+            }
+            self.usages_search_index.insert(new_path.clone(), usages_meta_names);
+        }
+        if let Some(imports) = self.imports.remove(&old_path) {
+            self.imports.insert(new_path.clone(), imports);
+        }
+        if let Some(locations) = self.usage_locations.remove(&old_path) {
+            self.usage_locations.insert(new_path.clone(), locations);
+        }
         Ok(())
     }
 
@@ -181,6 +275,8 @@ impl AstIndex {
         self.declarations_search_index.clear();
         self.usages.clear();
         self.usages_search_index.clear();
+        self.imports.clear();
+        self.usage_locations.clear();
     }
 
     pub fn search_declarations(
@@ -330,6 +426,389 @@ impl AstIndex {
     pub fn get_indexed_file_paths(&self) -> Vec<PathBuf> {
         self.usages_search_index.iter().map(|(path, _)| path.clone()).collect()
     }
+
+    pub fn search_usage_locations(
+        &self,
+        name: &str,
+        language: Option<LanguageId>,
+        declarations: &Vec<String>,
+        top_n: usize,
+    ) -> Vec<(PathBuf, Range)> {
+        // the usages with this name, a call that resolves to some other declaration is left out,
+        // the type of the object is not known for most of them, those stay
+        let mut result: Vec<(PathBuf, Range)> = vec![];
+        for (path, locations) in self.usage_locations.iter() {
+            if language.is_some() && get_language_id_by_filename(path) != language {
+                continue;
+            }
+            for (usage_name, call_meta_path, range) in locations.iter() {
+                if usage_name != name {
+                    continue;
+                }
+                if let Some(call_meta_path) = call_meta_path.as_ref().filter(|_| !declarations.is_empty()) {
+                    let resolved = self.resolve_usage(path, call_meta_path);
+                    if !resolved.is_empty() && !resolved.iter().any(|x| declarations.contains(x)) {
+                        continue;
+                    }
+                }
+                result.push((path.clone(), range.clone()));
+            }
+        }
+        result.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.start_byte.cmp(&b.1.start_byte)));
+        result.truncate(top_n);
+        result
+    }
+
+    pub fn get_counts(&self) -> (usize, usize, usize) {
+        // files, declarations, usages
+        (self.declarations_search_index.len(), self.declarations.len(), self.usages.values().map(|x| x.len()).sum())
+    }
+
+    pub fn get_top_level_symbols_count(&self) -> HashMap<PathBuf, usize> {
+        // meta_path is "file::Name" for top level symbols, "file::Class::method" for nested ones
+        let mut result: HashMap<PathBuf, usize> = HashMap::new();
+        for decl in self.declarations.values() {
+            let prefix = format!("{}::", decl.definition_info.path.to_str().unwrap_or_default());
+            match decl.meta_path.strip_prefix(&prefix) {
+                Some(rest) if !rest.contains("::") => {
+                    *result.entry(decl.definition_info.path.clone()).or_insert(0) += 1;
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+
+    fn find_declarations_by_symbol(&self, symbol: &str) -> Vec<String> {
+        // a full meta_path from the completion, "Class::method", or just a name
+        if self.declarations.contains_key(symbol) {
+            return vec![symbol.to_string()];
+        }
+        let suffix = format!("::{}", symbol);
+        let mut result: Vec<String> = self.declarations.iter()
+            .filter(|(meta_path, decl)| meta_path.ends_with(&suffix) || decl.name == symbol)
+            .map(|(meta_path, _)| meta_path.clone())
+            .collect();
+        result.sort();
+        result
+    }
+
+    pub fn get_declarations_by_symbol(&self, symbol: &str) -> Vec<SymbolDeclarationStruct> {
+        self.find_declarations_by_symbol(symbol).iter()
+            .filter_map(|x| self.declarations.get(x).cloned())
+            .collect()
+    }
+
+    fn module_files(&self, importing_file: &PathBuf, module: &str) -> Vec<PathBuf> {
+        // pkg.sub is pkg/sub.py or pkg/sub/__init__.py anywhere in the workspace, .sub and ..sub are next to the importing file
+        let dots = module.chars().take_while(|c| *c == '.').count();
+        let relative: PathBuf = module[dots..].split('.').filter(|x| !x.is_empty()).collect();
+        if dots > 0 {
+            let mut base = match importing_file.parent() {
+                Some(x) => x.to_path_buf(),
+                None => return vec![],
+            };
+            for _ in 1..dots {
+                base = match base.parent() {
+                    Some(x) => x.to_path_buf(),
+                    None => return vec![],
+                };
+            }
+            let base = base.join(&relative);
+            let mut candidates = vec![base.join("__init__.py")];
+            if relative.components().count() > 0 {
+                candidates.push(base.with_extension("py"));
+            }
+            return candidates.into_iter().filter(|x| self.declarations_search_index.contains_key(x)).collect();
+        }
+        if relative.components().count() == 0 {
+            return vec![];
+        }
+        let as_file = relative.with_extension("py");
+        let as_package = relative.join("__init__.py");
+        let mut result: Vec<PathBuf> = self.declarations_search_index.keys()
+            .filter(|x| x.ends_with(&as_file) || x.ends_with(&as_package))
+            .cloned()
+            .collect();
+        result.sort();
+        result
+    }
+
+    fn resolve_usage(&self, path: &PathBuf, usage_meta_path: &str) -> Vec<String> {
+        // usage_meta_path is "name" or "name::object" for calls, the file imports tell which declaration it is
+        let mut parts = usage_meta_path.splitn(2, "::");
+        let name = parts.next().unwrap_or_default();
+        let object = parts.next();
+        let no_imports = vec![];
+        let imports = self.imports.get(path).unwrap_or(&no_imports);
+        let in_file = |file: &PathBuf, tail: &str| format!("{}::{}", file.to_str().unwrap_or_default(), tail);
+        let mut meta_paths: Vec<String> = vec![];
+        match object {
+            None => {
+                meta_paths.push(in_file(path, name));
+                for import in imports.iter().filter(|x| x.alias == name) {
+                    if let Some(imported) = &import.name {
+                        for file in self.module_files(path, &import.module) {
+                            meta_paths.push(in_file(&file, imported));
+                        }
+                    }
+                }
+            }
+            Some("self") | Some("cls") => {
+                let prefix = format!("{}::", path.to_str().unwrap_or_default());
+                meta_paths.extend(self.declarations.iter()
+                    .filter(|(meta_path, decl)| decl.name == name && decl.symbol_type == SymbolType::Method && meta_path.starts_with(&prefix))
+                    .map(|(meta_path, _)| meta_path.clone()));
+            }
+            Some(object) => {
+                meta_paths.push(in_file(path, &format!("{}::{}", object, name)));
+                for import in imports.iter() {
+                    match &import.name {
+                        Some(imported) if import.alias == object => {
+                            // from pkg import module; module.f() or from module import Class; Class.f()
+                            let submodule = if import.module.ends_with('.') {
+                                format!("{}{}", import.module, imported)
+                            } else {
+                                format!("{}.{}", import.module, imported)
+                            };
+                            for file in self.module_files(path, &submodule) {
+                                meta_paths.push(in_file(&file, name));
+                            }
+                            for file in self.module_files(path, &import.module) {
+                                meta_paths.push(in_file(&file, &format!("{}::{}", imported, name)));
+                            }
+                        }
+                        Some(_) => {}
+                        None => {
+                            if let Some(module) = module_bound_to(import, object) {
+                                for file in self.module_files(path, &module) {
+                                    meta_paths.push(in_file(&file, name));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        meta_paths.sort();
+        meta_paths.dedup();
+        meta_paths.retain(|x| self.declarations.contains_key(x));
+        meta_paths
+    }
+
+    pub fn search_declarations_by_imports(&self, path: &PathBuf, usage_meta_path: &str) -> Vec<SymbolsSearchResultStruct> {
+        // exact matches, so there is no need for the fuzzy search_declarations()
+        let mut search_results: Vec<SymbolsSearchResultStruct> = vec![];
+        for meta_path in self.resolve_usage(path, usage_meta_path) {
+            let decl = match self.declarations.get(&meta_path) {
+                Some(x) => x,
+                None => continue,
+            };
+            let content = match decl.get_content_blocked() {
+                Ok(content) => content,
+                Err(err) => {
+                    info!("Error opening the file {:?}: {}", decl.definition_info.path, err);
+                    continue;
+                }
+            };
+            search_results.push(SymbolsSearchResultStruct {
+                symbol_declaration: decl.clone(),
+                content,
+                sim_to_query: 1.0,
+            });
+        }
+        search_results
+    }
+
+    pub fn get_usages_enclosing_declarations(&self, meta_paths: &HashSet<String>) -> Vec<SymbolDeclarationStruct> {
+        // where the declarations are used: the functions and classes around the usages, comments and literals don't count;
+        // as in callers_of(), a usage that resolves to another declaration with the same name is not counted
+        let names: HashSet<&str> = meta_paths.iter()
+            .filter_map(|x| self.declarations.get(x))
+            .map(|x| x.name.as_str())
+            .collect();
+        let mut result: Vec<String> = vec![];
+        for (usage_meta_path, usages) in self.usages.iter() {
+            if !names.contains(usage_meta_path.split("::").next().unwrap_or_default()) {
+                continue;
+            }
+            for usage in usages.iter().filter(|u| u.type_str() != "static_info") {
+                let enclosing = match usage.get_declaration_meta_path() {
+                    Some(x) => x,
+                    None => continue,
+                };
+                if let Some(enclosing_decl) = self.declarations.get(&enclosing) {
+                    let resolved = self.resolve_usage(&enclosing_decl.definition_info.path, &usage.meta_path());
+                    if !resolved.is_empty() && !resolved.iter().any(|x| meta_paths.contains(x)) {
+                        continue;
+                    }
+                }
+                result.push(enclosing);
+            }
+        }
+        result.sort();
+        result.dedup();
+        result.iter().filter_map(|x| self.declarations.get(x).cloned()).collect()
+    }
+
+    fn callers_of(&self, decl: &SymbolDeclarationStruct) -> Vec<String> {
+        // usages are keyed by "name" or "name::CallerType" for function calls, linked to the enclosing declaration
+        let mut result: Vec<String> = vec![];
+        for (usage_meta_path, usages) in self.usages.iter() {
+            if usage_meta_path.split("::").next() != Some(decl.name.as_str()) {
+                continue;
+            }
+            for usage in usages.iter().filter(|u| u.type_str() == "function_call_info") {
+                let caller = match usage.get_declaration_meta_path() {
+                    Some(x) if x != decl.meta_path => x,
+                    _ => continue,
+                };
+                // a call that resolves to another declaration with the same name is not a caller, the bare name is the fallback
+                if let Some(caller_decl) = self.declarations.get(&caller) {
+                    let resolved = self.resolve_usage(&caller_decl.definition_info.path, &usage.meta_path());
+                    if !resolved.is_empty() && !resolved.contains(&decl.meta_path) {
+                        continue;
+                    }
+                }
+                result.push(caller);
+            }
+        }
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    fn calls_by_declaration(&self) -> HashMap<String, HashSet<String>> {
+        // function calls keyed by the declaration around them, a call in a nested declaration counts for the outer ones too
+        let mut result: HashMap<String, HashSet<String>> = HashMap::new();
+        for usage in self.usages.values().flatten().filter(|u| u.type_str() == "function_call_info") {
+            let mut enclosing = match usage.get_declaration_meta_path() {
+                Some(x) => x,
+                None => continue,
+            };
+            loop {
+                result.entry(enclosing.clone()).or_default().insert(usage.meta_path());
+                match enclosing.rsplit_once("::") {
+                    Some((outer, _)) if self.declarations.contains_key(outer) => enclosing = outer.to_string(),
+                    _ => break,
+                }
+            }
+        }
+        result
+    }
+
+    fn callees_of(&self, decl: &SymbolDeclarationStruct, calls_by_declaration: &HashMap<String, HashSet<String>>) -> Vec<String> {
+        // only the calls that resolve in the same file or through its imports, a bare name could be any function in the workspace
+        let mut result: Vec<String> = vec![];
+        for usage_meta_path in calls_by_declaration.get(&decl.meta_path).into_iter().flatten() {
+            let resolved = self.resolve_usage(&decl.definition_info.path, usage_meta_path);
+            result.extend(resolved.into_iter().filter(|x| *x != decl.meta_path));
+        }
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    pub fn search_call_graph(
+        &self,
+        symbol: &str,
+        direction: CallGraphDirection,
+        depth: usize,
+        max_results: usize,
+    ) -> Result<Vec<(SymbolDeclarationStruct, usize)>, String> {
+        // breadth-first, the symbol itself is at distance 0, each declaration is visited once
+        let start = self.find_declarations_by_symbol(symbol);
+        if start.is_empty() {
+            return Err(format!("symbol {} is not found in the AST index", symbol));
+        }
+        let calls_by_declaration = if direction == CallGraphDirection::Callees {
+            self.calls_by_declaration()
+        } else {
+            HashMap::new()
+        };
+        let mut visited: HashSet<String> = start.iter().cloned().collect();
+        let mut result: Vec<(SymbolDeclarationStruct, usize)> = vec![];
+        let mut frontier: Vec<String> = vec![];
+        for meta_path in start.into_iter().take(max_results) {
+            if let Some(decl) = self.declarations.get(&meta_path) {
+                result.push((decl.clone(), 0));
+                frontier.push(meta_path);
+            }
+        }
+        for distance in 1..=depth {
+            let mut next_frontier: Vec<String> = vec![];
+            for meta_path in frontier.iter() {
+                let decl = match self.declarations.get(meta_path) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                let neighbours = match direction {
+                    CallGraphDirection::Callers => self.callers_of(decl),
+                    CallGraphDirection::Callees => self.callees_of(decl, &calls_by_declaration),
+                };
+                for n in neighbours {
+                    if result.len() >= max_results {
+                        return Ok(result);
+                    }
+                    if !visited.insert(n.clone()) {
+                        continue;
+                    }
+                    if let Some(n_decl) = self.declarations.get(&n) {
+                        result.push((n_decl.clone(), distance));
+                        next_frontier.push(n);
+                    }
+                }
+            }
+            if next_frontier.is_empty() {
+                break;
+            }
+            frontier = next_frontier;
+        }
+        Ok(result)
+    }
+}
+
+fn module_bound_to(import: &ImportInfo, object: &str) -> Option<String> {
+    // import os.path binds os: os.f() is in os, os.path.f() is in os.path; import numpy as np binds numpy to np
+    let rest = object.strip_prefix(import.alias.as_str())?;
+    if !rest.is_empty() && !rest.starts_with('.') {
+        return None;
+    }
+    let implicit = import.module == import.alias || import.module.starts_with(&format!("{}.", import.alias));
+    let bound = if implicit { &import.alias } else { &import.module };
+    Some(format!("{}{}", bound, rest))
+}
+
+pub(crate) fn rename_meta_path(meta_path: &str, old_path: &PathBuf, new_path: &PathBuf) -> Option<String> {
+    // meta paths start with the file path: "/path/to/file.py::Class::method"
+    let rest = meta_path.strip_prefix(old_path.to_str()?)?;
+    if !rest.starts_with("::") {
+        return None;
+    }
+    Some(format!("{}{}", new_path.to_str()?, rest))
+}
+
+pub(crate) fn rename_declaration(declaration: &mut SymbolDeclarationStruct, old_path: &PathBuf, new_path: &PathBuf) {
+    if let Some(meta_path) = rename_meta_path(&declaration.meta_path, old_path, new_path) {
+        declaration.meta_path = meta_path;
+    }
+    if declaration.definition_info.path == *old_path {
+        declaration.definition_info.path = new_path.clone();
+    }
+    for extra in declaration.extra_declarations.iter_mut().filter(|x| x.path == *old_path) {
+        extra.path = new_path.clone();
+    }
+    for child in declaration.children.iter_mut() {
+        rename_declaration(child, old_path, new_path);
+    }
+}
+
+pub(crate) fn rename_usage(usage: &mut dyn UsageSymbolInfo, old_path: &PathBuf, new_path: &PathBuf) {
+    let declaration_meta_path = usage.get_declaration_meta_path()
+        .and_then(|x| rename_meta_path(&x, old_path, new_path));
+    if let Some(meta_path) = declaration_meta_path {
+        usage.set_definition_meta_path(meta_path);
+    }
 }
 
 fn link_declarations_to_usages(
@@ -349,11 +828,12 @@ fn link_declarations_to_usages(
         let range = usage.get_range();
         for (meta_path, declaration) in declarations.iter() {
             if within_range(&declaration.definition_info.range, &range) {
+                // the innermost declaration around the usage wins, it has the fewest rows
                 let distance = max(
                     declaration.definition_info.range.end_point.row - declaration.definition_info.range.start_point.row,
                     0,
                 );
-                if closest_declaration.is_none() || closest_declaration_rows_count.unwrap_or(distance + 1) < distance {
+                if closest_declaration.is_none() || distance < closest_declaration_rows_count.unwrap_or(distance + 1) {
                     closest_declaration = Some(meta_path.clone());
                     closest_declaration_rows_count = Some(distance);
                 }
@@ -370,3 +850,154 @@ fn link_declarations_to_usages(
     }
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_declarations_to_usages_innermost() {
+        let path = PathBuf::from("/tmp/test.rs");
+        let code = "fn outer() {\n    fn inner() {\n        helper();\n    }\n    inner();\n}\n\nfn helper() {}\n";
+        let mut parser = get_parser_by_filename(&path).unwrap();
+        let declarations = parser.parse_declarations(code, &path).unwrap();
+        let mut usages = parser.parse_usages(code, false).unwrap();
+        link_declarations_to_usages(&declarations, &mut usages);
+
+        let linked: HashMap<usize, String> = usages.iter()
+            .map(|x| (x.get_range().start_point.row, x.get_declaration_meta_path().unwrap()))
+            .collect();
+        // helper() is inside both outer and inner, inner is the one that calls it
+        assert!(linked[&2].ends_with("::inner"), "{}", linked[&2]);
+        assert!(linked[&4].ends_with("::outer"), "{}", linked[&4]);
+    }
+
+    fn index_text(index: &mut AstIndex, path: &str, code: &str) {
+        let doc = DocumentInfo::from_pathbuf(&PathBuf::from(path)).unwrap();
+        let (declarations, usages, imports) = AstIndex::get_declarations_and_usages_from_text(&doc, &code.to_string(), None).unwrap();
+        index.add_or_update_declarations_and_usages(&doc, declarations, usages, imports).unwrap();
+    }
+
+    fn call_graph_names(index: &AstIndex, symbol: &str, direction: CallGraphDirection) -> Vec<(String, usize)> {
+        index.search_call_graph(symbol, direction, 3, 10).unwrap().into_iter()
+            .map(|(decl, distance)| (decl.meta_path, distance))
+            .collect()
+    }
+
+    #[test]
+    fn test_search_call_graph() {
+        let mut index = AstIndex::init();
+        index_text(&mut index, "/tmp/graph/a.py", "def helper():\n    pass\n\ndef middle():\n    helper()\n\ndef top():\n    middle()\n");
+        // the same name in another file, its callers are not callers of a.py::helper
+        index_text(&mut index, "/tmp/graph/b.py", "def helper():\n    pass\n\ndef other():\n    helper()\n");
+
+        let callers = call_graph_names(&index, "/tmp/graph/a.py::helper", CallGraphDirection::Callers);
+        assert_eq!(callers, vec![
+            ("/tmp/graph/a.py::helper".to_string(), 0),
+            ("/tmp/graph/a.py::middle".to_string(), 1),
+            ("/tmp/graph/a.py::top".to_string(), 2),
+        ]);
+
+        let callees = call_graph_names(&index, "/tmp/graph/a.py::top", CallGraphDirection::Callees);
+        assert_eq!(callees, vec![
+            ("/tmp/graph/a.py::top".to_string(), 0),
+            ("/tmp/graph/a.py::middle".to_string(), 1),
+            ("/tmp/graph/a.py::helper".to_string(), 2),
+        ]);
+
+        // helper is neither in c.py nor imported there, the functions with that name elsewhere are not its callees
+        index_text(&mut index, "/tmp/graph/c.py", "def lonely():\n    helper()\n");
+        let callees = call_graph_names(&index, "/tmp/graph/c.py::lonely", CallGraphDirection::Callees);
+        assert_eq!(callees, vec![("/tmp/graph/c.py::lonely".to_string(), 0)]);
+
+        assert!(index.search_call_graph("nonexistent", CallGraphDirection::Callers, 3, 10).is_err());
+    }
+
+    #[test]
+    fn test_resolve_usage_through_imports() {
+        let mut index = AstIndex::init();
+        index_text(&mut index, "/tmp/imports/pkg/__init__.py", "VERSION = 1\n");
+        index_text(&mut index, "/tmp/imports/pkg/shapes.py", "class Circle:\n    def area(self):\n        return 1\n\ndef make():\n    return Circle()\n");
+        index_text(&mut index, "/tmp/imports/pkg/util.py", "def helper():\n    pass\n");
+        let main = PathBuf::from("/tmp/imports/main.py");
+        index_text(&mut index, main.to_str().unwrap(), "import pkg.util\nfrom pkg.shapes import Circle\nfrom pkg import shapes\nfrom .pkg import util as u\n\n\
+            def run():\n    pkg.util.helper()\n    shapes.make()\n    Circle.area()\n    u.helper()\n");
+
+        assert_eq!(index.module_files(&main, "pkg"), vec![PathBuf::from("/tmp/imports/pkg/__init__.py")]);
+        assert_eq!(index.module_files(&main, ".pkg.util"), vec![PathBuf::from("/tmp/imports/pkg/util.py")]);
+        assert_eq!(index.module_files(&PathBuf::from("/tmp/imports/pkg/util.py"), ".shapes"), vec![PathBuf::from("/tmp/imports/pkg/shapes.py")]);
+
+        // import pkg.util binds pkg, pkg.util.helper() is in pkg/util.py
+        assert_eq!(index.resolve_usage(&main, "helper::pkg.util"), vec!["/tmp/imports/pkg/util.py::helper".to_string()]);
+        assert!(index.resolve_usage(&main, "helper::pkg").is_empty());
+        // from pkg import shapes is a module, from pkg.shapes import Circle is a class in it
+        assert_eq!(index.resolve_usage(&main, "make::shapes"), vec!["/tmp/imports/pkg/shapes.py::make".to_string()]);
+        assert_eq!(index.resolve_usage(&main, "area::Circle"), vec!["/tmp/imports/pkg/shapes.py::Circle::area".to_string()]);
+        assert_eq!(index.resolve_usage(&main, "Circle"), vec!["/tmp/imports/pkg/shapes.py::Circle".to_string()]);
+        // relative import with an alias
+        assert_eq!(index.resolve_usage(&main, "helper::u"), vec!["/tmp/imports/pkg/util.py::helper".to_string()]);
+        assert_eq!(index.resolve_usage(&main, "run"), vec!["/tmp/imports/main.py::run".to_string()]);
+    }
+
+    #[test]
+    fn test_get_usages_enclosing_declarations() {
+        let mut index = AstIndex::init();
+        index_text(&mut index, "/tmp/enclosing/a.py", "def helper():\n    pass\n");
+        index_text(&mut index, "/tmp/enclosing/b.py", "def helper():\n    pass\n\ndef test_b():\n    helper()\n");
+        index_text(&mut index, "/tmp/enclosing/tests/test_a.py", "from a import helper\n\ndef test_a():\n    helper()\n");
+        let names = |decls: Vec<SymbolDeclarationStruct>| -> Vec<String> { decls.into_iter().map(|x| x.meta_path).collect() };
+
+        // test_b calls the helper in b.py, not the one in a.py
+        let targets = HashSet::from(["/tmp/enclosing/a.py::helper".to_string()]);
+        assert_eq!(names(index.get_usages_enclosing_declarations(&targets)), vec!["/tmp/enclosing/tests/test_a.py::test_a".to_string()]);
+        let targets = HashSet::from(["/tmp/enclosing/b.py::helper".to_string()]);
+        assert_eq!(names(index.get_usages_enclosing_declarations(&targets)), vec!["/tmp/enclosing/b.py::test_b".to_string()]);
+    }
+
+    #[test]
+    fn test_search_usage_locations() {
+        let mut index = AstIndex::init();
+        index_text(&mut index, "/tmp/references/a.py", "def helper():\n    pass\n\ndef top():\n    helper()\n    helper()\n");
+        index_text(&mut index, "/tmp/references/b.py", "def helper():\n    pass\n\ndef other():\n    helper()\n");
+        let rows = |result: Vec<(PathBuf, Range)>| -> Vec<(String, usize)> {
+            result.into_iter().map(|(path, range)| (path.to_string_lossy().to_string(), range.start_point.row)).collect()
+        };
+
+        let all = rows(index.search_usage_locations("helper", None, &vec![], 10));
+        assert_eq!(all, vec![
+            ("/tmp/references/a.py".to_string(), 4),
+            ("/tmp/references/a.py".to_string(), 5),
+            ("/tmp/references/b.py".to_string(), 4),
+        ]);
+        // b.py calls its own helper
+        let scoped = rows(index.search_usage_locations("helper", None, &vec!["/tmp/references/a.py::helper".to_string()], 10));
+        assert_eq!(scoped, vec![("/tmp/references/a.py".to_string(), 4), ("/tmp/references/a.py".to_string(), 5)]);
+        assert_eq!(index.search_usage_locations("helper", None, &vec![], 2).len(), 2);
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut index = AstIndex::init();
+        let old_path = PathBuf::from("/tmp/rename/old.py");
+        let new_path = PathBuf::from("/tmp/rename/new.py");
+        index_text(&mut index, old_path.to_str().unwrap(), "def helper():\n    pass\n\ndef top():\n    helper()\n");
+        let counts = index.get_counts();
+
+        let old_doc = DocumentInfo::from_pathbuf(&old_path).unwrap();
+        let new_doc = DocumentInfo::from_pathbuf(&new_path).unwrap();
+        index.rename(&old_doc, &new_doc).unwrap();
+        assert_eq!(index.get_counts(), counts);
+        assert!(!index.declarations.contains_key("/tmp/rename/old.py::helper"));
+        let helper = index.declarations.get("/tmp/rename/new.py::helper").unwrap();
+        assert_eq!(helper.definition_info.path, new_path);
+        assert_eq!(index.get_indexed_file_paths(), vec![new_path.clone()]);
+
+        // the usages point to the moved declarations
+        let callers = call_graph_names(&index, "/tmp/rename/new.py::helper", CallGraphDirection::Callers);
+        assert_eq!(callers, vec![("/tmp/rename/new.py::helper".to_string(), 0), ("/tmp/rename/new.py::top".to_string(), 1)]);
+        let locations = index.search_usage_locations("helper", None, &vec!["/tmp/rename/new.py::helper".to_string()], 10);
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].0, new_path);
+    }
+}
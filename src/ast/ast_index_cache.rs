@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex as StdMutex;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::ast::ast_index::{AstIndex, ParsedDocument, rename_declaration, rename_usage};
+use crate::ast::treesitter::document_trees::DocumentTrees;
+use crate::ast::treesitter::structs::{ImportInfo, SymbolDeclarationStruct, UsageSymbolInfo};
+use crate::files_in_workspace::DocumentInfo;
+
+// parsers change between versions, so do their results
+const AST_CACHE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+
+#[derive(Serialize, Deserialize)]
+struct CachedDocument {
+    version: String,
+    path: PathBuf,
+    mtime: Option<SystemTime>,  // None if parsed from the text open in the IDE, it might be unsaved
+    content_hash: String,
+    declarations: HashMap<String, SymbolDeclarationStruct>,
+    usages: Vec<Box<dyn UsageSymbolInfo>>,
+    imports: Vec<ImportInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CacheStamp {
+    mtime: Option<SystemTime>,
+    content_hash: String,
+}
+
+#[derive(Debug)]
+pub struct AstIndexCache {
+    dir: PathBuf,
+    stamps: StdMutex<HashMap<PathBuf, CacheStamp>>,  // what AstIndex holds right now
+}
+
+fn content_hash(text: &str) -> String {
+    format!("{:x}", md5::compute(text))
+}
+
+fn file_mtime(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+impl AstIndexCache {
+    pub fn new(cache_dir: &PathBuf) -> Self {
+        AstIndexCache {
+            dir: cache_dir.join("ast_index"),
+            stamps: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    fn entry_path(&self, path: &PathBuf) -> PathBuf {
+        self.dir.join(format!("{:x}.json", md5::compute(path.to_string_lossy().as_bytes())))
+    }
+
+    fn read_entry(&self, entry_path: &PathBuf) -> Option<CachedDocument> {
+        let data = std::fs::read(entry_path).ok()?;
+        let entry: CachedDocument = serde_json::from_slice(&data).ok()?;
+        if entry.version != AST_CACHE_VERSION {
+            return None;
+        }
+        Some(entry)
+    }
+
+    fn write_entry(&self, entry: &CachedDocument) {
+        let entry_path = self.entry_path(&entry.path);
+        let result = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| serde_json::to_vec(entry).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)))
+            .and_then(|data| {
+                // write and rename, a half-written entry must not be loaded next time
+                let tmp_path = entry_path.with_extension("json.tmp");
+                std::fs::write(&tmp_path, data)?;
+                std::fs::rename(&tmp_path, &entry_path)
+            });
+        if let Err(e) = result {
+            info!("cannot save AST cache for {}: {}", entry.path.display(), e);
+        }
+    }
+
+    fn remember(&self, path: &PathBuf, stamp: CacheStamp) {
+        self.stamps.lock().unwrap().insert(path.clone(), stamp);
+    }
+
+    pub fn forget(&self, path: &PathBuf) {
+        // the file is gone from the workspace, its entry would only take space
+        self.stamps.lock().unwrap().remove(path);
+        let _ = std::fs::remove_file(self.entry_path(path));
+    }
+
+    pub fn forget_all(&self) {
+        // the entries stay, a reset parses the files again and the unchanged ones come from the cache
+        self.stamps.lock().unwrap().clear();
+    }
+
+    pub fn rename(&self, old_path: &PathBuf, new_path: &PathBuf) {
+        // the entry moves along with the file, it is still valid for the same content
+        let stamp = self.stamps.lock().unwrap().remove(old_path);
+        let old_entry_path = self.entry_path(old_path);
+        let entry = self.read_entry(&old_entry_path).filter(|x| x.path == *old_path);
+        let _ = std::fs::remove_file(&old_entry_path);
+        let mut entry = match entry {
+            Some(x) => x,
+            None => return,
+        };
+        entry.path = new_path.clone();
+        if entry.mtime.is_some() {
+            entry.mtime = file_mtime(new_path);
+        }
+        entry.declarations = entry.declarations.into_values()
+            .map(|mut x| {
+                rename_declaration(&mut x, old_path, new_path);
+                (x.meta_path.clone(), x)
+            })
+            .collect();
+        for usage in entry.usages.iter_mut() {
+            rename_usage(usage.as_mut(), old_path, new_path);
+        }
+        self.write_entry(&entry);
+        if let Some(stamp) = stamp {
+            self.remember(new_path, CacheStamp { mtime: entry.mtime, content_hash: stamp.content_hash });
+        }
+    }
+
+    pub fn load(&self, docs: &[DocumentInfo]) -> Vec<(DocumentInfo, ParsedDocument)> {
+        // the cache directory is shared by all projects, only the files asked for are loaded,
+        // and only if the entry still matches the file on disk, the rest will be parsed again
+        let t0 = std::time::Instant::now();
+        let mut result = vec![];
+        for doc in docs {
+            let path = doc.get_path();
+            if doc.document.is_some() || self.stamps.lock().unwrap().contains_key(&path) {
+                continue;
+            }
+            let entry = match self.read_entry(&self.entry_path(&path)) {
+                Some(x) if x.path == path && x.mtime.is_some() && x.mtime == file_mtime(&path) => x,
+                _ => continue,
+            };
+            self.remember(&path, CacheStamp { mtime: entry.mtime, content_hash: entry.content_hash.clone() });
+            result.push((doc.clone(), (entry.declarations, entry.usages, entry.imports)));
+        }
+        if !result.is_empty() {
+            info!("AST cache: loaded {} of {} files, took {:.3}s", result.len(), docs.len(), t0.elapsed().as_secs_f32());
+        }
+        result
+    }
+
+    pub fn is_same_content(&self, doc: &DocumentInfo) -> bool {
+        // the index has this file with the same text, whatever happened to its path or mtime
+        let text = match doc.read_file_blocked() {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+        match self.stamps.lock().unwrap().get(&doc.get_path()) {
+            Some(stamp) => stamp.content_hash == content_hash(&text),
+            None => false,
+        }
+    }
+
+    pub fn is_up_to_date(&self, doc: &DocumentInfo) -> bool {
+        // the index has this file as it is on disk, no need to even read it
+        if doc.document.is_some() {
+            return false;
+        }
+        let path = doc.get_path();
+        match self.stamps.lock().unwrap().get(&path) {
+            Some(stamp) => stamp.mtime.is_some() && stamp.mtime == file_mtime(&path),
+            None => false,
+        }
+    }
+
+    pub fn get_or_parse(&self, doc: &DocumentInfo, document_trees: Option<&StdMutex<DocumentTrees>>) -> Result<ParsedDocument, String> {
+        self.get_or_parse_timed(doc, document_trees).map(|(parsed, _)| parsed)
+    }
+
+    pub fn get_or_parse_timed(&self, doc: &DocumentInfo, document_trees: Option<&StdMutex<DocumentTrees>>) -> Result<(ParsedDocument, Option<f64>), String> {
+        // the parse time in ms, None if the result came from the cache
+        let path = doc.get_path();
+        let text = doc.read_file_blocked().map_err(|e| e.to_string())?;
+        let mtime = if doc.document.is_none() { file_mtime(&path) } else { None };
+        let stamp = CacheStamp { mtime, content_hash: content_hash(&text) };
+
+        if let Some(mut entry) = self.read_entry(&self.entry_path(&path)) {
+            if entry.path == path && entry.content_hash == stamp.content_hash {
+                if stamp.mtime.is_some() && entry.mtime != stamp.mtime {
+                    // touched but not changed, keep the entry loadable at startup
+                    entry.mtime = stamp.mtime;
+                    self.write_entry(&entry);
+                }
+                self.remember(&path, stamp);
+                return Ok(((entry.declarations, entry.usages, entry.imports), None));
+            }
+        }
+
+        let document_trees = if doc.document.is_some() { document_trees } else { None };
+        let t0 = std::time::Instant::now();
+        let (declarations, usages, imports) = AstIndex::get_declarations_and_usages_from_text(doc, &text, document_trees)?;
+        let parse_time_ms = t0.elapsed().as_secs_f64() * 1000.0;
+        let entry = CachedDocument {
+            version: AST_CACHE_VERSION.to_string(),
+            path: path.clone(),
+            mtime: stamp.mtime,
+            content_hash: stamp.content_hash.clone(),
+            declarations,
+            usages,
+            imports,
+        };
+        if entry.mtime.is_some() {
+            // an unsaved buffer must not replace what is saved for the file on disk
+            self.write_entry(&entry);
+        }
+        self.remember(&path, stamp);
+        Ok(((entry.declarations, entry.usages, entry.imports), Some(parse_time_ms)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ast_cache_roundtrip() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src_dir = tmp_dir.path().join("src");
+        std::fs::create_dir_all(&src_dir).unwrap();
+        let file_path = src_dir.join("main.rs");
+        std::fs::write(&file_path, "fn main() {\n    foo();\n}\n\nfn foo() {}\n").unwrap();
+        let doc = DocumentInfo::from_pathbuf(&file_path).unwrap();
+
+        let cache = AstIndexCache::new(&tmp_dir.path().join("cache"));
+        let ((declarations, _, _), parse_time_ms) = cache.get_or_parse_timed(&doc, None).unwrap();
+        assert_eq!(declarations.len(), 2);
+        assert!(parse_time_ms.is_some());
+        assert!(cache.is_up_to_date(&doc));
+        let (_, parse_time_ms) = AstIndexCache::new(&tmp_dir.path().join("cache")).get_or_parse_timed(&doc, None).unwrap();
+        assert!(parse_time_ms.is_none());
+
+        let cache2 = AstIndexCache::new(&tmp_dir.path().join("cache"));
+        assert!(!cache2.is_up_to_date(&doc));
+        let loaded = cache2.load(&[doc.clone()]);
+        assert_eq!(loaded.len(), 1);
+        let (_, (loaded_declarations, _, _)) = &loaded[0];
+        assert_eq!(*loaded_declarations, declarations);
+        assert!(cache2.is_up_to_date(&doc));
+
+        // a deleted file takes its entry along
+        cache2.forget(&file_path);
+        assert!(!cache2.is_up_to_date(&doc));
+        assert!(AstIndexCache::new(&tmp_dir.path().join("cache")).load(&[doc.clone()]).is_empty());
+    }
+
+    #[test]
+    fn test_ast_cache_rename() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src_dir = tmp_dir.path().join("src");
+        std::fs::create_dir_all(&src_dir).unwrap();
+        let old_path = src_dir.join("old.rs");
+        let new_path = src_dir.join("new.rs");
+        std::fs::write(&old_path, "fn main() {\n    foo();\n}\n\nfn foo() {}\n").unwrap();
+
+        let cache = AstIndexCache::new(&tmp_dir.path().join("cache"));
+        cache.get_or_parse(&DocumentInfo::from_pathbuf(&old_path).unwrap(), None).unwrap();
+        std::fs::rename(&old_path, &new_path).unwrap();
+        cache.rename(&old_path, &new_path);
+        let new_doc = DocumentInfo::from_pathbuf(&new_path).unwrap();
+        assert!(cache.is_up_to_date(&new_doc));
+
+        let loaded = AstIndexCache::new(&tmp_dir.path().join("cache")).load(&[new_doc.clone()]);
+        assert_eq!(loaded.len(), 1);
+        let (doc, (declarations, _, _)) = &loaded[0];
+        assert_eq!(doc.get_path(), new_path);
+        let prefix = format!("{}::", new_path.display());
+        assert!(declarations.iter().all(|(k, v)| k.starts_with(&prefix) && v.definition_info.path == new_path));
+    }
+
+    #[test]
+    fn test_ast_cache_other_files_and_unsaved_buffers() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let src_dir = tmp_dir.path().join("src");
+        std::fs::create_dir_all(&src_dir).unwrap();
+        let file_path = src_dir.join("main.rs");
+        let other_path = tmp_dir.path().join("other_project").join("lib.rs");
+        std::fs::create_dir_all(other_path.parent().unwrap()).unwrap();
+        std::fs::write(&file_path, "fn main() {\n    foo();\n}\n\nfn foo() {}\n").unwrap();
+        std::fs::write(&other_path, "fn bar() {}\n").unwrap();
+        let doc = DocumentInfo::from_pathbuf(&file_path).unwrap();
+
+        let cache = AstIndexCache::new(&tmp_dir.path().join("cache"));
+        cache.get_or_parse(&doc, None).unwrap();
+        cache.get_or_parse(&DocumentInfo::from_pathbuf(&other_path).unwrap(), None).unwrap();
+        let unsaved = DocumentInfo::from_pathbuf_and_text(&file_path, &"fn main() {}\n".to_string()).unwrap();
+        let (declarations, _, _) = cache.get_or_parse(&unsaved, None).unwrap();
+        assert_eq!(declarations.len(), 1);
+
+        // another project's files stay out, the unsaved text did not replace the saved file
+        let loaded = AstIndexCache::new(&tmp_dir.path().join("cache")).load(&[doc.clone()]);
+        assert_eq!(loaded.len(), 1);
+        let (loaded_doc, (loaded_declarations, _, _)) = &loaded[0];
+        assert_eq!(loaded_doc.get_path(), file_path);
+        assert_eq!(loaded_declarations.len(), 2);
+    }
+}
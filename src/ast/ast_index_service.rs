@@ -1,17 +1,23 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Write;
 use std::iter::zip;
 use std::ops::Div;
 use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
 use std::time::SystemTime;
 use tokio::sync::RwLock as ARwLock;
 use tokio::sync::Mutex as AMutex;
 use tokio::task::JoinHandle;
 use tracing::info;
+use url::Url;
 use rayon::prelude::*;
-use crate::ast::ast_index::AstIndex;
-use crate::ast::treesitter::structs::{SymbolDeclarationStruct, UsageSymbolInfo};
+use crate::ast::ast_index::{AstIndex, ParsedDocument};
+use crate::ast::ast_index_cache::AstIndexCache;
+use crate::ast::comments_wrapper::get_language_id_by_filename;
+use crate::ast::structs::{AstIndexState, AstIndexStatus};
+use crate::ast::treesitter::document_trees::DocumentTrees;
 use crate::files_in_workspace::{DocumentInfo, on_workspaces_init};
+use crate::files_priority::FilesPriority;
 use crate::global_context;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -41,11 +47,16 @@ pub struct AstIndexService {
     update_request_queue: Arc<AMutex<VecDeque<AstEvent>>>,
     output_queue: Arc<AMutex<VecDeque<AstEvent>>>,
     ast_index: Arc<AMutex<AstIndex>>,
+    ast_index_cache: Arc<AstIndexCache>,
+    document_trees: Arc<StdMutex<DocumentTrees>>,
+    files_priority: Arc<StdMutex<FilesPriority>>,
+    status: Arc<AMutex<AstIndexStatus>>,
 }
 
 async fn cooldown_queue_thread(
     update_request_queue: Arc<AMutex<VecDeque<AstEvent>>>,
     out_queue: Arc<AMutex<VecDeque<AstEvent>>>,
+    status: Arc<AMutex<AstIndexStatus>>,
     cooldown_secs: u64,
 ) {
     let mut last_updated: HashMap<AstEvent, SystemTime> = HashMap::new();
@@ -82,6 +93,7 @@ async fn cooldown_queue_thread(
             last_updated.remove(&event);
             out_queue.lock().await.push_back(event);
         }
+        status.lock().await.files_in_cooldown = count_parsable_docs(last_updated.keys());
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
     }
 }
@@ -90,7 +102,15 @@ async fn cooldown_queue_thread(
 async fn ast_indexer_thread(
     queue: Arc<AMutex<VecDeque<AstEvent>>>,
     ast_index: Arc<AMutex<AstIndex>>,
+    ast_index_cache: Arc<AstIndexCache>,
+    document_trees: Arc<StdMutex<DocumentTrees>>,
+    files_priority: Arc<StdMutex<FilesPriority>>,
+    status: Arc<AMutex<AstIndexStatus>>,
 ) {
+    // files to parse, sorted by priority before each batch, a batch is small enough to re-sort soon
+    let mut pending: Vec<DocumentInfo> = vec![];
+    let mut sorted_generation: u64 = u64::MAX;
+    let mut pending_sorted = true;
     loop {
         let events = {
             let mut queue_locked = queue.lock().await;
@@ -99,48 +119,148 @@ async fn ast_indexer_thread(
             events
         };
 
-        if events.len() == 0 {
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-            continue;
-        }
-
         for event in events {
-            let list_of_path = event.docs;
             match event.typ {
                 EventType::Add => {
-                    let ast_index = ast_index.clone();
-                    let declarations_and_usages: Vec<Result<(HashMap<String, SymbolDeclarationStruct>, Vec<Box<dyn UsageSymbolInfo>>), String>>
-                        = list_of_path.par_iter().map(move |document| {
-                        AstIndex::get_declarations_and_usages(&document)
-                    }).collect();
-
-                    let mut ast_index = ast_index.lock().await;
-                    zip(list_of_path, declarations_and_usages).for_each(|(doc, res)| {
-                        match res {
-                            Ok((declaration, usages)) => {
-                                match ast_index.add_or_update_declarations_and_usages(&doc, declaration, usages) {
-                                    Ok(_) => {}
-                                    Err(e) => { info!("Error adding/updating records in AST index: {}", e); }
-                                }
+                    // the queue gets every file of the workspace, only the languages we can parse matter
+                    let docs: Vec<DocumentInfo> = event.docs.into_iter()
+                        .filter(|doc| get_language_id_by_filename(&doc.get_path()).is_some())
+                        .collect();
+                    if docs.is_empty() {
+                        continue;
+                    }
+                    // what was parsed last time is available right away, the queue then re-parses only the changed files
+                    let cache = ast_index_cache.clone();
+                    let docs_to_load = docs.clone();
+                    let loaded = tokio::task::spawn_blocking(move || cache.load(&docs_to_load)).await.unwrap_or_default();
+                    if !loaded.is_empty() {
+                        let mut ast_index_locked = ast_index.lock().await;
+                        for (doc, (declarations, usages, imports)) in loaded {
+                            if let Err(e) = ast_index_locked.add_or_update_declarations_and_usages(&doc, declarations, usages, imports) {
+                                info!("Error adding records from AST cache: {}", e);
                             }
-                            Err(e) => { info!("Error adding/updating records in AST index: {}", e); }
                         }
-                    })
+                        update_index_counts(&mut *status.lock().await, &ast_index_locked);
+                    }
+                    // a newer version of the same file replaces the queued one
+                    let uris: HashSet<Url> = docs.iter().map(|x| x.uri.clone()).collect();
+                    pending.retain(|x| !uris.contains(&x.uri));
+                    pending.extend(docs);
+                    pending_sorted = false;
                 }
                 EventType::Reset => {
+                    pending.clear();
                     ast_index.lock().await.clear_index().await;
+                    ast_index_cache.forget_all();
+                    {
+                        let ast_index_locked = ast_index.lock().await;
+                        let mut status_locked = status.lock().await;
+                        status_locked.parse_errors.clear();
+                        update_index_counts(&mut status_locked, &ast_index_locked);
+                    }
                     info!("Reset AST Index");
                 }
             }
         }
+
+        if pending.is_empty() {
+            {
+                let mut status_locked = status.lock().await;
+                status_locked.state = AstIndexState::Idle;
+                status_locked.files_queued = 0;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            continue;
+        }
+
+        {
+            let files_priority_locked = files_priority.lock().unwrap();
+            if !pending_sorted || files_priority_locked.generation() != sorted_generation {
+                files_priority_locked.sort_docs(&mut pending);
+                sorted_generation = files_priority_locked.generation();
+                pending_sorted = true;
+            }
+        }
+        let list_of_path: Vec<DocumentInfo> = pending.drain(..pending.len().min(AST_BATCH_SIZE)).collect();
+        {
+            let mut status_locked = status.lock().await;
+            status_locked.state = AstIndexState::Parsing;
+            status_locked.files_queued = pending.len();
+            status_locked.files_in_progress = list_of_path.len();
+        }
+        let cache = ast_index_cache.clone();
+        let document_trees = document_trees.clone();
+        // the parse time is None when nothing was parsed, only real parses go to the stats
+        let declarations_and_usages: Vec<(Result<Option<ParsedDocument>, String>, Option<f64>)>
+            = list_of_path.par_iter().map(move |document| {
+            if cache.is_up_to_date(&document) {
+                return (Ok(None), None);
+            }
+            match cache.get_or_parse_timed(&document, Some(&*document_trees)) {
+                Ok((parsed, parse_time_ms)) => (Ok(Some(parsed)), parse_time_ms),
+                Err(e) => (Err(e), None),
+            }
+        }).collect();
+
+        let mut ast_index_locked = ast_index.lock().await;
+        let mut status_locked = status.lock().await;
+        zip(list_of_path, declarations_and_usages).for_each(|(doc, (res, parse_time_ms))| {
+            let path = doc.get_path().to_string_lossy().to_string();
+            match res {
+                Ok(None) => {}
+                Ok(Some((declaration, usages, imports))) => {
+                    if let Some(parse_time_ms) = parse_time_ms {
+                        status_locked.files_parsed_since_start += 1;
+                        status_locked.parse_time_total_ms += parse_time_ms;
+                        if parse_time_ms > status_locked.parse_time_max_ms {
+                            status_locked.parse_time_max_ms = parse_time_ms;
+                            status_locked.parse_time_max_file = path.clone();
+                        }
+                    }
+                    match ast_index_locked.add_or_update_declarations_and_usages(&doc, declaration, usages, imports) {
+                        Ok(_) => {
+                            status_locked.parse_errors.remove(&path);
+                        }
+                        Err(e) => {
+                            info!("Error adding/updating records in AST index: {}", e);
+                            status_locked.parse_errors.insert(path, e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    info!("Error adding/updating records in AST index: {}", e);
+                    status_locked.parse_errors.insert(path, e);
+                }
+            }
+        });
+        status_locked.files_in_progress = 0;
+        update_index_counts(&mut status_locked, &ast_index_locked);
+    }
+}
+
+fn count_parsable_docs<'a>(events: impl Iterator<Item = &'a AstEvent>) -> usize {
+    events.map(|x| x.docs.iter().filter(|doc| get_language_id_by_filename(&doc.get_path()).is_some()).count()).sum()
+}
+
+fn update_index_counts(status: &mut AstIndexStatus, ast_index: &AstIndex) {
+    let (files, symbols, usages) = ast_index.get_counts();
+    status.files_indexed = files;
+    status.symbols_count = symbols;
+    status.usages_count = usages;
+    if status.files_parsed_since_start > 0 {
+        status.parse_time_avg_ms = status.parse_time_total_ms / status.files_parsed_since_start as f64;
     }
 }
 
 const COOLDOWN_SECS: u64 = 5;
+const AST_BATCH_SIZE: usize = 64;
 
 impl AstIndexService {
     pub fn init(
-        ast_index: Arc<AMutex<AstIndex>>
+        ast_index: Arc<AMutex<AstIndex>>,
+        ast_index_cache: Arc<AstIndexCache>,
+        document_trees: Arc<StdMutex<DocumentTrees>>,
+        files_priority: Arc<StdMutex<FilesPriority>>,
     ) -> Self {
         let update_request_queue = Arc::new(AMutex::new(VecDeque::new()));
         let output_queue = Arc::new(AMutex::new(VecDeque::new()));
@@ -148,6 +268,13 @@ impl AstIndexService {
             update_request_queue: update_request_queue.clone(),
             output_queue: output_queue.clone(),
             ast_index: ast_index.clone(),
+            ast_index_cache,
+            document_trees,
+            files_priority,
+            status: Arc::new(AMutex::new(AstIndexStatus {
+                state: AstIndexState::Starting,
+                ..Default::default()
+            })),
         }
     }
 
@@ -156,6 +283,7 @@ impl AstIndexService {
             cooldown_queue_thread(
                 self.update_request_queue.clone(),
                 self.output_queue.clone(),
+                self.status.clone(),
                 COOLDOWN_SECS,
             )
         );
@@ -163,6 +291,10 @@ impl AstIndexService {
             ast_indexer_thread(
                 self.output_queue.clone(),
                 self.ast_index.clone(),
+                self.ast_index_cache.clone(),
+                self.document_trees.clone(),
+                self.files_priority.clone(),
+                self.status.clone(),
             )
         );
         return vec![cooldown_queue_join_handle, indexer_handle];
@@ -176,4 +308,23 @@ impl AstIndexService {
             self.output_queue.lock().await.push_back(event);
         }
     }
+
+    pub async fn status(&self) -> AstIndexStatus {
+        let queued = count_parsable_docs(self.update_request_queue.lock().await.iter())
+            + count_parsable_docs(self.output_queue.lock().await.iter());
+        let mut status = self.status.lock().await.clone();
+        // the indexer thread keeps files_queued as what it took from the queue and didn't parse yet
+        status.files_queued += queued + status.files_in_cooldown;
+        status
+    }
+
+    pub async fn forget_files(&self, docs: &Vec<DocumentInfo>) {
+        // same order as the indexer thread: the index first, then the status
+        let ast_index_locked = self.ast_index.lock().await;
+        let mut status_locked = self.status.lock().await;
+        for doc in docs.iter() {
+            status_locked.parse_errors.remove(&doc.get_path().to_string_lossy().to_string());
+        }
+        update_index_counts(&mut status_locked, &ast_index_locked);
+    }
 }
@@ -1,5 +1,7 @@
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
 use itertools::Itertools;
 
 use serde::Serialize;
@@ -7,14 +9,18 @@ use tokio::sync::Mutex as AMutex;
 use tokio::sync::RwLock as ARwLock;
 use tokio::task::JoinHandle;
 use tracing::info;
-use tree_sitter::Point;
+use tree_sitter::{Point, Range};
 
 use crate::global_context::GlobalContext;
 use crate::ast::ast_index::AstIndex;
+use crate::ast::ast_index_cache::AstIndexCache;
 use crate::ast::ast_index_service::{AstEvent, AstIndexService};
 use crate::ast::comments_wrapper::get_language_id_by_filename;
-use crate::ast::structs::{AstCursorSearchResult, AstQuerySearchResult, CursorUsagesResult, FileReferencesResult, SymbolsSearchResultStruct, UsageSearchResultStruct};
+use crate::ast::structs::{AstCursorSearchResult, AstIndexStatus, CallGraphDirection, AstQuerySearchResult, CursorUsagesResult, FileReferencesResult, SymbolsSearchResultStruct, UsageSearchResultStruct};
+use crate::ast::treesitter::document_trees::{DocumentTrees, parse_document_tree};
+use crate::ast::treesitter::language_id::LanguageId;
 use crate::ast::treesitter::parsers::get_parser_by_filename;
+use crate::ast::treesitter::structs::SymbolDeclarationStruct;
 use crate::files_in_workspace::DocumentInfo;
 use rayon::prelude::*;
 use crate::files_in_jsonl::files_in_jsonl;
@@ -22,6 +28,8 @@ use crate::files_in_jsonl::files_in_jsonl;
 pub struct AstModule {
     ast_index_service: Arc<AMutex<AstIndexService>>,
     ast_index: Arc<AMutex<AstIndex>>,
+    ast_index_cache: Arc<AstIndexCache>,
+    document_trees: Arc<StdMutex<DocumentTrees>>,
     // cmdline -- take from command line what's needed, don't store a copy
 }
 
@@ -36,12 +44,21 @@ impl AstModule {
         global_context: Arc<ARwLock<GlobalContext>>,
     ) -> Result<AstModule, String> {
         let ast_index = Arc::new(AMutex::new(AstIndex::init()));
-        let ast_index_service = Arc::new(AMutex::new(AstIndexService::init(ast_index.clone())));
+        let (ast_index_cache, document_trees, files_priority) = {
+            let gcx_locked = global_context.read().await;
+            (Arc::new(AstIndexCache::new(&gcx_locked.cache_dir)), gcx_locked.documents_state.document_trees.clone(),
+             gcx_locked.documents_state.files_priority.clone())
+        };
+        let ast_index_service = Arc::new(AMutex::new(AstIndexService::init(
+            ast_index.clone(), ast_index_cache.clone(), document_trees.clone(), files_priority,
+        )));
 
         let documents = files_in_jsonl(global_context.clone()).await;
         let me = AstModule {
             ast_index_service,
             ast_index,
+            ast_index_cache,
+            document_trees,
         };
         me.ast_indexer_enqueue_files(&documents, true).await;
         Ok(me)
@@ -56,16 +73,50 @@ impl AstModule {
     }
 
     pub async fn ast_add_file_no_queue(&self, document: &DocumentInfo) -> Result<(), String> {
-        self.ast_index.lock().await.add_or_update(&document)
+        let (declarations, usages, imports) = self.ast_index_cache.get_or_parse(&document, Some(&*self.document_trees))?;
+        self.ast_index.lock().await.add_or_update_declarations_and_usages(&document, declarations, usages, imports)
     }
     
     pub async fn ast_reset_index(&self) {
         self.ast_index_service.lock().await.ast_indexer_enqueue_files(AstEvent::reset(), false).await;
     }
 
-    pub async fn remove_file(&self, doc: &DocumentInfo) {
+    pub async fn remove_files(&self, docs: &Vec<DocumentInfo>) {
         // TODO: will not work if the same file is in the indexer queue
-        let _ = self.ast_index.lock().await.remove(doc);
+        {
+            let mut ast_index_locked = self.ast_index.lock().await;
+            for doc in docs.iter() {
+                let _ = ast_index_locked.remove(doc);
+                self.ast_index_cache.forget(&doc.get_path());
+            }
+        }
+        self.ast_index_service.lock().await.forget_files(docs).await;
+    }
+
+    pub async fn rename_files(&self, renamed: &Vec<(DocumentInfo, DocumentInfo)>) -> Vec<DocumentInfo> {
+        // the new documents that changed along with the rename, only those need to be parsed again
+        {
+            let mut ast_index_locked = self.ast_index.lock().await;
+            for (old_doc, new_doc) in renamed.iter() {
+                match ast_index_locked.rename(old_doc, new_doc) {
+                    Ok(()) => self.ast_index_cache.rename(&old_doc.get_path(), &new_doc.get_path()),
+                    Err(e) => {
+                        info!("cannot move AST symbols to {}, will parse it again: {}", new_doc.get_path().display(), e);
+                        let _ = ast_index_locked.remove(old_doc);
+                        self.ast_index_cache.forget(&old_doc.get_path());
+                    }
+                }
+            }
+        }
+        let old_docs: Vec<DocumentInfo> = renamed.iter().map(|(old_doc, _)| old_doc.clone()).collect();
+        self.ast_index_service.lock().await.forget_files(&old_docs).await;
+
+        let new_docs: Vec<DocumentInfo> = renamed.iter().map(|(_, new_doc)| new_doc.clone()).collect();
+        let cache = self.ast_index_cache.clone();
+        let new_docs_t = new_docs.clone();
+        tokio::task::spawn_blocking(move || new_docs_t.into_iter().filter(|x| !cache.is_same_content(x)).collect())
+            .await
+            .unwrap_or(new_docs)
     }
 
     pub async fn clear_index(&self) {
@@ -97,6 +148,12 @@ impl AstModule {
             let ast_index = self.ast_index.clone();
             let ast_index_locked = ast_index.lock().await;
             usage_result.search_results.par_iter().map(|sym| {
+                if sym.type_str == "function_call_info" {
+                    let resolved = ast_index_locked.search_declarations_by_imports(&path, sym.symbol_path.as_str());
+                    if !resolved.is_empty() {
+                        return resolved;
+                    }
+                }
                 match ast_index_locked.search_declarations(
                     sym.symbol_path.as_str(),
                     1,
@@ -244,6 +301,13 @@ impl AstModule {
         }
     }
 
+    pub async fn search_usage_locations(&self, name: &str, language: Option<LanguageId>, declarations: &Vec<String>, top_n: usize) -> Vec<(PathBuf, Range)> {
+        let t0 = std::time::Instant::now();
+        let results = self.ast_index.lock().await.search_usage_locations(name, language, declarations, top_n);
+        info!("search_usage_locations {} time {:.3}s, found {} results", name, t0.elapsed().as_secs_f32(), results.len());
+        results
+    }
+
     pub async fn get_file_symbols(&self, doc: &DocumentInfo) -> Result<FileReferencesResult, String> {
         let ast_index = self.ast_index.clone();
         let ast_index_locked = ast_index.lock().await;
@@ -257,6 +321,10 @@ impl AstModule {
         })
     }
 
+    pub async fn ast_index_status(&self) -> AstIndexStatus {
+        self.ast_index_service.lock().await.status().await
+    }
+
     pub async fn get_indexed_symbol_paths(&self) -> Vec<String> {
         let ast_index = self.ast_index.clone();
         let ast_index_locked = ast_index.lock().await;
@@ -275,6 +343,39 @@ impl AstModule {
         ast_index_locked.get_indexed_file_paths()
     }
 
+    pub async fn get_top_level_symbols_count(&self) -> HashMap<PathBuf, usize> {
+        let ast_index = self.ast_index.clone();
+        let ast_index_locked = ast_index.lock().await;
+        ast_index_locked.get_top_level_symbols_count()
+    }
+
+    pub async fn get_declarations_by_symbol(&self, symbol: String) -> Vec<SymbolDeclarationStruct> {
+        let ast_index = self.ast_index.clone();
+        let ast_index_locked = ast_index.lock().await;
+        ast_index_locked.get_declarations_by_symbol(symbol.as_str())
+    }
+
+    pub async fn get_usages_enclosing_declarations(&self, meta_paths: &HashSet<String>) -> Vec<SymbolDeclarationStruct> {
+        let ast_index = self.ast_index.clone();
+        let ast_index_locked = ast_index.lock().await;
+        ast_index_locked.get_usages_enclosing_declarations(meta_paths)
+    }
+
+    pub async fn search_call_graph(
+        &self,
+        symbol: String,
+        direction: CallGraphDirection,
+        depth: usize,
+        max_results: usize,
+    ) -> Result<Vec<(SymbolDeclarationStruct, usize)>, String> {
+        let t0 = std::time::Instant::now();
+        let ast_index = self.ast_index.clone();
+        let ast_index_locked = ast_index.lock().await;
+        let results = ast_index_locked.search_call_graph(symbol.as_str(), direction, depth, max_results)?;
+        info!("search_call_graph {:?} of {} time {:.3}s, found {} results", direction, symbol, t0.elapsed().as_secs_f32(), results.len());
+        Ok(results)
+    }
+
     async fn parse_near_cursor(
         &mut self,
         doc: &DocumentInfo,
@@ -289,7 +390,9 @@ impl AstModule {
                 return Err(err.message);
             }
         };
-        let usages = match parser.parse_usages(code, false) {
+        // FIM calls this on every completion, the tree of the open document makes it cheap
+        let usages = match parse_document_tree(&self.document_trees, &doc.uri, parser.as_mut(), code)
+            .and_then(|tree| parser.parse_usages_from_tree(&tree, code, false)) {
             Ok(usages) => usages,
             Err(e) => {
                 return Err(format!("Error parsing {}: {}", path.display(), e));
@@ -8,8 +8,11 @@ pub fn get_language_id_by_filename(filename: &PathBuf) -> Option<LanguageId> {
         "cpp" | "cc" | "cxx" | "c++" | "c" | "h" | "hpp" | "hxx" | "hh" => Some(LanguageId::Cpp),
         "inl" | "inc" | "tpp" | "tpl" => Some(LanguageId::Cpp),
         "py" | "pyo" | "py3" | "pyx" => Some(LanguageId::Python),
+        "cs" => Some(LanguageId::CSharp),
+        "go" => Some(LanguageId::Go),
         "java" => Some(LanguageId::Java),
         "js" | "jsx" => Some(LanguageId::JavaScript),
+        "kt" | "kts" => Some(LanguageId::Kotlin),
         "rs" => Some(LanguageId::Rust),
         "ts" => Some(LanguageId::TypeScript),
         "tsx" => Some(LanguageId::TypeScriptReact),
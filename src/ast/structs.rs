@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
@@ -49,4 +50,35 @@ pub struct AstQuerySearchResult {
 pub struct FileReferencesResult {
     pub file_path: PathBuf,
     pub symbols: Vec<SymbolDeclarationStruct>
-}
\ No newline at end of file
+}
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum CallGraphDirection {
+    Callers,
+    Callees,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AstIndexState {
+    #[default]
+    Starting,  // while the cache loads
+    Parsing,
+    Idle,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AstIndexStatus {
+    pub state: AstIndexState,
+    pub files_queued: usize,  // including the files waiting for the cooldown
+    pub files_in_cooldown: usize,
+    pub files_in_progress: usize,
+    pub files_indexed: usize,
+    pub symbols_count: usize,
+    pub usages_count: usize,
+    pub parse_errors: HashMap<String, String>,  // file path -> the last error
+    pub files_parsed_since_start: usize,
+    pub parse_time_total_ms: f64,
+    pub parse_time_avg_ms: f64,
+    pub parse_time_max_ms: f64,
+    pub parse_time_max_file: String,
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex as StdMutex;
+use std::time::Instant;
+
+use tree_sitter::{InputEdit, Tree};
+use url::Url;
+
+use crate::ast::treesitter::parsers::LanguageParser;
+
+// open documents only, a closed document is forgotten, this is the safety net
+const DOCUMENT_TREES_MAX: usize = 64;
+
+
+#[derive(Debug)]
+struct DocumentTree {
+    tree: Tree,
+    edited: bool,  // edits came after the parse, the tree needs parse_tree() before it's any good
+    last_used: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct DocumentTrees {
+    trees: HashMap<Url, DocumentTree>,
+    text_hashes: HashMap<Url, u64>,  // of the latest text of the edited documents, a tree is kept only if it's for that text
+}
+
+fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl DocumentTrees {
+    pub fn new() -> Self {
+        DocumentTrees { trees: HashMap::new(), text_hashes: HashMap::new() }
+    }
+
+    pub fn forget(&mut self, uri: &Url) {
+        self.trees.remove(uri);
+        self.text_hashes.remove(uri);
+    }
+
+    pub fn edit(&mut self, uri: &Url, edits: &[InputEdit], new_text: &str) -> bool {
+        // the edits come from the ranged changes of the document, new_text is the text after them;
+        // returns true if there is a tree to parse again
+        self.text_hashes.insert(uri.clone(), text_hash(new_text));
+        match self.trees.get_mut(uri) {
+            Some(x) => {
+                for edit in edits.iter() {
+                    x.tree.edit(edit);
+                }
+                x.edited = x.edited || !edits.is_empty();
+                x.edited
+            }
+            None => false,
+        }
+    }
+
+    fn is_latest(&self, uri: &Url, hash: u64) -> bool {
+        // a document that was never edited has only one text
+        self.text_hashes.get(uri).map(|x| *x == hash).unwrap_or(true)
+    }
+
+    fn put(&mut self, uri: &Url, tree: &Tree) {
+        if !self.trees.contains_key(uri) && self.trees.len() >= DOCUMENT_TREES_MAX {
+            let oldest = self.trees.iter().min_by_key(|(_, x)| x.last_used).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                self.trees.remove(&oldest);
+            }
+        }
+        self.trees.insert(uri.clone(), DocumentTree {
+            tree: tree.clone(),
+            edited: false,
+            last_used: Instant::now(),
+        });
+    }
+}
+
+pub fn parse_document_tree(
+    document_trees: &StdMutex<DocumentTrees>,
+    uri: &Url,
+    parser: &mut dyn LanguageParser,
+    code: &str,
+) -> Result<Tree, String> {
+    // the lock is not held while parsing, other documents are parsed in parallel;
+    // code might be older than the edits already applied to the tree, then the tree is of no use and stays as it is
+    let hash = text_hash(code);
+    let previous = {
+        let mut document_trees_locked = document_trees.lock().unwrap();
+        if !document_trees_locked.is_latest(uri, hash) {
+            None
+        } else {
+            document_trees_locked.trees.get_mut(uri).map(|x| {
+                x.last_used = Instant::now();
+                (x.tree.clone(), x.edited)
+            })
+        }
+    };
+    // the parser comes from the file extension, the same url always gets the same language
+    let tree = match previous {
+        Some((tree, false)) => return Ok(tree),
+        Some((old_tree, true)) => parser.parse_tree(code, Some(&old_tree))?,
+        None => parser.parse_tree(code, None)?,
+    };
+    let mut document_trees_locked = document_trees.lock().unwrap();
+    if document_trees_locked.is_latest(uri, hash) {
+        document_trees_locked.put(uri, &tree);
+    }
+    Ok(tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use ropey::Rope;
+    use tower_lsp::lsp_types::{Position, Range, TextDocumentContentChangeEvent};
+
+    use super::*;
+    use crate::ast::treesitter::parsers::get_parser_by_filename;
+    use crate::files_in_workspace::Document;
+
+    fn apply(doc: &mut Document, document_trees: &StdMutex<DocumentTrees>, uri: &Url, start: (u32, u32), end: (u32, u32), text: &str) {
+        let change = TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))),
+            range_length: None,
+            text: text.to_string(),
+        };
+        let edit = doc.apply_change(&change).unwrap();
+        document_trees.lock().unwrap().edit(uri, &[edit], &doc.text.to_string());
+    }
+
+    #[test]
+    fn test_parse_document_tree_matches_full_parse() {
+        let path = PathBuf::from("/tmp/test.py");
+        let uri = Url::from_file_path(&path).unwrap();
+        let mut parser = get_parser_by_filename(&path).unwrap();
+        let document_trees = StdMutex::new(DocumentTrees::new());
+        let mut doc = Document::new("python".to_string(), Rope::from_str("def foo():\n    return 1\n"));
+        let check = |doc: &Document, parser: &mut dyn LanguageParser| {
+            let code = doc.text.to_string();
+            let tree = parse_document_tree(&document_trees, &uri, parser, &code).unwrap();
+            let full = parser.parse_tree(&code, None).unwrap();
+            assert_eq!(tree.root_node().to_sexp(), full.root_node().to_sexp());
+        };
+        check(&doc, parser.as_mut());
+        apply(&mut doc, &document_trees, &uri, (2, 0), (2, 0), "def bar():\n    foo()\n");
+        check(&doc, parser.as_mut());
+        apply(&mut doc, &document_trees, &uri, (0, 0), (0, 0), "import os\n\n");
+        check(&doc, parser.as_mut());
+        // "é" is two bytes, one UTF-16 code unit
+        apply(&mut doc, &document_trees, &uri, (3, 11), (3, 12), "\"é\"");
+        check(&doc, parser.as_mut());
+        apply(&mut doc, &document_trees, &uri, (2, 0), (4, 0), "");
+        assert_eq!(doc.text.to_string(), "import os\n\ndef bar():\n    foo()\n");
+        check(&doc, parser.as_mut());
+    }
+
+    #[test]
+    fn test_parse_document_tree_older_text() {
+        let path = PathBuf::from("/tmp/test.py");
+        let uri = Url::from_file_path(&path).unwrap();
+        let mut parser = get_parser_by_filename(&path).unwrap();
+        let document_trees = StdMutex::new(DocumentTrees::new());
+        let mut doc = Document::new("python".to_string(), Rope::from_str("def foo():\n    return 1\n"));
+        let old_code = doc.text.to_string();
+        parse_document_tree(&document_trees, &uri, parser.as_mut(), &old_code).unwrap();
+        apply(&mut doc, &document_trees, &uri, (1, 11), (1, 12), "2");
+
+        // the indexer still has the text from before the edit: parsed from scratch, the edited tree stays for the new text
+        let tree = parse_document_tree(&document_trees, &uri, parser.as_mut(), &old_code).unwrap();
+        assert_eq!(tree.root_node().to_sexp(), parser.parse_tree(&old_code, None).unwrap().root_node().to_sexp());
+        assert!(document_trees.lock().unwrap().trees.get(&uri).unwrap().edited);
+        let new_code = doc.text.to_string();
+        let tree = parse_document_tree(&document_trees, &uri, parser.as_mut(), &new_code).unwrap();
+        assert_eq!(tree.root_node().to_sexp(), parser.parse_tree(&new_code, None).unwrap().root_node().to_sexp());
+        assert!(!document_trees.lock().unwrap().trees.get(&uri).unwrap().edited);
+    }
+}
@@ -143,12 +143,18 @@ impl From<Language> for LanguageId {
     fn from(value: Language) -> Self {
         if value == tree_sitter_cpp::language() {
             Self::Cpp
+        } else if value == tree_sitter_c_sharp::language() {
+            Self::CSharp
+        } else if value == tree_sitter_go::language() {
+            Self::Go
         } else if value == tree_sitter_python::language() {
             Self::Python
         } else if value == tree_sitter_java::language() {
             Self::Java
         } else if value == tree_sitter_javascript::language() {
             Self::JavaScript
+        } else if value == tree_sitter_kotlin::language() {
+            Self::Kotlin
         } else if value == tree_sitter_rust::language() {
             Self::Rust
         } else if value == tree_sitter_typescript::language_typescript() {
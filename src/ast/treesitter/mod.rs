@@ -1,3 +1,4 @@
 pub mod language_id;
+pub mod document_trees;
 pub mod parsers;
 pub mod structs;
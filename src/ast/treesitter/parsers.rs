@@ -8,11 +8,14 @@ use crate::ast::comments_wrapper::get_language_id_by_filename;
 
 use crate::ast::treesitter::language_id::LanguageId;
 use crate::ast::treesitter::parsers::utils::{get_call, get_static, get_variable};
-use crate::ast::treesitter::structs::{FunctionCallInfo, StaticInfo, SymbolDeclarationStruct, SymbolInfo, SymbolType, UsageSymbolInfo, VariableInfo};
+use crate::ast::treesitter::structs::{FunctionCallInfo, ImportInfo, StaticInfo, SymbolDeclarationStruct, SymbolInfo, SymbolType, UsageSymbolInfo, VariableInfo};
 
 pub(crate)  mod cpp;
+pub(crate) mod csharp;
+pub(crate) mod go;
 pub(crate)  mod python;
 pub(crate)  mod java;
+pub(crate) mod kotlin;
 pub(crate) mod rust;
 pub(crate) mod js;
 pub(crate) mod ts;
@@ -54,12 +57,30 @@ pub trait LanguageParser: Send {
         get_static(captures, query, code)
     }
 
+    fn parse_tree(&mut self, code: &str, old_tree: Option<&Tree>) -> Result<Tree, String> {
+        // old_tree must be edited to match the code already, then the unchanged subtrees are reused
+        match self.get_parser().parse(code, old_tree) {
+            Some(tree) => Ok(tree),
+            None => Err("Parse error".to_string()),
+        }
+    }
+
+    fn parse_imports(&mut self, code: &str) -> Result<Vec<ImportInfo>, String> {
+        let tree = self.parse_tree(code, None)?;
+        self.parse_imports_from_tree(&tree, code)
+    }
+
+    fn parse_imports_from_tree(&mut self, _tree: &Tree, _code: &str) -> Result<Vec<ImportInfo>, String> {
+        Ok(vec![])
+    }
+
     fn parse_declarations(&mut self, code: &str, path: &PathBuf) -> Result<HashMap<String, SymbolDeclarationStruct>, String> {
+        let tree = self.parse_tree(code, None)?;
+        self.parse_declarations_from_tree(&tree, code, path)
+    }
+
+    fn parse_declarations_from_tree(&mut self, tree: &Tree, code: &str, path: &PathBuf) -> Result<HashMap<String, SymbolDeclarationStruct>, String> {
         let mut indexes: HashMap<String, SymbolDeclarationStruct> = Default::default();
-        let tree: Tree = match self.get_parser().parse(code, None) {
-            Some(tree) => tree,
-            None => return Err("Parse error".to_string()),
-        };
         let mut qcursor = tree_sitter::QueryCursor::new();
         let query = Query::new(self.get_parser().language().unwrap(), self.get_parser_query()).unwrap();
         let matches = qcursor.matches(&query, tree.root_node(), code.as_bytes());
@@ -83,7 +104,7 @@ pub trait LanguageParser: Send {
                                            symbol_type: SymbolType::Class,
                                            meta_path: key,
                                            language: LanguageId::from(capture.node.language()),
-                                           extra_declarations: self.get_extra_declarations_for_struct(class_name, &tree, code, &path),
+                                           extra_declarations: self.get_extra_declarations_for_struct(class_name, tree, code, &path),
                                        });
                     }
                     "enum" => {
@@ -109,7 +130,7 @@ pub trait LanguageParser: Send {
                                            });
                         });
                     }
-                    "function" => {
+                    "function" | "method" => {
                         let range = capture.node.range();
                         let mut namespaces = self.get_namespace(Some(capture.node), code);
                         let (name, scopes) = self.get_function_name_and_scope(capture.node.clone(), code);
@@ -119,12 +140,17 @@ pub trait LanguageParser: Send {
                         namespaces.iter().for_each(|ns| {
                             key += format!("::{}", ns).as_str();
                         });
+                        let symbol_type = if capture_name == "method" { SymbolType::Method } else { SymbolType::Function };
+                        // methods match the function query too, the more specific capture wins
+                        if symbol_type == SymbolType::Function && indexes.get(&key).map(|x| x.symbol_type == SymbolType::Method).unwrap_or(false) {
+                            continue;
+                        }
                         indexes.insert(key.clone(),
                                        SymbolDeclarationStruct {
                                            name,
                                            definition_info: SymbolInfo { path: path.clone(), range },
                                            children: vec![],
-                                           symbol_type: SymbolType::Function,
+                                           symbol_type,
                                            meta_path: key,
                                            language: LanguageId::from(capture.node.language()),
                                            extra_declarations: vec![],
@@ -158,10 +184,11 @@ pub trait LanguageParser: Send {
     }
 
     fn parse_usages(&mut self, code: &str, include_static_data: bool) -> Result<Vec<Box<dyn UsageSymbolInfo>>, String> {
-        let tree: Tree = match self.get_parser().parse(code, None) {
-            Some(tree) => tree,
-            None => return Err("Parse error".to_string()),
-        };
+        let tree = self.parse_tree(code, None)?;
+        self.parse_usages_from_tree(&tree, code, include_static_data)
+    }
+
+    fn parse_usages_from_tree(&mut self, tree: &Tree, code: &str, include_static_data: bool) -> Result<Vec<Box<dyn UsageSymbolInfo>>, String> {
         let mut usages: Vec<Box<dyn UsageSymbolInfo>> = vec![];
         let mut qcursor = tree_sitter::QueryCursor::new();
         let query = Query::new(self.get_parser().language().unwrap(), self.get_parser_query_find_all()).unwrap();
@@ -206,6 +233,14 @@ fn get_parser(language_id: LanguageId) -> Result<Box<dyn LanguageParser + 'stati
             let parser = cpp::CppParser::new()?;
             Ok(Box::new(parser))
         }
+        LanguageId::CSharp => {
+            let parser = csharp::CSharpParser::new()?;
+            Ok(Box::new(parser))
+        }
+        LanguageId::Go => {
+            let parser = go::GoParser::new()?;
+            Ok(Box::new(parser))
+        }
         LanguageId::Python => {
             let parser = python::PythonParser::new()?;
             Ok(Box::new(parser))
@@ -214,6 +249,10 @@ fn get_parser(language_id: LanguageId) -> Result<Box<dyn LanguageParser + 'stati
             let parser = java::JavaParser::new()?;
             Ok(Box::new(parser))
         }
+        LanguageId::Kotlin => {
+            let parser = kotlin::KotlinParser::new()?;
+            Ok(Box::new(parser))
+        }
         LanguageId::JavaScript => {
             let parser = js::JavascriptParser::new()?;
             Ok(Box::new(parser))
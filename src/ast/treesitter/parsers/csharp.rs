@@ -0,0 +1,194 @@
+use std::string::ToString;
+
+use similar::DiffableStr;
+use structopt::lazy_static::lazy_static;
+use tree_sitter::{Node, Parser, Query, QueryCapture};
+use tree_sitter_c_sharp::language;
+
+use crate::ast::treesitter::parsers::{internal_error, LanguageParser, ParserError};
+use crate::ast::treesitter::parsers::utils::{find_statics_query, get_function_name, get_variable};
+use crate::ast::treesitter::structs::VariableInfo;
+
+const CSHARP_PARSER_QUERY_GLOBAL_VARIABLE: &str = "((property_declaration name: (identifier)) @global_variable)\n\
+    ((field_declaration (variable_declaration (variable_declarator (identifier)))) @global_variable)";
+const CSHARP_PARSER_QUERY_FUNCTION: &str = "((method_declaration name: (identifier)) @function)\n\
+    ((constructor_declaration name: (identifier)) @function)";
+const CSHARP_PARSER_QUERY_CLASS: &str = "((class_declaration name: (identifier)) @class)\n\
+    ((struct_declaration name: (identifier)) @struct)\n\
+    ((interface_declaration name: (identifier)) @trait)";
+const CSHARP_PARSER_QUERY_ENUM: &str = "((enum_declaration name: (identifier)) @enum)";
+
+const CSHARP_PARSER_QUERY_FIND_VARIABLES: &str = r#"((local_declaration_statement
+    (variable_declaration type: (_) @variable_type (variable_declarator (identifier) @variable_name))) @variable)"#;
+
+const CSHARP_PARSER_QUERY_FIND_CALLS: &str = r#"
+    ((invocation_expression function: [
+    (identifier) @call_name
+    (member_access_expression name: (identifier) @call_name)
+    ]) @call)"#;
+
+// records came with C# 9, the grammar might not know them
+const CSHARP_RECORD_KINDS: [&str; 2] = ["record_declaration", "record_struct_declaration"];
+const CSHARP_CLASS_KINDS: [&str; 5] = ["class_declaration", "struct_declaration", "interface_declaration", "record_declaration", "record_struct_declaration"];
+
+lazy_static! {
+    static ref CSHARP_PARSER_QUERY: String = {
+        let mut m: Vec<String> = Vec::new();
+        m.push(CSHARP_PARSER_QUERY_GLOBAL_VARIABLE.to_string());
+        m.push(CSHARP_PARSER_QUERY_FUNCTION.to_string());
+        m.push(CSHARP_PARSER_QUERY_CLASS.to_string());
+        m.push(CSHARP_PARSER_QUERY_ENUM.to_string());
+        for kind in CSHARP_RECORD_KINDS.iter().filter(|x| language().id_for_node_kind(x, true) != 0) {
+            m.push(format!("(({} name: (identifier)) @class)", kind));
+        }
+        m.join("\n")
+    };
+
+    static ref CSHARP_PARSER_QUERY_FIND_ALL: String = format!("{}\n{}\n{}",
+        CSHARP_PARSER_QUERY_FIND_VARIABLES, CSHARP_PARSER_QUERY_FIND_CALLS,
+        find_statics_query(language(), &["comment"],
+                           &["string_literal", "verbatim_string_literal", "raw_string_literal", "interpolated_string_expression"]));
+
+    static ref NAME_ID: u16 = language().field_id_for_name("name").unwrap();
+}
+
+pub(crate) struct CSharpParser {
+    pub parser: Parser,
+}
+
+impl CSharpParser {
+    pub fn new() -> Result<CSharpParser, ParserError> {
+        let mut parser = Parser::new();
+        parser
+            .set_language(language())
+            .map_err(internal_error)?;
+        Ok(CSharpParser { parser })
+    }
+}
+
+fn push_qualified_name(namespaces: &mut Vec<String>, name: &str) {
+    // Company.Product.Module, pushed in reverse because get_namespace() reverses everything at the end
+    name.split('.').rev().map(|x| x.trim()).filter(|x| !x.is_empty()).for_each(|x| namespaces.push(x.to_string()));
+}
+
+fn find_child_by_kind<'a>(parent: Node<'a>, kind: &str) -> Option<Node<'a>> {
+    for i in 0..parent.child_count() {
+        if let Some(child) = parent.child(i) {
+            if child.kind() == kind {
+                return Some(child);
+            }
+        }
+    }
+    None
+}
+
+fn get_extension_receiver_type_name(method: Node, text: &str) -> Option<String> {
+    // static string Shout(this string s): the first parameter marked with this is the extended type
+    let parameters = method.child_by_field_name("parameters")?;
+    let first = (0..parameters.named_child_count())
+        .filter_map(|i| parameters.named_child(i))
+        .find(|x| x.kind() == "parameter")?;
+    let is_extension = (0..first.child_count())
+        .filter_map(|i| first.child(i))
+        .any(|x| text.slice(x.byte_range()) == "this");
+    if !is_extension {
+        return None;
+    }
+    // List<int> => List, System.String => String, Point? => Point
+    let type_text = text.slice(first.child_by_field_name("type")?.byte_range());
+    let name = type_text.split('<').next().unwrap_or_default()
+        .trim_end_matches('?')
+        .rsplit('.').next().unwrap_or_default()
+        .trim();
+    if name.is_empty() { None } else { Some(name.to_string()) }
+}
+
+impl LanguageParser for CSharpParser {
+    fn get_parser(&mut self) -> &mut Parser {
+        &mut self.parser
+    }
+
+    fn get_parser_query(&self) -> &String {
+        &CSHARP_PARSER_QUERY
+    }
+
+    fn get_parser_query_find_all(&self) -> &String {
+        &CSHARP_PARSER_QUERY_FIND_ALL
+    }
+
+    fn get_namespace(&self, mut parent: Option<Node>, text: &str) -> Vec<String> {
+        let mut namespaces: Vec<String> = vec![];
+        let mut inside_namespace_block = false;
+        while parent.is_some() {
+            let node = parent.unwrap();
+            match node.kind() {
+                kind if CSHARP_CLASS_KINDS.contains(&kind) => {
+                    if let Some(child) = node.child_by_field_id(*NAME_ID) {
+                        namespaces.push(text.slice(child.byte_range()).to_string());
+                    }
+                }
+                "namespace_declaration" => {
+                    if let Some(child) = node.child_by_field_id(*NAME_ID) {
+                        push_qualified_name(&mut namespaces, text.slice(child.byte_range()));
+                    }
+                    inside_namespace_block = true;
+                }
+                "compilation_unit" if !inside_namespace_block => {
+                    // namespace X; applies to the whole file, the declarations are not inside of it
+                    if let Some(file_scoped) = find_child_by_kind(node, "file_scoped_namespace_declaration") {
+                        if let Some(child) = file_scoped.child_by_field_id(*NAME_ID) {
+                            push_qualified_name(&mut namespaces, text.slice(child.byte_range()));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            parent = node.parent();
+        }
+        namespaces.reverse();
+        namespaces
+    }
+
+    fn get_enum_name_and_all_values(&self, parent: Node, text: &str) -> (String, Vec<String>) {
+        let name = parent.child_by_field_id(*NAME_ID)
+            .map(|x| text.slice(x.byte_range()).to_string())
+            .unwrap_or_default();
+        let mut values: Vec<String> = vec![];
+        if let Some(body) = find_child_by_kind(parent, "enum_member_declaration_list") {
+            for i in 0..body.child_count() {
+                if let Some(member) = body.child(i) {
+                    if member.kind() != "enum_member_declaration" {
+                        continue;
+                    }
+                    if let Some(value) = find_child_by_kind(member, "identifier") {
+                        values.push(text.slice(value.byte_range()).to_string());
+                    }
+                }
+            }
+        }
+        (name, values)
+    }
+
+    fn get_function_name_and_scope(&self, parent: Node, text: &str) -> (String, Vec<String>) {
+        (get_function_name(parent, text), get_extension_receiver_type_name(parent, text).into_iter().collect())
+    }
+
+    fn get_variable_name(&self, parent: Node, text: &str) -> String {
+        // property_declaration has a name, field_declaration has declarators: int a = 1, b;
+        if let Some(child) = parent.child_by_field_id(*NAME_ID) {
+            return text.slice(child.byte_range()).to_string();
+        }
+        find_child_by_kind(parent, "variable_declaration")
+            .and_then(|x| find_child_by_kind(x, "variable_declarator"))
+            .and_then(|x| find_child_by_kind(x, "identifier"))
+            .map(|x| text.slice(x.byte_range()).to_string())
+            .unwrap_or_default()
+    }
+
+    fn get_variable(&mut self, captures: &[QueryCapture], query: &Query, code: &str) -> Option<VariableInfo> {
+        let mut var = get_variable(captures, query, code)?;
+        // var x = ... says nothing about the type
+        var.type_names.retain(|x| x != "var");
+        Some(var)
+    }
+}
@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+use std::string::ToString;
+
+use similar::DiffableStr;
+use structopt::lazy_static::lazy_static;
+use tree_sitter::{Node, Parser, Tree};
+use tree_sitter_go::language;
+
+use crate::ast::treesitter::parsers::{internal_error, LanguageParser, ParserError};
+use crate::ast::treesitter::parsers::utils::get_function_name;
+use crate::ast::treesitter::structs::SymbolInfo;
+
+const GO_PARSER_QUERY_GLOBAL_VARIABLE: &str = "(source_file (var_declaration (var_spec name: (identifier)) @global_variable))\n\
+    (source_file (const_declaration (const_spec name: (identifier)) @global_variable))";
+const GO_PARSER_QUERY_FUNCTION: &str = "((function_declaration name: (identifier)) @function)\n\
+    ((method_declaration name: (field_identifier)) @function)";
+const GO_PARSER_QUERY_CLASS: &str = "((type_spec name: (type_identifier) type: (struct_type)) @struct)\n\
+    ((type_spec name: (type_identifier) type: (interface_type)) @trait)";
+
+const GO_PARSER_QUERY_FIND_VARIABLES: &str = r#"[
+    ((short_var_declaration left: (expression_list (identifier) @variable_name)) @variable)
+    (block (var_declaration (var_spec name: (identifier) @variable_name)) @variable)
+    ]"#;
+
+const GO_PARSER_QUERY_FIND_CALLS: &str = r#"
+    ((call_expression function: [
+    (identifier) @call_name
+    (selector_expression field: (field_identifier) @call_name)
+    ]) @call)"#;
+
+const GO_PARSER_QUERY_FIND_STATICS: &str = r#"(
+([
+(comment) @comment
+(interpreted_string_literal) @string_literal
+(raw_string_literal) @string_literal
+])
+)"#;
+
+lazy_static! {
+    static ref GO_PARSER_QUERY: String = {
+        let mut m = Vec::new();
+        m.push(GO_PARSER_QUERY_GLOBAL_VARIABLE);
+        m.push(GO_PARSER_QUERY_FUNCTION);
+        m.push(GO_PARSER_QUERY_CLASS);
+        m.join("\n")
+    };
+
+    static ref GO_PARSER_QUERY_FIND_ALL: String = format!("{}\n{}\n{}",
+        GO_PARSER_QUERY_FIND_VARIABLES, GO_PARSER_QUERY_FIND_CALLS, GO_PARSER_QUERY_FIND_STATICS);
+
+    static ref NAME_ID: u16 = language().field_id_for_name("name").unwrap();
+    static ref TYPE_ID: u16 = language().field_id_for_name("type").unwrap();
+    static ref RECEIVER_ID: u16 = language().field_id_for_name("receiver").unwrap();
+}
+
+pub(crate) struct GoParser {
+    pub parser: Parser,
+}
+
+impl GoParser {
+    pub fn new() -> Result<GoParser, ParserError> {
+        let mut parser = Parser::new();
+        parser
+            .set_language(language())
+            .map_err(internal_error)?;
+        Ok(GoParser { parser })
+    }
+}
+
+fn get_package_name(source_file: Node, text: &str) -> Option<String> {
+    for i in 0..source_file.child_count() {
+        if let Some(child) = source_file.child(i) {
+            if child.kind() == "package_clause" {
+                return child.named_child(0).map(|x| text.slice(x.byte_range()).to_string());
+            }
+        }
+    }
+    None
+}
+
+fn get_receiver_type_name(method: Node, text: &str) -> Option<String> {
+    // func (p Point) ..., func (p *Point) ..., func (l List[T]) ...
+    let receiver = method.child_by_field_id(*RECEIVER_ID)?;
+    for i in 0..receiver.named_child_count() {
+        if let Some(param) = receiver.named_child(i) {
+            if param.kind() != "parameter_declaration" {
+                continue;
+            }
+            let mut type_node = param.child_by_field_id(*TYPE_ID)?;
+            if type_node.kind() == "pointer_type" {
+                type_node = type_node.named_child(0)?;
+            }
+            if type_node.kind() == "generic_type" {
+                type_node = type_node.child_by_field_id(*TYPE_ID)?;
+            }
+            if type_node.kind() == "type_identifier" {
+                return Some(text.slice(type_node.byte_range()).to_string());
+            }
+            return None;
+        }
+    }
+    None
+}
+
+impl LanguageParser for GoParser {
+    fn get_parser(&mut self) -> &mut Parser {
+        &mut self.parser
+    }
+
+    fn get_parser_query(&self) -> &String {
+        &GO_PARSER_QUERY
+    }
+
+    fn get_parser_query_find_all(&self) -> &String {
+        &GO_PARSER_QUERY_FIND_ALL
+    }
+
+    fn get_namespace(&self, mut parent: Option<Node>, text: &str) -> Vec<String> {
+        // the package, then the type for type declarations; methods get their receiver as a scope
+        let mut namespaces: Vec<String> = vec![];
+        while parent.is_some() {
+            match parent.unwrap().kind() {
+                "type_spec" => {
+                    if let Some(child) = parent.unwrap().child_by_field_id(*NAME_ID) {
+                        namespaces.push(text.slice(child.byte_range()).to_string());
+                    }
+                }
+                "source_file" => {
+                    if let Some(package) = get_package_name(parent.unwrap(), text) {
+                        namespaces.push(package);
+                    }
+                }
+                _ => {}
+            }
+            parent = parent.unwrap().parent();
+        }
+        namespaces.reverse();
+        namespaces
+    }
+
+    fn get_extra_declarations_for_struct(&mut self, struct_name: String, tree: &Tree, code: &str, path: &PathBuf) -> Vec<SymbolInfo> {
+        // methods are declared outside of the struct, like impl blocks in rust
+        let mut res: Vec<SymbolInfo> = vec![];
+        let root = tree.root_node();
+        for i in 0..root.child_count() {
+            if let Some(child) = root.child(i) {
+                if child.kind() == "method_declaration" && get_receiver_type_name(child, code).as_ref() == Some(&struct_name) {
+                    res.push(SymbolInfo {
+                        path: path.clone(),
+                        range: child.range(),
+                    });
+                }
+            }
+        }
+        res
+    }
+
+    fn get_function_name_and_scope(&self, parent: Node, text: &str) -> (String, Vec<String>) {
+        let scopes = if parent.kind() == "method_declaration" {
+            get_receiver_type_name(parent, text).into_iter().collect()
+        } else {
+            vec![]
+        };
+        (get_function_name(parent, text), scopes)
+    }
+
+    fn get_variable_name(&self, parent: Node, text: &str) -> String {
+        for i in 0..parent.child_count() {
+            if let Some(child) = parent.child(i) {
+                if child.kind() == "identifier" {
+                    return text.slice(child.byte_range()).to_string();
+                }
+            }
+        }
+        "".to_string()
+    }
+}
@@ -0,0 +1,199 @@
+use std::path::PathBuf;
+use std::string::ToString;
+
+use similar::DiffableStr;
+use structopt::lazy_static::lazy_static;
+use tree_sitter::{Node, Parser, Tree};
+use tree_sitter_kotlin::language;
+
+use crate::ast::treesitter::parsers::{internal_error, LanguageParser, ParserError};
+use crate::ast::treesitter::parsers::utils::find_statics_query;
+use crate::ast::treesitter::structs::SymbolInfo;
+
+// the kotlin grammar has no field names, everything below looks at the node kinds
+const KOTLIN_PARSER_QUERY_GLOBAL_VARIABLE: &str = "(source_file (property_declaration) @global_variable)\n\
+    (class_body (property_declaration) @global_variable)";
+const KOTLIN_PARSER_QUERY_FUNCTION: &str = "((function_declaration (simple_identifier)) @function)";
+const KOTLIN_PARSER_QUERY_CLASS: &str = "((class_declaration (type_identifier)) @class)\n\
+    ((object_declaration (type_identifier)) @class)";
+const KOTLIN_PARSER_QUERY_ENUM: &str = "((class_declaration (enum_class_body) @enum))";
+
+const KOTLIN_PARSER_QUERY_FIND_VARIABLES: &str = r#"(statements
+    (property_declaration (variable_declaration (simple_identifier) @variable_name)) @variable)"#;
+
+const KOTLIN_PARSER_QUERY_FIND_CALLS: &str = r#"
+    ((call_expression [
+    (simple_identifier) @call_name
+    (navigation_expression (navigation_suffix (simple_identifier) @call_name))
+    ]) @call)"#;
+
+lazy_static! {
+    static ref KOTLIN_PARSER_QUERY: String = {
+        let mut m = Vec::new();
+        m.push(KOTLIN_PARSER_QUERY_GLOBAL_VARIABLE);
+        m.push(KOTLIN_PARSER_QUERY_FUNCTION);
+        m.push(KOTLIN_PARSER_QUERY_CLASS);
+        m.push(KOTLIN_PARSER_QUERY_ENUM);
+        m.join("\n")
+    };
+
+    static ref KOTLIN_PARSER_QUERY_FIND_ALL: String = format!("{}\n{}\n{}",
+        KOTLIN_PARSER_QUERY_FIND_VARIABLES, KOTLIN_PARSER_QUERY_FIND_CALLS,
+        find_statics_query(language(), &["comment", "line_comment", "multiline_comment"], &["string_literal"]));
+}
+
+pub(crate) struct KotlinParser {
+    pub parser: Parser,
+}
+
+impl KotlinParser {
+    pub fn new() -> Result<KotlinParser, ParserError> {
+        let mut parser = Parser::new();
+        parser
+            .set_language(language())
+            .map_err(internal_error)?;
+        Ok(KotlinParser { parser })
+    }
+}
+
+fn find_child_by_kind<'a>(parent: Node<'a>, kind: &str) -> Option<Node<'a>> {
+    for i in 0..parent.child_count() {
+        if let Some(child) = parent.child(i) {
+            if child.kind() == kind {
+                return Some(child);
+            }
+        }
+    }
+    None
+}
+
+fn get_type_name(mut type_node: Node, text: &str) -> Option<String> {
+    // Point, Point?, com.example.Point, List<Int> => the last simple name
+    if type_node.kind() == "nullable_type" {
+        type_node = type_node.named_child(0)?;
+    }
+    if type_node.kind() != "user_type" {
+        return None;
+    }
+    let mut name = None;
+    for i in 0..type_node.child_count() {
+        if let Some(child) = type_node.child(i) {
+            if child.kind() == "type_identifier" {
+                name = Some(text.slice(child.byte_range()).to_string());
+            }
+        }
+    }
+    name
+}
+
+fn get_receiver_type_name(function: Node, text: &str) -> Option<String> {
+    // fun Point.distanceTo(...): the receiver goes before the name, the return type after it
+    for i in 0..function.child_count() {
+        if let Some(child) = function.child(i) {
+            match child.kind() {
+                "simple_identifier" => return None,
+                "user_type" | "nullable_type" => return get_type_name(child, text),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+impl LanguageParser for KotlinParser {
+    fn get_parser(&mut self) -> &mut Parser {
+        &mut self.parser
+    }
+
+    fn get_parser_query(&self) -> &String {
+        &KOTLIN_PARSER_QUERY
+    }
+
+    fn get_parser_query_find_all(&self) -> &String {
+        &KOTLIN_PARSER_QUERY_FIND_ALL
+    }
+
+    fn get_namespace(&self, mut parent: Option<Node>, text: &str) -> Vec<String> {
+        let mut namespaces: Vec<String> = vec![];
+        if let Some(node) = parent {
+            if node.kind() == "enum_class_body" {
+                // enum values get the enum name from get_enum_name_and_all_values()
+                parent = node.parent().and_then(|x| x.parent());
+            }
+        }
+        while parent.is_some() {
+            let node = parent.unwrap();
+            match node.kind() {
+                "class_declaration" | "object_declaration" => {
+                    if let Some(child) = find_child_by_kind(node, "type_identifier") {
+                        namespaces.push(text.slice(child.byte_range()).to_string());
+                    }
+                }
+                "source_file" => {
+                    if let Some(package) = find_child_by_kind(node, "package_header").and_then(|x| find_child_by_kind(x, "identifier")) {
+                        let parts: Vec<String> = (0..package.named_child_count())
+                            .filter_map(|i| package.named_child(i))
+                            .map(|x| text.slice(x.byte_range()).to_string())
+                            .collect();
+                        namespaces.extend(parts.into_iter().rev());
+                    }
+                }
+                _ => {}
+            }
+            parent = node.parent();
+        }
+        namespaces.reverse();
+        namespaces
+    }
+
+    fn get_enum_name_and_all_values(&self, parent: Node, text: &str) -> (String, Vec<String>) {
+        // parent is enum_class_body
+        let name = parent.parent()
+            .and_then(|x| find_child_by_kind(x, "type_identifier"))
+            .map(|x| text.slice(x.byte_range()).to_string())
+            .unwrap_or_default();
+        let mut values: Vec<String> = vec![];
+        for i in 0..parent.child_count() {
+            if let Some(entry) = parent.child(i) {
+                if entry.kind() != "enum_entry" {
+                    continue;
+                }
+                if let Some(value) = find_child_by_kind(entry, "simple_identifier") {
+                    values.push(text.slice(value.byte_range()).to_string());
+                }
+            }
+        }
+        (name, values)
+    }
+
+    fn get_extra_declarations_for_struct(&mut self, struct_name: String, tree: &Tree, code: &str, path: &PathBuf) -> Vec<SymbolInfo> {
+        // top level extension functions of the class
+        let mut res: Vec<SymbolInfo> = vec![];
+        let root = tree.root_node();
+        for i in 0..root.child_count() {
+            if let Some(child) = root.child(i) {
+                if child.kind() == "function_declaration" && get_receiver_type_name(child, code).as_ref() == Some(&struct_name) {
+                    res.push(SymbolInfo {
+                        path: path.clone(),
+                        range: child.range(),
+                    });
+                }
+            }
+        }
+        res
+    }
+
+    fn get_function_name_and_scope(&self, parent: Node, text: &str) -> (String, Vec<String>) {
+        let name = find_child_by_kind(parent, "simple_identifier")
+            .map(|x| text.slice(x.byte_range()).to_string())
+            .unwrap_or_default();
+        (name, get_receiver_type_name(parent, text).into_iter().collect())
+    }
+
+    fn get_variable_name(&self, parent: Node, text: &str) -> String {
+        find_child_by_kind(parent, "variable_declaration")
+            .and_then(|x| find_child_by_kind(x, "simple_identifier"))
+            .map(|x| text.slice(x.byte_range()).to_string())
+            .unwrap_or_default()
+    }
+}
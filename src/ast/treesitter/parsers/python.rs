@@ -2,20 +2,28 @@ use std::string::ToString;
 
 use similar::DiffableStr;
 use structopt::lazy_static::lazy_static;
-use tree_sitter::{Node, Parser, Query, QueryCapture, Range};
+use tree_sitter::{Node, Parser, Query, QueryCapture, Range, Tree};
 use tree_sitter_python::language;
 
 use crate::ast::treesitter::parsers::{internal_error, LanguageParser, ParserError};
-use crate::ast::treesitter::parsers::utils::get_function_name;
-use crate::ast::treesitter::structs::VariableInfo;
+use crate::ast::treesitter::parsers::utils::{get_call, get_function_name};
+use crate::ast::treesitter::structs::{FunctionCallInfo, ImportInfo, VariableInfo};
 
 const PYTHON_PARSER_QUERY_GLOBAL_VARIABLE: &str = "(expression_statement (assignment left: (identifier)) @global_variable)";
 const PYTHON_PARSER_QUERY_FUNCTION: &str = "((function_definition name: (identifier)) @function)";
 const PYTHON_PARSER_QUERY_CLASS: &str = "((class_definition name: (identifier)) @class)";
-const PYTHON_PARSER_QUERY_CALL_FUNCTION: &str = "";
-const PYTHON_PARSER_QUERY_IMPORT_STATEMENT: &str = "";
-const PYTHON_PARSER_QUERY_IMPORT_FROM_STATEMENT: &str = "";
-const PYTHON_PARSER_QUERY_CLASS_METHOD: &str = "";
+const PYTHON_PARSER_QUERY_IMPORT_STATEMENT: &str = r#"(import_statement name: [
+(dotted_name) @import_module
+(aliased_import name: (dotted_name) @import_module alias: (identifier) @import_alias)
+]) @import"#;
+const PYTHON_PARSER_QUERY_IMPORT_FROM_STATEMENT: &str = r#"(import_from_statement module_name: (_) @import_module name: [
+(dotted_name) @import_name
+(aliased_import name: (dotted_name) @import_name alias: (identifier) @import_alias)
+]) @import"#;
+const PYTHON_PARSER_QUERY_CLASS_METHOD: &str = r#"(class_definition body: (block [
+(function_definition name: (identifier)) @method
+(decorated_definition definition: (function_definition name: (identifier)) @method)
+]))"#;
 
 const PYTHON_PARSER_QUERY_FIND_VARIABLES: &str = r#"(expression_statement 
 (assignment left: (identifier) @variable_left type: (_)? @variable_type right: (_) @variable_right) @variable)"#;
@@ -23,7 +31,7 @@ const PYTHON_PARSER_QUERY_FIND_VARIABLES: &str = r#"(expression_statement
 const PYTHON_PARSER_QUERY_FIND_CALLS: &str = r#"
 ((call function: [
 (identifier) @call_name
-(attribute attribute: (identifier) @call_name)
+(attribute object: (_) @call_object attribute: (identifier) @call_name)
 ]) @call)"#;
 
 const PYTHON_PARSER_QUERY_FIND_STATICS: &str = r#"(
@@ -39,12 +47,12 @@ lazy_static! {
         m.push(PYTHON_PARSER_QUERY_GLOBAL_VARIABLE);
         m.push(PYTHON_PARSER_QUERY_FUNCTION);
         m.push(PYTHON_PARSER_QUERY_CLASS);
-        m.push(PYTHON_PARSER_QUERY_CALL_FUNCTION);
-        m.push(PYTHON_PARSER_QUERY_IMPORT_STATEMENT);
-        m.push(PYTHON_PARSER_QUERY_IMPORT_FROM_STATEMENT);
         m.push(PYTHON_PARSER_QUERY_CLASS_METHOD);
         m.join("\n")
     };
+
+    static ref PYTHON_PARSER_QUERY_IMPORTS: String = format!("{}\n{}",
+        PYTHON_PARSER_QUERY_IMPORT_STATEMENT, PYTHON_PARSER_QUERY_IMPORT_FROM_STATEMENT);
     
     static ref PYTHON_PARSER_QUERY_FIND_ALL: String = format!("{}\n{}\n{}", 
         PYTHON_PARSER_QUERY_FIND_VARIABLES, PYTHON_PARSER_QUERY_FIND_CALLS, PYTHON_PARSER_QUERY_FIND_STATICS);
@@ -100,6 +108,47 @@ impl LanguageParser for PythonParser {
         namespaces
     }
 
+    fn get_call(&self, captures: &[QueryCapture], query: &Query, code: &str) -> Option<FunctionCallInfo> {
+        // self.method(), np.array(), the object is resolved later using the class or the imports
+        let mut call = get_call(captures, query, code)?;
+        for capture in captures {
+            if query.capture_names()[capture.index as usize] == "call_object" {
+                call.caller_type_name = Some(code.slice(capture.node.byte_range()).to_string());
+            }
+        }
+        Some(call)
+    }
+
+    fn parse_imports_from_tree(&mut self, tree: &Tree, code: &str) -> Result<Vec<ImportInfo>, String> {
+        let mut imports: Vec<ImportInfo> = vec![];
+        let mut qcursor = tree_sitter::QueryCursor::new();
+        let query = Query::new(language(), &PYTHON_PARSER_QUERY_IMPORTS).unwrap();
+        let matches = qcursor.matches(&query, tree.root_node(), code.as_bytes());
+        for match_ in matches {
+            let mut module = "".to_string();
+            let mut name: Option<String> = None;
+            let mut alias: Option<String> = None;
+            let mut range = tree.root_node().range();
+            for capture in match_.captures {
+                let text = code.slice(capture.node.byte_range()).to_string();
+                match query.capture_names()[capture.index as usize].as_str() {
+                    "import" => range = capture.node.range(),
+                    "import_module" => module = text,
+                    "import_name" => name = Some(text),
+                    "import_alias" => alias = Some(text),
+                    &_ => {}
+                }
+            }
+            if module.is_empty() {
+                continue;
+            }
+            // import os.path binds os, from x import y binds y
+            let alias = alias.unwrap_or(name.clone().unwrap_or(module.split('.').next().unwrap_or_default().to_string()));
+            imports.push(ImportInfo { alias, module, name, range });
+        }
+        Ok(imports)
+    }
+
     fn get_function_name_and_scope(&self, parent: Node, text: &str) -> (String, Vec<String>) {
         (get_function_name(parent, text), vec![])
     }
@@ -195,6 +244,7 @@ mod tests {
 
     use crate::ast::treesitter::parsers::LanguageParser;
     use crate::ast::treesitter::parsers::python::PythonParser;
+    use crate::ast::treesitter::structs::SymbolType;
 
     const TEST_CODE: &str =
         r#"import numpy as np
@@ -244,4 +294,36 @@ def foo():
         // assert_eq!(indexes.len(), 1);
         // assert_eq!(indexes.get("function").unwrap().name, "foo");
     }
+
+    #[test]
+    fn test_python_imports_and_methods() {
+        let code = "import numpy as np\nimport os.path\nfrom pkg.shapes import Circle, Square as Sq\nfrom . import utils\n\n\
+            class A:\n    def f(self):\n        return self.g()\n\n    @staticmethod\n    def g():\n        return np.zeros(3)\n\n\
+            def h():\n    return A()\n";
+        let mut parser = PythonParser::new().expect("PythonParser::new");
+        let imports: Vec<(String, String, Option<String>)> = parser.parse_imports(code).unwrap().into_iter()
+            .map(|x| (x.alias, x.module, x.name))
+            .collect();
+        assert_eq!(imports, vec![
+            ("np".to_string(), "numpy".to_string(), None),
+            ("os".to_string(), "os.path".to_string(), None),
+            ("Circle".to_string(), "pkg.shapes".to_string(), Some("Circle".to_string())),
+            ("Sq".to_string(), "pkg.shapes".to_string(), Some("Square".to_string())),
+            ("utils".to_string(), ".".to_string(), Some("utils".to_string())),
+        ]);
+
+        let path = PathBuf::from("a.py");
+        let indexes = parser.parse_declarations(code, &path).unwrap();
+        assert_eq!(indexes.get("a.py::A::f").unwrap().symbol_type, SymbolType::Method);
+        assert_eq!(indexes.get("a.py::A::g").unwrap().symbol_type, SymbolType::Method);
+        assert_eq!(indexes.get("a.py::h").unwrap().symbol_type, SymbolType::Function);
+
+        let calls: Vec<String> = parser.parse_usages(code, false).unwrap().iter()
+            .filter(|x| x.type_str() == "function_call_info")
+            .map(|x| x.meta_path())
+            .collect();
+        assert!(calls.contains(&"g::self".to_string()));
+        assert!(calls.contains(&"zeros::np".to_string()));
+        assert!(calls.contains(&"A".to_string()));
+    }
 }
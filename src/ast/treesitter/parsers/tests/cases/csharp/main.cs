@@ -0,0 +1,51 @@
+using System;
+
+namespace Demo.Shapes
+{
+    // A point on a plane
+    public record Point(double X, double Y);
+
+    public interface IShape
+    {
+        double Area();
+    }
+
+    public enum Color
+    {
+        Red,
+        Green
+    }
+
+    public class Circle : IShape
+    {
+        private readonly double radius;
+
+        public string Name { get; set; }
+
+        public Circle(double radius)
+        {
+            this.radius = radius;
+        }
+
+        public double Area()
+        {
+            double squared = Square(radius);
+            return Math.PI * squared;
+        }
+
+        private static double Square(double x)
+        {
+            return x * x;
+        }
+    }
+
+    public static class StringExtensions
+    {
+        public static string Shout(this string s)
+        {
+            var loud = s.ToUpper();
+            Console.WriteLine("shouting");
+            return loud;
+        }
+    }
+}
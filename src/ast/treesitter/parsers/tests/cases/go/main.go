@@ -0,0 +1,44 @@
+package main
+
+import (
+	"fmt"
+	"strings"
+)
+
+// Greeting is printed by main
+const Greeting = "hello"
+
+var counter int
+
+type Shape interface {
+	Area() float64
+}
+
+type Point struct {
+	X float64
+	Y float64
+}
+
+func (p Point) Distance(other Point) float64 {
+	dx := p.X - other.X
+	dy := p.Y - other.Y
+	return dx*dx + dy*dy
+}
+
+func (p *Point) Move(dx float64, dy float64) {
+	p.X += dx
+	p.Y += dy
+}
+
+func NewPoint(x float64, y float64) *Point {
+	return &Point{X: x, Y: y}
+}
+
+func main() {
+	p := NewPoint(1, 2)
+	p.Move(3, 4)
+	var name string
+	name = strings.ToUpper(Greeting)
+	fmt.Println(name, p.Distance(Point{}), `raw`)
+	counter++
+}
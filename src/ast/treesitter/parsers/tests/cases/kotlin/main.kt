@@ -0,0 +1,39 @@
+package demo.shapes
+
+import kotlin.math.sqrt
+
+// Everything here is about shapes
+val ORIGIN = Point(0.0, 0.0)
+
+data class Point(val x: Double, val y: Double)
+
+interface Shape {
+    fun area(): Double
+}
+
+enum class Color {
+    RED,
+    GREEN
+}
+
+class Circle(private val radius: Double) : Shape {
+    var name: String = "circle"
+
+    override fun area(): Double {
+        val squared = square(radius)
+        return Math.PI * squared
+    }
+
+    private fun square(x: Double): Double = x * x
+}
+
+object Registry {
+    fun register(shape: Shape) {
+        println("registered")
+    }
+}
+
+fun Point.distanceTo(other: Point): Double {
+    val dx = x - other.x
+    return sqrt(dx * dx)
+}
@@ -0,0 +1,20 @@
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::ast::treesitter::parsers::csharp::CSharpParser;
+    use crate::ast::treesitter::parsers::tests::test_query_function;
+
+    const MAIN_CS_CODE: &str = include_str!("cases/csharp/main.cs");
+    const MAIN_CS_INDEXES: &str = include_str!("cases/csharp/main.cs.indexes.json");
+    const MAIN_CS_USAGES: &str = include_str!("cases/csharp/main.cs.usages.json");
+
+    #[test]
+    fn test_query_csharp_function() {
+        let parser = Box::new(CSharpParser::new().expect("CSharpParser::new"));
+        let path = PathBuf::from("main.cs");
+        test_query_function(parser, &path, MAIN_CS_CODE,
+                            serde_json::from_str(MAIN_CS_INDEXES).unwrap(),
+                            serde_json::from_str(MAIN_CS_USAGES).unwrap());
+    }
+}
@@ -0,0 +1,20 @@
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::ast::treesitter::parsers::kotlin::KotlinParser;
+    use crate::ast::treesitter::parsers::tests::test_query_function;
+
+    const MAIN_KT_CODE: &str = include_str!("cases/kotlin/main.kt");
+    const MAIN_KT_INDEXES: &str = include_str!("cases/kotlin/main.kt.indexes.json");
+    const MAIN_KT_USAGES: &str = include_str!("cases/kotlin/main.kt.usages.json");
+
+    #[test]
+    fn test_query_kotlin_function() {
+        let parser = Box::new(KotlinParser::new().expect("KotlinParser::new"));
+        let path = PathBuf::from("main.kt");
+        test_query_function(parser, &path, MAIN_KT_CODE,
+                            serde_json::from_str(MAIN_KT_INDEXES).unwrap(),
+                            serde_json::from_str(MAIN_KT_USAGES).unwrap());
+    }
+}
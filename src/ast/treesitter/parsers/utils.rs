@@ -1,5 +1,5 @@
 use similar::DiffableStr;
-use tree_sitter::{Node, Parser, Query, QueryCapture, Range};
+use tree_sitter::{Language, Node, Parser, Query, QueryCapture, Range};
 
 use crate::ast::treesitter::structs::{FunctionCallInfo, StaticInfo, StaticType, VariableInfo};
 
@@ -119,3 +119,12 @@ pub(crate) fn try_to_find_matches(parser: &mut Parser, query_str: &str, parent:
     }
     res
 }
+
+pub(crate) fn find_statics_query(language: Language, comments: &[&str], literals: &[&str]) -> String {
+    // grammar versions rename comment and string nodes, an unknown node kind makes the whole query fail
+    let known = |kind: &&&str| language.id_for_node_kind(kind, true) != 0;
+    let mut alternatives: Vec<String> = vec![];
+    alternatives.extend(comments.iter().filter(known).map(|x| format!("({}) @comment", x)));
+    alternatives.extend(literals.iter().filter(known).map(|x| format!("({}) @string_literal", x)));
+    format!("(\n([\n{}\n])\n)", alternatives.join("\n"))
+}
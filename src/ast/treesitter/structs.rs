@@ -36,6 +36,7 @@ pub(crate) struct RangeDef {
 #[dyn_partial_eq]
 pub trait UsageSymbolInfo: Debug + Send + Sync {
     fn meta_path(&self) -> String;
+    fn name(&self) -> String;
     fn distance_to_cursor(&self, cursor: &Point) -> usize;
     fn type_str(&self) -> String;
     fn get_range(&self) -> Range;
@@ -63,6 +64,9 @@ impl UsageSymbolInfo for VariableInfo {
             self.name.clone()
         }
     }
+    fn name(&self) -> String {
+        self.name.clone()
+    }
     fn distance_to_cursor(&self, cursor: &Point) -> usize {
         cursor.row.abs_diff(self.range.start_point.row)
     }
@@ -102,6 +106,9 @@ impl UsageSymbolInfo for FunctionCallInfo {
             None => self.name.clone(),
         }
     }
+    fn name(&self) -> String {
+        self.name.clone()
+    }
     fn distance_to_cursor(&self, cursor: &Point) -> usize {
         cursor.row.abs_diff(self.range.start_point.row)
     }
@@ -143,6 +150,9 @@ impl UsageSymbolInfo for StaticInfo {
     fn meta_path(&self) -> String {
         format!("{}", self.data)
     }
+    fn name(&self) -> String {
+        self.data.clone()
+    }
     fn distance_to_cursor(&self, cursor: &Point) -> usize {
         cursor.row.abs_diff(self.range.start_point.row)
     }
@@ -195,6 +205,15 @@ pub struct SymbolInfo {
     pub range: Range,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ImportInfo {
+    pub alias: String,  // the name the file uses: "np" for import numpy as np, "os" for import os.path, "Foo" for from x import Foo
+    pub module: String,  // "numpy", "pkg.sub", ".sibling" or "." for relative imports
+    pub name: Option<String>,  // Some("Foo") for from x import Foo, None for import x
+    #[serde(with = "RangeDef")]
+    pub range: Range,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct SymbolDeclarationStruct {
     pub name: String,
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::json;
+use tokio::sync::Mutex as AMutex;
+use tracing::info;
+
+use crate::ast::structs::CallGraphDirection;
+use crate::ast::treesitter::structs::SymbolDeclarationStruct;
+use crate::at_commands::at_commands::{AtCommand, AtCommandsContext, AtParam};
+use crate::at_commands::at_params::AtParamSymbolPathQuery;
+use crate::at_commands::at_tree::AtParamDepth;
+use crate::call_validation::{ChatMessage, ContextFile};
+
+const CALL_GRAPH_DEFAULT_DEPTH: usize = 2;
+const CALL_GRAPH_MAX_DEPTH: usize = 5;
+const CALL_GRAPH_MAX_SYMBOLS: usize = 30;
+const CALL_GRAPH_MAX_USEFULNESS: f32 = 90.0;
+
+
+async fn results2message(results: &Vec<(SymbolDeclarationStruct, usize)>) -> ChatMessage {
+    // the closer in the call graph, the more useful
+    let mut vector_of_context_file: Vec<ContextFile> = vec![];
+    for (decl, distance) in results.iter() {
+        let content = decl.get_content().await.unwrap_or("".to_string());
+        vector_of_context_file.push(ContextFile {
+            file_name: decl.definition_info.path.to_string_lossy().to_string(),
+            file_content: content,
+            line1: decl.definition_info.range.start_point.row + 1,
+            line2: decl.definition_info.range.end_point.row + 1,
+            usefulness: CALL_GRAPH_MAX_USEFULNESS / (*distance as f32 + 1.0),
+            synthetic: false,
+        });
+    }
+    ChatMessage {
+        role: "context_file".to_string(),
+        content: json!(vector_of_context_file).to_string(),
+    }
+}
+
+async fn execute_call_graph(
+    direction: CallGraphDirection,
+    args: &Vec<String>,
+    context: &AtCommandsContext,
+) -> Result<ChatMessage, String> {
+    let symbol = match args.get(0) {
+        Some(x) => x.clone(),
+        None => return Err("no symbol given".to_string()),
+    };
+    let depth = match args.get(1) {
+        Some(x) => x.parse::<usize>().map_err(|_| format!("depth should be a number, not {:?}", x))?,
+        None => CALL_GRAPH_DEFAULT_DEPTH,
+    }.clamp(1, CALL_GRAPH_MAX_DEPTH);
+    info!("execute call graph {:?} of {:?} depth {}", direction, symbol, depth);
+    let ast_module = context.global_context.read().await.ast_module.clone();
+    let results = match *ast_module.lock().await {
+        Some(ref ast) => ast.search_call_graph(symbol.clone(), direction, depth, CALL_GRAPH_MAX_SYMBOLS).await?,
+        None => return Err("Ast module is not available".to_string()),
+    };
+    if results.len() <= 1 {
+        let what = match direction {
+            CallGraphDirection::Callers => "callers",
+            CallGraphDirection::Callees => "callees",
+        };
+        return Err(format!("no {} found for {}", what, symbol));
+    }
+    Ok(results2message(&results).await)
+}
+
+
+pub struct AtAstCallers {
+    pub name: String,
+    pub params: Vec<Arc<AMutex<dyn AtParam>>>,
+}
+
+impl AtAstCallers {
+    pub fn new() -> Self {
+        AtAstCallers {
+            name: "@callers".to_string(),
+            params: vec![
+                Arc::new(AMutex::new(AtParamSymbolPathQuery::new())),
+                Arc::new(AMutex::new(AtParamDepth::new())),
+            ],
+        }
+    }
+}
+
+#[async_trait]
+impl AtCommand for AtAstCallers {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>> {
+        &self.params
+    }
+    async fn can_execute(&self, args: &Vec<String>, context: &AtCommandsContext) -> bool {
+        can_execute_call_graph(&self.params, args, context).await
+    }
+    async fn execute(&self, _query: &String, args: &Vec<String>, _top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
+        execute_call_graph(CallGraphDirection::Callers, args, context).await
+    }
+}
+
+
+pub struct AtAstCallees {
+    pub name: String,
+    pub params: Vec<Arc<AMutex<dyn AtParam>>>,
+}
+
+impl AtAstCallees {
+    pub fn new() -> Self {
+        AtAstCallees {
+            name: "@callees".to_string(),
+            params: vec![
+                Arc::new(AMutex::new(AtParamSymbolPathQuery::new())),
+                Arc::new(AMutex::new(AtParamDepth::new())),
+            ],
+        }
+    }
+}
+
+#[async_trait]
+impl AtCommand for AtAstCallees {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>> {
+        &self.params
+    }
+    async fn can_execute(&self, args: &Vec<String>, context: &AtCommandsContext) -> bool {
+        can_execute_call_graph(&self.params, args, context).await
+    }
+    async fn execute(&self, _query: &String, args: &Vec<String>, _top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
+        execute_call_graph(CallGraphDirection::Callees, args, context).await
+    }
+}
+
+async fn can_execute_call_graph(params: &Vec<Arc<AMutex<dyn AtParam>>>, args: &Vec<String>, context: &AtCommandsContext) -> bool {
+    // the symbol is required, the depth is optional
+    if args.is_empty() || args.len() > params.len() {
+        return false;
+    }
+    for (arg, param) in args.iter().zip(params.iter()) {
+        if !param.lock().await.is_value_valid(arg, context).await {
+            return false;
+        }
+    }
+    true
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::ast_module::AstModule;
+    use crate::files_in_workspace::DocumentInfo;
+    use crate::global_context::create_global_context_for_tests;
+
+    #[tokio::test]
+    async fn test_at_callers_and_callees() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("a.py");
+        std::fs::write(&path, "def helper():\n    pass\n\ndef middle():\n    helper()\n\ndef top():\n    middle()\n").unwrap();
+        let gcx = create_global_context_for_tests(tmp_dir.path().join("cache")).await;
+        let ast = AstModule::ast_indexer_init(gcx.clone()).await.unwrap();
+        ast.ast_add_file_no_queue(&DocumentInfo::from_pathbuf(&path).unwrap()).await.unwrap();
+        gcx.write().await.ast_module = Arc::new(AMutex::new(Some(ast)));
+        let context = AtCommandsContext::new(gcx.clone()).await;
+
+        let msg = AtAstCallers::new().execute(&"".to_string(), &vec!["helper".to_string(), "1".to_string()], 5, &context).await.unwrap();
+        let files: Vec<ContextFile> = serde_json::from_str(&msg.content).unwrap();
+        let lines: Vec<(usize, usize)> = files.iter().map(|x| (x.line1, x.line2)).collect();
+        assert_eq!(lines, vec![(1, 2), (4, 5)]);  // helper itself, then middle, top is 2 calls away
+        assert!(files[0].usefulness > files[1].usefulness);
+        assert!(files[1].file_content.contains("def middle"));
+
+        let msg = AtAstCallees::new().execute(&"".to_string(), &vec!["top".to_string()], 5, &context).await.unwrap();
+        let files: Vec<ContextFile> = serde_json::from_str(&msg.content).unwrap();
+        assert_eq!(files.len(), 3);
+
+        assert!(AtAstCallers::new().execute(&"".to_string(), &vec!["top".to_string()], 5, &context).await.is_err());
+    }
+}
@@ -4,27 +4,89 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use serde_json::json;
 use tokio::sync::Mutex as AMutex;
+use tracing::info;
 
-use crate::ast::structs::FileReferencesResult;
+use crate::ast::treesitter::structs::{SymbolDeclarationStruct, SymbolType};
 use crate::at_commands::at_commands::{AtCommand, AtCommandsContext, AtParam};
-use crate::at_commands::at_file::AtParamFilePath;
+use crate::at_commands::at_file::{AtParamFilePath, parameter_repair_candidates};
 use crate::call_validation::{ChatMessage, ContextFile};
-use crate::files_in_workspace::DocumentInfo;
-
-fn results2message(result: &FileReferencesResult) -> ChatMessage {
-    let simplified_symbols: Vec<ContextFile> = result.symbols.iter().map(|x| {
-        let path = format!("{:?}::", result.file_path).to_string();
-        ContextFile {
-            file_name: x.meta_path.replace(path.as_str(), ""),
-            file_content: format!("{:?}", x.symbol_type),
-            line1: x.definition_info.range.start_point.row + 1,
-            line2: x.definition_info.range.end_point.row + 1,
-            usefulness: 100.0
+use crate::files_in_workspace::{DocumentInfo, get_file_text_from_memory_or_disk};
+
+const OUTLINE_USEFULNESS: f32 = 5.0;  // an outline is cheap, but it's less useful than real code
+const OUTLINE_SIGNATURE_MAX_CHARS: usize = 120;
+
+
+fn symbols_into_hierarchy(symbols: Vec<SymbolDeclarationStruct>) -> Vec<SymbolDeclarationStruct> {
+    // parsers give a flat list, nest it using meta_path: "file::Class::method" goes into children of "file::Class"
+    let mut symbols = symbols;
+    symbols.sort_by_key(|s| std::cmp::Reverse(s.meta_path.matches("::").count()));
+    let mut roots: Vec<SymbolDeclarationStruct> = vec![];
+    while !symbols.is_empty() {
+        // the deepest symbol goes first, so its parent is still in the list
+        let sym = symbols.remove(0);
+        let parent_idx = symbols.iter()
+            .enumerate()
+            .filter(|(_, p)| sym.meta_path.starts_with(&format!("{}::", p.meta_path)))
+            .max_by_key(|(_, p)| p.meta_path.len())
+            .map(|(idx, _)| idx);
+        match parent_idx {
+            Some(idx) => symbols[idx].children.push(sym),
+            None => roots.push(sym),
         }
-    }).collect();
+    }
+    fn sort_by_line(symbols: &mut Vec<SymbolDeclarationStruct>) {
+        symbols.sort_by_key(|s| (s.definition_info.range.start_point.row, s.name.clone()));
+        for s in symbols.iter_mut() {
+            sort_by_line(&mut s.children);
+        }
+    }
+    sort_by_line(&mut roots);
+    roots
+}
+
+fn symbol_signature(symbol: &SymbolDeclarationStruct, file_lines: &Vec<&str>) -> String {
+    if symbol.symbol_type == SymbolType::Enum {
+        // enum values share the range of the whole enum, the first line is not about them
+        return symbol.name.clone();
+    }
+    let line = file_lines.get(symbol.definition_info.range.start_point.row).map(|x| x.trim()).unwrap_or("");
+    let signature = line.trim_end_matches(|c| c == '{' || c == ':' || c == ' ');
+    if signature.is_empty() {
+        return symbol.name.clone();
+    }
+    crate::nicer_logs::first_n_chars(&signature.to_string(), OUTLINE_SIGNATURE_MAX_CHARS)
+}
+
+fn render_outline(symbol: &SymbolDeclarationStruct, file_lines: &Vec<&str>, depth: usize, out: &mut String) {
+    out.push_str(&format!("{}{}  [{}-{}]\n",
+        "    ".repeat(depth),
+        symbol_signature(symbol, file_lines),
+        symbol.definition_info.range.start_point.row + 1,
+        symbol.definition_info.range.end_point.row + 1,
+    ));
+    for child in symbol.children.iter() {
+        render_outline(child, file_lines, depth + 1, out);
+    }
+}
+
+fn results2message(file_path: &String, symbols: Vec<SymbolDeclarationStruct>, file_text: &String) -> ChatMessage {
+    let file_lines: Vec<&str> = file_text.lines().collect();
+    let mut outline: Vec<ContextFile> = vec![];
+    for sym in symbols_into_hierarchy(symbols).iter() {
+        let mut text = String::new();
+        render_outline(sym, &file_lines, 0, &mut text);
+        outline.push(ContextFile {
+            file_name: file_path.clone(),
+            file_content: text,
+            line1: sym.definition_info.range.start_point.row + 1,
+            line2: sym.definition_info.range.end_point.row + 1,
+            usefulness: OUTLINE_USEFULNESS,
+            synthetic: true,
+        });
+    }
     ChatMessage {
-        role: "simplified_symbol_declaration".to_string(),
-        content: json!(simplified_symbols).to_string(),
+        role: "context_file".to_string(),
+        content: json!(outline).to_string(),
     }
 }
 
@@ -36,7 +98,7 @@ pub struct AtAstFileSymbols {
 impl AtAstFileSymbols {
     pub fn new() -> Self {
         AtAstFileSymbols {
-            name: "@symbols-at".to_string(),
+            name: "@symbols".to_string(),
             params: vec![
                 Arc::new(AMutex::new(AtParamFilePath::new()))
             ],
@@ -66,20 +128,26 @@ impl AtCommand for AtAstFileSymbols {
         if !can_execute {
             return Err("incorrect arguments".to_string());
         }
-        let file_path = match args.get(0) {
+        let correctable_file_path = match args.get(0) {
             Some(x) => x,
             None => return Err("no file path".to_string()),
         };
+        let file_path = match parameter_repair_candidates(correctable_file_path, context, 1).await.get(0) {
+            Some(x) => x.clone(),
+            None => return Err(format!("file {:?} not found", correctable_file_path)),
+        };
+        info!("execute @symbols {:?}", file_path);
+        let file_text = get_file_text_from_memory_or_disk(context.global_context.clone(), &file_path).await?;
 
         let binding = context.global_context.read().await;
         let x = match *binding.ast_module.lock().await {
             Some(ref ast) => {
-                let doc = match DocumentInfo::from_pathbuf(&PathBuf::from(file_path)).ok() {
+                let doc = match DocumentInfo::from_pathbuf(&PathBuf::from(&file_path)).ok() {
                     Some(doc) => doc,
                     None => return Err("file not found".to_string())
                 };
                 match ast.get_file_symbols(&doc).await {
-                    Ok(res) => Ok(results2message(&res)),
+                    Ok(res) => Ok(results2message(&file_path, res.symbols, &file_text)),
                     Err(err) => Err(err)
                 }
             }
@@ -87,3 +155,48 @@ impl AtCommand for AtAstFileSymbols {
         }; x
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::{Point, Range};
+    use crate::ast::treesitter::language_id::LanguageId;
+    use crate::ast::treesitter::structs::SymbolInfo;
+    use super::*;
+
+    fn make_symbol(meta_path: &str, symbol_type: SymbolType, row1: usize, row2: usize) -> SymbolDeclarationStruct {
+        SymbolDeclarationStruct {
+            name: meta_path.split("::").last().unwrap().to_string(),
+            definition_info: SymbolInfo {
+                path: PathBuf::from("/a.rs"),
+                range: Range { start_byte: 0, end_byte: 0, start_point: Point::new(row1, 0), end_point: Point::new(row2, 0) },
+            },
+            children: vec![],
+            symbol_type,
+            meta_path: meta_path.to_string(),
+            language: LanguageId::Rust,
+            extra_declarations: vec![],
+        }
+    }
+
+    #[test]
+    fn test_outline() {
+        let code = "struct Point {\n    x: f32,\n}\nimpl Point {\n    fn len(&self) -> f32 {\n        0.0\n    }\n}\nfn main() {\n}\n".to_string();
+        let symbols = vec![
+            make_symbol("/a.rs::main", SymbolType::Function, 8, 9),
+            make_symbol("/a.rs::Point::len", SymbolType::Function, 4, 6),
+            make_symbol("/a.rs::Point", SymbolType::Class, 0, 2),
+        ];
+        let roots = symbols_into_hierarchy(symbols);
+        assert_eq!(roots.len(), 2);
+        assert_eq!(roots[0].name, "Point");
+        assert_eq!(roots[0].children.len(), 1);
+        assert_eq!(roots[1].name, "main");
+
+        let msg = results2message(&"/a.rs".to_string(), roots, &code);
+        let outline: Vec<ContextFile> = serde_json::from_str(&msg.content).unwrap();
+        assert_eq!(outline.len(), 2);
+        assert_eq!(outline[0].file_content, "struct Point  [1-3]\n    fn len(&self) -> f32  [5-7]\n");
+        assert_eq!(outline[1].file_content, "fn main()  [9-10]\n");
+        assert!(outline.iter().all(|x| x.synthetic));
+    }
+}
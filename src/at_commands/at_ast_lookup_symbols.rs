@@ -42,6 +42,7 @@ pub async fn results2message(result: &AstCursorSearchResult) -> ChatMessage {
             line1: res.symbol_declaration.definition_info.range.start_point.row + 1,
             line2: res.symbol_declaration.definition_info.range.end_point.row + 1,
             usefulness: res.sim_to_query,
+            synthetic: false,
         });
     }
     ChatMessage {
@@ -4,11 +4,20 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use tokio::sync::Mutex as AMutex;
 use tokio::sync::RwLock as ARwLock;
+use tracing::warn;
 
+use crate::at_commands::at_ast_call_graph::{AtAstCallees, AtAstCallers};
 use crate::at_commands::at_ast_definition::AtAstDefinition;
+use crate::at_commands::at_ast_file_symbols::AtAstFileSymbols;
 use crate::at_commands::at_ast_lookup_symbols::AtAstLookupSymbols;
 use crate::at_commands::at_ast_reference::AtAstReference;
+use crate::at_commands::at_custom::custom_at_commands_dict;
+use crate::at_commands::at_diff::AtDiff;
 use crate::at_commands::at_file::AtFile;
+use crate::at_commands::at_grep::AtGrep;
+use crate::at_commands::at_pin::AtPin;
+use crate::at_commands::at_tests::AtTests;
+use crate::at_commands::at_tree::AtTree;
 use crate::at_commands::at_workspace::AtWorkspace;
 use crate::call_validation::ChatMessage;
 use crate::global_context::GlobalContext;
@@ -16,13 +25,33 @@ use crate::global_context::GlobalContext;
 pub struct AtCommandsContext {
     pub global_context: Arc<ARwLock<GlobalContext>>,
     pub at_commands: HashMap<String, Arc<AMutex<Box<dyn AtCommand + Send>>>>,
+    pub chat_id: String,  // the thread the commands are executed for, @pin remembers pins per thread
+    pub dry_run: bool,    // preview, commands should not change anything
+    pub tokens_limit: usize,  // context budget of the message being executed, 0 if not known
 }
 
 impl AtCommandsContext {
     pub async fn new(global_context: Arc<ARwLock<GlobalContext>>) -> Self {
+        let mut at_commands = at_commands_dict().await;
+        let allowed_executables = global_context.read().await.cmdline.allow_executable.clone();
+        match crate::toolbox::toolbox_config::load_customization_cached(global_context.clone()).await {
+            Ok(tconfig) => {
+                for (name, cmd) in custom_at_commands_dict(&tconfig.custom_at_commands, &allowed_executables) {
+                    if at_commands.contains_key(&name) {
+                        warn!("custom at-command {} is ignored, there is a built-in command with the same name", name);
+                        continue;
+                    }
+                    at_commands.insert(name, cmd);
+                }
+            }
+            Err(e) => warn!("custom at-commands are not available: {}", e),
+        }
         AtCommandsContext {
             global_context,
-            at_commands: at_commands_dict().await,
+            at_commands,
+            chat_id: "".to_string(),
+            dry_run: false,
+            tokens_limit: 0,
         }
     }
 }
@@ -31,6 +60,7 @@ impl AtCommandsContext {
 pub trait AtCommand: Send + Sync {
     fn name(&self) -> &String;
     fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>>;
+    fn max_args(&self) -> usize {self.params().len()}  // more than params() for free text that goes on to the end of the line
     async fn can_execute(&self, _args: &Vec<String>, _context: &AtCommandsContext) -> bool {true}
     async fn execute(&self, query: &String, args: &Vec<String>, top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String>;
 }
@@ -46,13 +76,15 @@ pub trait AtParam: Send + Sync {
 pub struct AtCommandCall {
     pub command: Arc<AMutex<Box<dyn AtCommand + Send>>>,
     pub args: Vec<String>,
+    pub flags: HashMap<String, String>,  // --key=value, applied by the caller to any command, see execute_at_commands_in_query
 }
 
 impl AtCommandCall {
-    pub fn new(command: Arc<AMutex<Box<dyn AtCommand + Send>>>, args: Vec<String>) -> Self {
+    pub fn new(command: Arc<AMutex<Box<dyn AtCommand + Send>>>, args: Vec<String>, flags: HashMap<String, String>) -> Self {
         AtCommandCall {
             command,
             args,
+            flags,
         }
     }
 }
@@ -64,5 +96,13 @@ pub async fn at_commands_dict() -> HashMap<String, Arc<AMutex<Box<dyn AtCommand
         ("@definition".to_string(), Arc::new(AMutex::new(Box::new(AtAstDefinition::new()) as Box<dyn AtCommand + Send>))),
         ("@references".to_string(), Arc::new(AMutex::new(Box::new(AtAstReference::new()) as Box<dyn AtCommand + Send>))),
         ("@symbols-at".to_string(), Arc::new(AMutex::new(Box::new(AtAstLookupSymbols::new()) as Box<dyn AtCommand + Send>))),
+        ("@callers".to_string(), Arc::new(AMutex::new(Box::new(AtAstCallers::new()) as Box<dyn AtCommand + Send>))),
+        ("@callees".to_string(), Arc::new(AMutex::new(Box::new(AtAstCallees::new()) as Box<dyn AtCommand + Send>))),
+        ("@symbols".to_string(), Arc::new(AMutex::new(Box::new(AtAstFileSymbols::new()) as Box<dyn AtCommand + Send>))),
+        ("@diff".to_string(), Arc::new(AMutex::new(Box::new(AtDiff::new()) as Box<dyn AtCommand + Send>))),
+        ("@grep".to_string(), Arc::new(AMutex::new(Box::new(AtGrep::new()) as Box<dyn AtCommand + Send>))),
+        ("@pin".to_string(), Arc::new(AMutex::new(Box::new(AtPin::new()) as Box<dyn AtCommand + Send>))),
+        ("@tests".to_string(), Arc::new(AMutex::new(Box::new(AtTests::new()) as Box<dyn AtCommand + Send>))),
+        ("@tree".to_string(), Arc::new(AMutex::new(Box::new(AtTree::new()) as Box<dyn AtCommand + Send>))),
     ]);
 }
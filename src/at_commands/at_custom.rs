@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::AsyncWriteExt;
+use serde_json::json;
+use tokio::sync::Mutex as AMutex;
+use tracing::{info, warn};
+
+use crate::at_commands::at_commands::{at_commands_dict, AtCommand, AtCommandsContext, AtParam};
+use crate::at_commands::at_file::AtParamFilePath;
+use crate::at_commands::at_params::AtParamSymbolPathQuery;
+use crate::at_commands::query::quote_if_needed;
+use crate::at_commands::utils::execute_at_commands_in_query;
+use crate::call_validation::{ChatMessage, ContextFile};
+use crate::toolbox::toolbox_config::CustomAtCommand;
+
+const CUSTOM_EXECUTABLE_TIMEOUT: u64 = 30;  // seconds
+
+
+#[derive(Debug)]
+pub struct AtParamFreeText {
+    pub name: String,
+}
+
+impl AtParamFreeText {
+    pub fn new(name: &String) -> Self {
+        Self {
+            name: name.clone()
+        }
+    }
+}
+
+#[async_trait]
+impl AtParam for AtParamFreeText {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    async fn is_value_valid(&self, value: &String, _: &AtCommandsContext) -> bool {
+        !value.is_empty()
+    }
+    async fn complete(&self, _value: &String, _context: &AtCommandsContext, _top_n: usize) -> Vec<String> {
+        vec![]
+    }
+}
+
+
+fn context_files_from_messages(messages: &Vec<ChatMessage>) -> Vec<ContextFile> {
+    let mut result = vec![];
+    for msg in messages.iter() {
+        match serde_json::from_str::<Vec<ContextFile>>(&msg.content) {
+            Ok(x) => result.extend(x),
+            Err(e) => warn!("cannot parse context files from {}: {}", msg.role, e),
+        }
+    }
+    result
+}
+
+fn results2message(context_files: &Vec<ContextFile>) -> ChatMessage {
+    ChatMessage {
+        role: "context_file".to_string(),
+        content: json!(context_files).to_string(),
+    }
+}
+
+fn expand_macro(expand_to: &String, args: &Vec<String>) -> String {
+    let args_str = args.iter().map(quote_if_needed).collect::<Vec<String>>().join(" ");
+    expand_to.replace("%ARGS%", &args_str)
+}
+
+
+pub struct AtCustomCommand {
+    pub name: String,
+    pub params: Vec<Arc<AMutex<dyn AtParam>>>,
+    pub config: CustomAtCommand,
+    pub allowed_executables: Vec<String>,
+}
+
+impl AtCustomCommand {
+    pub fn new(name: String, config: CustomAtCommand, allowed_executables: Vec<String>) -> Self {
+        let params = config.params.iter().map(|p| match p.as_str() {
+            "file_path" => Arc::new(AMutex::new(AtParamFilePath::new())) as Arc<AMutex<dyn AtParam>>,
+            "symbol" => Arc::new(AMutex::new(AtParamSymbolPathQuery::new())) as Arc<AMutex<dyn AtParam>>,
+            _ => Arc::new(AMutex::new(AtParamFreeText::new(p))) as Arc<AMutex<dyn AtParam>>,
+        }).collect();
+        AtCustomCommand {
+            name,
+            params,
+            config,
+            allowed_executables,
+        }
+    }
+
+    async fn execute_macro(&self, args: &Vec<String>, top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
+        let mut expanded = expand_macro(&self.config.expand_to, args);
+        info!("execute {} expands to {:?}", self.name, expanded);
+        // only built-in commands inside a macro, so macros can't call each other in a loop
+        let builtin_context = AtCommandsContext {
+            global_context: context.global_context.clone(),
+            at_commands: at_commands_dict().await,
+            chat_id: context.chat_id.clone(),
+            dry_run: context.dry_run,
+            tokens_limit: context.tokens_limit,
+        };
+        let messages = execute_at_commands_in_query(&mut expanded, &builtin_context, top_n).await;
+        let context_files = context_files_from_messages(&messages);
+        if context_files.is_empty() {
+            return Err(format!("{} expanded to nothing useful", self.name));
+        }
+        Ok(results2message(&context_files))
+    }
+
+    async fn execute_executable(&self, query: &String, args: &Vec<String>, top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
+        let program = &self.config.executable[0];
+        if !self.allowed_executables.contains(program) {
+            return Err(format!("{} is not allowed with --allow-executable, refusing to run it", program));
+        }
+        let workspace_folders: Vec<PathBuf> = context.global_context.read().await.documents_state.workspace_folders.lock().unwrap().clone();
+        let request = json!({
+            "command": self.name,
+            "args": args,
+            "query": query,
+            "top_n": top_n,
+            "workspace_folders": workspace_folders,
+        });
+        info!("execute {} runs {:?}", self.name, self.config.executable);
+        let mut cmd = async_process::Command::new(program);
+        cmd.args(&self.config.executable[1..])
+            .stdin(async_process::Stdio::piped())
+            .stdout(async_process::Stdio::piped())
+            .stderr(async_process::Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(folder) = workspace_folders.first() {
+            cmd.current_dir(folder);
+        }
+        let mut child = cmd.spawn().map_err(|e| format!("cannot run {}: {}", program, e))?;
+        // the timeout covers writing the request too, a program that doesn't read stdin would block it forever
+        let interaction = async {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(request.to_string().as_bytes()).await.map_err(|e| format!("cannot write to {}: {}", program, e))?;
+                // dropping stdin closes it, the program knows the request is complete
+            }
+            child.output().await.map_err(|e| format!("{} failed: {}", program, e))
+        };
+        let output = tokio::time::timeout(tokio::time::Duration::from_secs(CUSTOM_EXECUTABLE_TIMEOUT), interaction)
+            .await
+            .map_err(|_| format!("{} didn't finish in {} seconds", program, CUSTOM_EXECUTABLE_TIMEOUT))??;
+        if !output.status.success() {
+            return Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        let context_files: Vec<ContextFile> = serde_json::from_slice(&output.stdout)
+            .map_err(|e| format!("{} should print a json list of context files: {}", program, e))?;
+        Ok(results2message(&context_files))
+    }
+}
+
+#[async_trait]
+impl AtCommand for AtCustomCommand {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>> {
+        &self.params
+    }
+    async fn can_execute(&self, args: &Vec<String>, context: &AtCommandsContext) -> bool {
+        if args.len() > self.params.len() {
+            return false;
+        }
+        for (arg, param) in args.iter().zip(self.params.iter()) {
+            if !param.lock().await.is_value_valid(arg, context).await {
+                return false;
+            }
+        }
+        true
+    }
+    async fn execute(&self, query: &String, args: &Vec<String>, top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
+        if !self.config.expand_to.is_empty() {
+            self.execute_macro(args, top_n, context).await
+        } else if !self.config.executable.is_empty() {
+            self.execute_executable(query, args, top_n, context).await
+        } else {
+            Err(format!("{} has neither expand_to nor executable in customization.yaml", self.name))
+        }
+    }
+}
+
+pub fn custom_at_commands_dict(
+    custom_at_commands: &HashMap<String, CustomAtCommand>,
+    allowed_executables: &Vec<String>,
+) -> HashMap<String, Arc<AMutex<Box<dyn AtCommand + Send>>>> {
+    let mut result = HashMap::new();
+    for (name, config) in custom_at_commands.iter() {
+        let name = if name.starts_with("@") { name.clone() } else { format!("@{}", name) };
+        let cmd = AtCustomCommand::new(name.clone(), config.clone(), allowed_executables.clone());
+        result.insert(name, Arc::new(AMutex::new(Box::new(cmd) as Box<dyn AtCommand + Send>)));
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_macro() {
+        let expand_to = "@definition %ARGS%\n@references %ARGS%".to_string();
+        assert_eq!(expand_macro(&expand_to, &vec!["MyClass".to_string()]), "@definition MyClass\n@references MyClass");
+        assert_eq!(expand_macro(&"@file %ARGS%".to_string(), &vec!["a b.rs".to_string(), "c.rs".to_string()]), "@file \"a b.rs\" c.rs");
+    }
+}
@@ -0,0 +1,429 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::json;
+use tokio::sync::Mutex as AMutex;
+use tracing::info;
+use which::which;
+
+use crate::at_commands::at_commands::{AtCommand, AtCommandsContext, AtParam};
+use crate::at_commands::at_file::AtParamFilePath;
+use crate::call_validation::{ChatMessage, ContextFile};
+
+const DIFF_HUNK_USEFULNESS: f32 = 70.0;
+const DIFF_COMMIT_USEFULNESS: f32 = 90.0;  // commit messages are short and explain the intent, keep them first
+const DIFF_MAX_COMMITS: usize = 50;
+const DIFF_COMPLETION_RECENT_COMMITS: usize = 20;
+const DIFF_STAGED_ARG: &str = "--staged";
+
+
+async fn run_git(args: &[&str], path: &PathBuf) -> Result<String, String> {
+    info!("{} EXEC git {}", path.display(), args.join(" "));
+    let output = async_process::Command::new("git")
+        .args(args)
+        .current_dir(path)
+        .output()
+        .await
+        .map_err(|e| format!("cannot run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    String::from_utf8(output.stdout).map_err(|e| format!("git output is not utf-8: {}", e))
+}
+
+async fn workspace_folders(context: &AtCommandsContext) -> Vec<PathBuf> {
+    let folders = context.global_context.read().await.documents_state.workspace_folders.lock().unwrap().clone();
+    folders
+}
+
+async fn git_repo_root(context: &AtCommandsContext, hint: Option<&PathBuf>) -> Result<PathBuf, String> {
+    if which("git").is_err() {
+        return Err("git is not installed".to_string());
+    }
+    let mut folders = workspace_folders(context).await;
+    if let Some(hint) = hint {
+        // the folder containing the file goes first
+        folders.sort_by_key(|f| !hint.starts_with(f));
+    }
+    for folder in folders.iter() {
+        if let Ok(toplevel) = run_git(&["rev-parse", "--show-toplevel"], folder).await {
+            return Ok(PathBuf::from(toplevel.trim()));
+        }
+    }
+    Err("no git repository in workspace folders".to_string())
+}
+
+async fn resolve_path(arg: &String, context: &AtCommandsContext) -> Option<PathBuf> {
+    let path = PathBuf::from(arg);
+    if path.is_absolute() {
+        return if path.exists() { Some(path) } else { None };
+    }
+    for folder in workspace_folders(context).await.iter() {
+        let candidate = folder.join(&path);
+        if candidate.exists() {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+async fn is_git_revision(arg: &String, repo_root: &PathBuf) -> bool {
+    if arg.starts_with("-") {
+        return false;
+    }
+    let commit = format!("{}^{{commit}}", arg);
+    run_git(&["rev-parse", "--verify", "--quiet", commit.as_str()], repo_root).await.is_ok()
+}
+
+fn strip_diff_path_prefix(path: &str) -> Option<String> {
+    let path = path.trim().trim_matches('"');
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(path.strip_prefix("b/").or_else(|| path.strip_prefix("a/")).unwrap_or(path).to_string())
+}
+
+fn parse_hunk_header(line: &str) -> Option<(usize, usize)> {
+    // "@@ -12,7 +12,8 @@ fn main() {" => (12, 8), the count is optional and defaults to 1
+    let new_range = line.split_whitespace().nth(2)?.strip_prefix("+")?;
+    let mut parts = new_range.split(',');
+    let start = parts.next()?.parse::<usize>().ok()?;
+    let count = match parts.next() {
+        Some(x) => x.parse::<usize>().ok()?,
+        None => 1,
+    };
+    Some((start, count))
+}
+
+struct DiffHunk {
+    file_name: String,
+    line1: usize,
+    line2: usize,
+    text: String,
+}
+
+fn parse_unified_diff(diff: &str, repo_root: &PathBuf) -> Vec<DiffHunk> {
+    let mut hunks: Vec<DiffHunk> = vec![];
+    let mut old_path: Option<String> = None;
+    let mut new_path: Option<String> = None;
+    let mut current: Option<DiffHunk> = None;
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            hunks.extend(current.take());
+            old_path = None;
+            new_path = None;
+            continue;
+        }
+        if current.is_none() {
+            // file headers come before the first hunk, inside a hunk "--- x" is a removed line "-- x"
+            if let Some(p) = line.strip_prefix("--- ") {
+                old_path = strip_diff_path_prefix(p);
+                continue;
+            }
+            if let Some(p) = line.strip_prefix("+++ ") {
+                new_path = strip_diff_path_prefix(p);
+                continue;
+            }
+        }
+        if line.starts_with("@@ ") {
+            hunks.extend(current.take());
+            let (start, count) = match parse_hunk_header(line) {
+                Some(x) => x,
+                None => continue,
+            };
+            let relative_path = match new_path.clone().or(old_path.clone()) {
+                Some(x) => x,
+                None => continue,
+            };
+            // count == 0 means pure deletion, git reports the line before the deleted block (0 if at the top)
+            let line1 = start.max(1);
+            let line2 = if count == 0 { line1 } else { start + count - 1 };
+            current = Some(DiffHunk {
+                file_name: repo_root.join(relative_path).to_string_lossy().to_string(),
+                line1,
+                line2,
+                text: format!("{}\n", line),
+            });
+            continue;
+        }
+        if let Some(hunk) = current.as_mut() {
+            if line.starts_with(" ") || line.starts_with("+") || line.starts_with("-") || line.starts_with("\\") || line.is_empty() {
+                hunk.text.push_str(line);
+                hunk.text.push('\n');
+            }
+        }
+    }
+    hunks.extend(current.take());
+    hunks
+}
+
+fn parse_git_log(log: &str) -> Vec<(String, String)> {
+    // records are separated by \x1e, the hash and the rest by \x1f, see --format in execute()
+    log.split('\x1e')
+        .filter_map(|record| {
+            let record = record.trim();
+            let (hash, text) = record.split_once('\x1f')?;
+            Some((hash.to_string(), text.replace('\x1f', "\n").trim().to_string()))
+        })
+        .collect()
+}
+
+fn results2message(hunks: Vec<DiffHunk>, commits: Vec<(String, String)>, repo_root: &PathBuf) -> ChatMessage {
+    let mut vector_of_context_file: Vec<ContextFile> = vec![];
+    for (hash, text) in commits.iter() {
+        let file_content = format!("commit {}\n{}\n", hash, text);
+        vector_of_context_file.push(ContextFile {
+            file_name: repo_root.to_string_lossy().to_string(),
+            line1: 1,
+            line2: file_content.lines().count(),
+            file_content,
+            usefulness: DIFF_COMMIT_USEFULNESS,
+            synthetic: true,
+        });
+    }
+    for hunk in hunks.into_iter() {
+        vector_of_context_file.push(ContextFile {
+            file_name: hunk.file_name,
+            file_content: hunk.text,
+            line1: hunk.line1,
+            line2: hunk.line2,
+            usefulness: DIFF_HUNK_USEFULNESS,
+            synthetic: true,
+        });
+    }
+    ChatMessage {
+        role: "context_file".to_string(),
+        content: json!(vector_of_context_file).to_string(),
+    }
+}
+
+
+#[derive(Debug)]
+pub struct AtParamGitRevision {
+    pub name: String,
+}
+
+impl AtParamGitRevision {
+    pub fn new() -> Self {
+        Self {
+            name: "git_revision".to_string()
+        }
+    }
+}
+
+#[async_trait]
+impl AtParam for AtParamGitRevision {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    async fn is_value_valid(&self, value: &String, context: &AtCommandsContext) -> bool {
+        // a revision (branch, tag, commit, HEAD~3), a path, or --staged
+        if value == DIFF_STAGED_ARG || resolve_path(value, context).await.is_some() {
+            return true;
+        }
+        match git_repo_root(context, None).await {
+            Ok(repo_root) => is_git_revision(value, &repo_root).await,
+            Err(_) => false,
+        }
+    }
+    async fn complete(&self, value: &String, context: &AtCommandsContext, top_n: usize) -> Vec<String> {
+        let repo_root = match git_repo_root(context, None).await {
+            Ok(x) => x,
+            Err(_) => return vec![],
+        };
+        let mut candidates: Vec<String> = vec![DIFF_STAGED_ARG.to_string()];
+        if let Ok(branches) = run_git(&["for-each-ref", "--format=%(refname:short)", "refs/heads", "refs/remotes"], &repo_root).await {
+            candidates.extend(branches.lines().map(|x| x.trim().to_string()).filter(|x| !x.is_empty()));
+        }
+        let n_commits = format!("-n{}", DIFF_COMPLETION_RECENT_COMMITS);
+        if let Ok(commits) = run_git(&["log", n_commits.as_str(), "--format=%h"], &repo_root).await {
+            candidates.extend(commits.lines().map(|x| x.trim().to_string()).filter(|x| !x.is_empty()));
+        }
+        let value_lower = value.to_lowercase();
+        let mut result: Vec<String> = vec![];
+        for c in candidates.into_iter() {
+            if c.to_lowercase().contains(&value_lower) && !result.contains(&c) {
+                result.push(c);
+            }
+        }
+        // exact prefix matches first, the rest keeps git order: branches, then the most recent commits
+        result.sort_by_key(|x| !x.to_lowercase().starts_with(&value_lower));
+        result.into_iter().take(top_n).collect()
+    }
+    fn complete_if_valid(&self) -> bool {
+        true
+    }
+}
+
+
+pub struct AtDiff {
+    pub name: String,
+    pub params: Vec<Arc<AMutex<dyn AtParam>>>,
+}
+
+impl AtDiff {
+    pub fn new() -> Self {
+        AtDiff {
+            name: "@diff".to_string(),
+            params: vec![
+                Arc::new(AMutex::new(AtParamGitRevision::new())),
+                Arc::new(AMutex::new(AtParamFilePath::new())),
+            ],
+        }
+    }
+}
+
+#[async_trait]
+impl AtCommand for AtDiff {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>> {
+        &self.params
+    }
+    async fn can_execute(&self, args: &Vec<String>, context: &AtCommandsContext) -> bool {
+        // "@diff" alone is fine (uncommitted changes), the second argument can only be a path
+        if args.len() > 2 {
+            return false;
+        }
+        if let Some(arg) = args.get(0) {
+            if !self.params[0].lock().await.is_value_valid(arg, context).await {
+                return false;
+            }
+        }
+        if let Some(arg) = args.get(1) {
+            if resolve_path(arg, context).await.is_none() {
+                return false;
+            }
+        }
+        true
+    }
+    async fn execute(&self, _query: &String, args: &Vec<String>, _top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
+        // @diff                  -- staged and unstaged changes against HEAD
+        // @diff --staged         -- staged changes only
+        // @diff <rev> [path]     -- everything since the fork point with <rev>, plus commit messages
+        // @diff <path>           -- uncommitted changes in one file or directory
+        let mut revision: Option<String> = None;
+        let mut staged = false;
+        let mut path: Option<PathBuf> = None;
+        for arg in args.iter().take(2) {
+            if arg == DIFF_STAGED_ARG {
+                staged = true;
+            } else if let Some(p) = resolve_path(arg, context).await {
+                path = Some(p);
+            } else if revision.is_none() && path.is_none() {
+                revision = Some(arg.clone());
+            } else {
+                break;
+            }
+        }
+        let repo_root = git_repo_root(context, path.as_ref()).await?;
+        let path_str = path.as_ref().map(|x| x.to_string_lossy().to_string());
+
+        let mut base: Option<String> = None;
+        if let Some(rev) = &revision {
+            if !is_git_revision(rev, &repo_root).await {
+                return Err(format!("{:?} is not a git revision or a path", rev));
+            }
+            // for a branch, compare with the point where the current branch forked from it
+            base = match run_git(&["merge-base", rev.as_str(), "HEAD"], &repo_root).await {
+                Ok(x) => Some(x.trim().to_string()),
+                Err(_) => Some(rev.clone()),
+            };
+        }
+        info!("execute @diff revision={:?} base={:?} staged={} path={:?}", revision, base, staged, path_str);
+
+        let mut diff_args: Vec<&str> = vec!["-c", "core.quotepath=false", "diff", "--no-color", "--no-ext-diff"];
+        if staged {
+            diff_args.push("--cached");
+        }
+        let head = "HEAD".to_string();
+        let diff_against = match (&base, staged) {
+            (Some(b), _) => Some(b),
+            (None, false) => Some(&head),
+            (None, true) => None,
+        };
+        if let Some(b) = diff_against {
+            diff_args.push(b.as_str());
+        }
+        diff_args.push("--");
+        if let Some(p) = &path_str {
+            diff_args.push(p.as_str());
+        }
+        let diff = match run_git(&diff_args, &repo_root).await {
+            Ok(x) => x,
+            Err(e) if base.is_none() => {
+                // no commits yet, HEAD doesn't exist: show what's in the index
+                info!("{}, trying without HEAD", e);
+                let mut fallback_args: Vec<&str> = vec!["-c", "core.quotepath=false", "diff", "--no-color", "--no-ext-diff", "--cached", "--"];
+                fallback_args.extend(path_str.iter().map(|x| x.as_str()));
+                run_git(&fallback_args, &repo_root).await?
+            }
+            Err(e) => return Err(e),
+        };
+        let hunks = parse_unified_diff(&diff, &repo_root);
+
+        let mut commits = vec![];
+        if let Some(b) = &base {
+            let range = format!("{}..HEAD", b);
+            let n_commits = format!("-n{}", DIFF_MAX_COMMITS);
+            let mut log_args: Vec<&str> = vec!["log", n_commits.as_str(), "--date=short", "--format=%h%x1fAuthor: %an, %ad%x1f%x1f%B%x1e", range.as_str(), "--"];
+            log_args.extend(path_str.iter().map(|x| x.as_str()));
+            commits = parse_git_log(&run_git(&log_args, &repo_root).await?);
+        }
+        if hunks.is_empty() && commits.is_empty() {
+            return Err("no changes found".to_string());
+        }
+        Ok(results2message(hunks, commits, &repo_root))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_unified_diff() {
+        let diff = "\
+diff --git a/src/main.rs b/src/main.rs
+index 3b18e51..a9c2f3e 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,3 +1,4 @@
+ fn main() {
++    println!(\"hello\");
+     let x = 1;
+ }
+@@ -20,2 +21,0 @@ fn other() {
+-    dead();
+-    code();
+diff --git a/old.txt b/old.txt
+deleted file mode 100644
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-bye
+";
+        let hunks = parse_unified_diff(diff, &PathBuf::from("/repo"));
+        assert_eq!(hunks.len(), 3);
+        assert_eq!(hunks[0].file_name, "/repo/src/main.rs");
+        assert_eq!((hunks[0].line1, hunks[0].line2), (1, 4));
+        assert_eq!(hunks[0].text.lines().count(), 5);
+        assert_eq!((hunks[1].line1, hunks[1].line2), (21, 21));
+        assert!(hunks[1].text.ends_with("-    code();\n"));
+        assert_eq!(hunks[2].file_name, "/repo/old.txt");
+        assert_eq!((hunks[2].line1, hunks[2].line2), (1, 1));
+    }
+
+    #[test]
+    fn test_parse_git_log() {
+        let log = "abc123\x1fAuthor: Jane, 2024-01-02\x1f\x1fFix parser\n\nLonger text\n\x1e\ndef456\x1fAuthor: Joe, 2024-01-01\x1f\x1fInit\n\x1e\n";
+        let commits = parse_git_log(log);
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].0, "abc123");
+        assert_eq!(commits[0].1, "Author: Jane, 2024-01-02\n\nFix parser\n\nLonger text");
+        assert_eq!(commits[1].1, "Author: Joe, 2024-01-01\n\nInit");
+    }
+}
@@ -10,12 +10,18 @@ use strsim::normalized_damerau_levenshtein;
 use url::Url;
 
 use crate::at_commands::at_commands::{AtCommand, AtCommandsContext, AtParam};
-use crate::at_commands::utils::split_file_into_chunks_from_line_inside;
+use crate::at_commands::utils::{glob_to_regex, split_file_into_chunks_from_line_inside};
 use crate::files_in_jsonl::files_in_jsonl;
 use crate::files_in_workspace::get_file_text_from_memory_or_disk;
 use crate::call_validation::{ChatMessage, ContextFile};
 use crate::global_context::GlobalContext;
 
+const FILE_MAX_ARGS: usize = 10;
+const FILE_GLOB_MAX_FILES: usize = 50;
+// files matched by a glob are less relevant than files named explicitly, and the biggest of them go first when trimming
+const FILE_GLOB_USEFULNESS_MAX: f32 = 0.6;
+const FILE_GLOB_USEFULNESS_MIN: f32 = 0.3;
+
 
 pub struct AtFile {
     pub name: String,
@@ -27,7 +33,7 @@ impl AtFile {
         AtFile {
             name: "@file".to_string(),
             params: vec![
-                Arc::new(AMutex::new(AtParamFilePath::new()))
+                Arc::new(AMutex::new(AtParamFilePathOrGlob::new()))
             ],
         }
     }
@@ -146,6 +152,7 @@ fn chunks_into_context_file(
                 line1: *line1,
                 line2: *line2,
                 usefulness: *usefulness_above.get(idx).unwrap_or(&100.),
+                synthetic: false,
             }
         })
     }
@@ -159,14 +166,14 @@ fn chunks_into_context_file(
                 line1: *line1,
                 line2: *line2,
                 usefulness: *usefulness_below.get(idx).unwrap_or(&0.0),
-
+                synthetic: false,
             }
         })
     }
     vector_of_context_file
 }
 
-async fn files_cache_rebuild_as_needed(global_context: Arc<ARwLock<GlobalContext>>)
+pub async fn files_cache_rebuild_as_needed(global_context: Arc<ARwLock<GlobalContext>>)
 -> (Arc<HashMap<String, String>>, Arc<Vec<String>>)
 {
     let cache_dirty_arc: Arc<AMutex<bool>>;
@@ -238,7 +245,7 @@ fn put_colon_back_to_arg(value: &mut String, colon: &Option<ColonLinesRange>) {
     }
 }
 
-async fn parameter_repair_candidates(
+pub async fn parameter_repair_candidates(
     value: &String,
     context: &AtCommandsContext,
     top_n: usize
@@ -264,7 +271,7 @@ async fn parameter_repair_candidates(
     for p in cache_fuzzy_arc.iter() {
         let dist = normalized_damerau_levenshtein(&correction_candidate, p);
         top_n_records.push((p.clone(), dist));
-        if top_n_records.len() >= top_n {
+        if top_n_records.len() > top_n {
             top_n_records.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
             top_n_records.pop();
         }
@@ -323,6 +330,149 @@ impl AtParam for AtParamFilePath {
     }
 }
 
+pub fn is_glob(value: &String) -> bool {
+    value.contains(|c| c == '*' || c == '?')
+}
+
+pub async fn glob_expand(value: &String, context: &AtCommandsContext) -> Vec<String> {
+    let re = match glob_to_regex(value) {
+        Ok(x) => x,
+        Err(_) => return vec![],
+    };
+    let (cache_correction_arc, _cache_fuzzy_arc) = files_cache_rebuild_as_needed(context.global_context.clone()).await;
+    // values are full paths, the same path is there many times under different keys
+    let mut result: Vec<String> = (*cache_correction_arc).values()
+        .filter(|x| re.is_match(x))
+        .cloned()
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect();
+    result.sort();
+    result
+}
+
+#[derive(Debug)]
+pub struct AtParamFilePathOrGlob {
+    pub name: String,
+    file_path: AtParamFilePath,
+}
+
+impl AtParamFilePathOrGlob {
+    pub fn new() -> Self {
+        Self {
+            name: "file_path_or_glob".to_string(),
+            file_path: AtParamFilePath::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl AtParam for AtParamFilePathOrGlob {
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    async fn is_value_valid(&self, value: &String, context: &AtCommandsContext) -> bool {
+        if is_glob(value) {
+            return !glob_expand(value, context).await.is_empty();
+        }
+        self.file_path.is_value_valid(value, context).await
+    }
+
+    async fn complete(&self, value: &String, context: &AtCommandsContext, top_n: usize) -> Vec<String> {
+        if is_glob(value) {
+            // the glob itself, then what it expands to
+            let expanded = glob_expand(value, context).await;
+            if expanded.is_empty() {
+                return vec![];
+            }
+            return std::iter::once(value.clone()).chain(expanded.into_iter()).take(top_n).collect();
+        }
+        self.file_path.complete(value, context, top_n).await
+    }
+
+    fn complete_if_valid(&self) -> bool {
+        true
+    }
+}
+
+async fn execute_one_file(file_path: &String, context: &AtCommandsContext) -> Result<Vec<ContextFile>, String> {
+    let mut file_path = file_path.clone();
+    let mut split_into_chunks = false;
+    let mut cursor = 0;
+    let mut line1 = 0;
+    let mut line2 = 0;
+
+    let colon = match colon_lines_range_from_arg(&mut file_path) {
+        Some(x) => {
+            info!("@file range: {:?}", x);
+            if x.kind == RangeKind::GradToCursorTwosided {
+                split_into_chunks = true;
+                cursor = x.line1;
+            }
+            if x.kind == RangeKind::GradToCursorPrefix {
+                split_into_chunks = true;
+                cursor = x.line2;
+            }
+            if x.kind == RangeKind::GradToCursorSuffix {
+                split_into_chunks = true;
+                cursor = x.line1;
+            }
+            if x.kind == RangeKind::Range {
+                line1 = x.line1;
+                line2 = x.line2;
+            }
+            x
+        },
+        None => {
+            split_into_chunks = true;
+            cursor = 0;
+            ColonLinesRange { kind: RangeKind::GradToCursorSuffix, line1: 0, line2: 0 }  // not used if split_into_chunks is true
+        }
+    };
+    info!("@file {:?} execute range {:?}", file_path, colon);
+
+    let mut file_text = get_file_text_from_memory_or_disk(context.global_context.clone(), &file_path).await?;
+    let mut file_lines: Vec<String> = file_text.lines().map(String::from).collect();
+    let lines_cnt = file_lines.len();
+
+    if split_into_chunks {
+        cursor = cursor.max(0).min(lines_cnt);
+        let (mut res_above, mut res_below) = split_file_into_chunks_from_line_inside(cursor, &mut file_lines, 20);
+        info!("split_into_chunks cursor: {} <= {}", cursor, lines_cnt);
+        if colon.kind == RangeKind::GradToCursorPrefix {
+            res_below.clear();
+        }
+        if colon.kind == RangeKind::GradToCursorSuffix {
+            res_above.clear();
+        }
+        for ((line1, line2), _text) in res_above.iter() {
+            info!("above: {}-{}", line1, line2);
+        }
+        for ((line1, line2), _text) in res_below.iter() {
+            info!("below: {}-{}", line1, line2);
+        }
+        return Ok(chunks_into_context_file(res_above, res_below, &file_path));
+    }
+
+    if line1 == 0 || line2 == 0 {
+        return Err(format!("{} incorrect range: {}-{}", file_path, colon.line1, colon.line2));
+    }
+    line1 = (line1 - 1).max(0).min(lines_cnt);
+    line2 = line2.max(0).min(lines_cnt);
+    let lines: Vec<&str> = file_text.lines().collect();
+    file_text = lines[line1 .. line2].join("\n");
+
+    Ok(vec![ContextFile {
+        file_name: file_path.clone(),
+        file_content: file_text,
+        line1: line1 + 1,
+        line2: line2,
+        usefulness: 100.0,
+        synthetic: false,
+    }])
+}
+
 #[async_trait]
 impl AtCommand for AtFile {
     fn name(&self) -> &String {
@@ -331,18 +481,21 @@ impl AtCommand for AtFile {
     fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>> {
         &self.params
     }
+    fn max_args(&self) -> usize {
+        FILE_MAX_ARGS  // every path goes through the same param
+    }
 
-    async fn can_execute(&self, args: &Vec<String>, _context: &AtCommandsContext) -> bool {
-        args.len() == 1
-        // let param = self.params.get(0).unwrap();
-        // if let Some(arg) = args.get(0) {
-        //     let mut arg_clone = arg.clone();
-        //     colon_lines_range_from_arg(&mut arg_clone);
-        //     if param.lock().await.is_value_valid(&arg_clone, context).await { // FIXME: is_value_valid is @file-specific, move here
-        //         return true;
-        //     }
-        // }
-        // false
+    async fn can_execute(&self, args: &Vec<String>, context: &AtCommandsContext) -> bool {
+        if args.is_empty() || args.len() > self.max_args() {
+            return false;
+        }
+        let param = self.params[0].lock().await;
+        for arg in args.iter() {
+            if !param.is_value_valid(arg, context).await {
+                return false;
+            }
+        }
+        true
     }
 
     async fn execute(&self, _query: &String, args: &Vec<String>, top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
@@ -350,90 +503,62 @@ impl AtCommand for AtFile {
         if !can_execute {
             return Err("incorrect arguments".to_string());
         }
-        let correctable_file_path = args[0].clone();
-        let candidates = parameter_repair_candidates(&correctable_file_path, context, top_n).await;
-        if candidates.len() == 0 {
-            info!("parameter {:?} is uncorrectable :/", &correctable_file_path);
-            return Err(format!("parameter {:?} is uncorrectable :/", &correctable_file_path));
-        }
-        let mut file_path = candidates[0].clone();
-
-        let mut split_into_chunks = false;
-        let mut cursor = 0;
-        let mut line1 = 0;
-        let mut line2 = 0;
-
-        let colon = match colon_lines_range_from_arg(&mut file_path) {
-            Some(x) => {
-                info!("@file range: {:?}", x);
-                if x.kind == RangeKind::GradToCursorTwosided {
-                    split_into_chunks = true;
-                    cursor = x.line1;
-                }
-                if x.kind == RangeKind::GradToCursorPrefix {
-                    split_into_chunks = true;
-                    cursor = x.line2;
-                }
-                if x.kind == RangeKind::GradToCursorSuffix {
-                    split_into_chunks = true;
-                    cursor = x.line1;
-                }
-                if x.kind == RangeKind::Range {
-                    line1 = x.line1;
-                    line2 = x.line2;
-                }
-                x
-            },
-            None => {
-                split_into_chunks = true;
-                cursor = 0;
-                ColonLinesRange { kind: RangeKind::GradToCursorSuffix, line1: 0, line2: 0 }  // not used if split_into_chunks is true
+        let mut vector_of_context_file: Vec<ContextFile> = vec![];
+        let mut explicit_paths: Vec<String> = vec![];
+        let mut glob_paths: Vec<String> = vec![];
+        for correctable_file_path in args.iter() {
+            if is_glob(correctable_file_path) {
+                glob_paths.extend(glob_expand(correctable_file_path, context).await);
+                continue;
             }
-        };
-        info!("@file {:?} execute range {:?}", file_path, colon);
-
-        let mut file_text = get_file_text_from_memory_or_disk(context.global_context.clone(), &file_path).await?;
-        let mut file_lines: Vec<String> = file_text.lines().map(String::from).collect();
-        let lines_cnt = file_lines.len();
-
-        if split_into_chunks {
-            cursor = cursor.max(0).min(lines_cnt);
-            let (mut res_above, mut res_below) = split_file_into_chunks_from_line_inside(cursor, &mut file_lines, 20);
-            info!("split_into_chunks cursor: {} <= {}", cursor, lines_cnt);
-            if colon.kind == RangeKind::GradToCursorPrefix {
-                res_below.clear();
+            let candidates = parameter_repair_candidates(correctable_file_path, context, top_n).await;
+            if candidates.len() == 0 {
+                info!("parameter {:?} is uncorrectable :/", correctable_file_path);
+                continue;
             }
-            if colon.kind == RangeKind::GradToCursorSuffix {
-                res_above.clear();
+            let file_path = candidates[0].clone();
+            match execute_one_file(&file_path, context).await {
+                Ok(x) => vector_of_context_file.extend(x),
+                Err(e) => {
+                    info!("@file {:?} failed: {}", file_path, e);
+                    if args.len() == 1 {
+                        return Err(e);
+                    }
+                }
             }
-            for ((line1, line2), _text) in res_above.iter() {
-                info!("above: {}-{}", line1, line2);
+            let mut without_colon = file_path.clone();
+            colon_lines_range_from_arg(&mut without_colon);
+            explicit_paths.push(without_colon);
+        }
+
+        glob_paths.sort();
+        glob_paths.dedup();
+        glob_paths.retain(|x| !explicit_paths.contains(x));
+        if glob_paths.len() > FILE_GLOB_MAX_FILES {
+            info!("@file glob matches {} files, taking {}", glob_paths.len(), FILE_GLOB_MAX_FILES);
+            glob_paths.truncate(FILE_GLOB_MAX_FILES);
+        }
+        let mut glob_results: Vec<(usize, Vec<ContextFile>)> = vec![];
+        for file_path in glob_paths.iter() {
+            match execute_one_file(file_path, context).await {
+                Ok(x) => glob_results.push((x.iter().map(|c| c.file_content.len()).sum(), x)),
+                Err(e) => info!("@file {:?} failed: {}", file_path, e),
             }
-            for ((line1, line2), _text) in res_below.iter() {
-                info!("below: {}-{}", line1, line2);
+        }
+        // smaller files are more useful: more of them fit, postprocess_at_results drops the big ones first
+        glob_results.sort_by_key(|(size, _)| *size);
+        let glob_files_cnt = glob_results.len();
+        for (rank, (_, context_files)) in glob_results.into_iter().enumerate() {
+            let factor = FILE_GLOB_USEFULNESS_MAX - (FILE_GLOB_USEFULNESS_MAX - FILE_GLOB_USEFULNESS_MIN) * (rank as f32) / (glob_files_cnt as f32);
+            for mut cf in context_files.into_iter() {
+                cf.usefulness *= factor;
+                vector_of_context_file.push(cf);
             }
-            return Ok(ChatMessage {
-                role: "context_file".to_string(),
-                content: json!(chunks_into_context_file(res_above, res_below, &file_path)).to_string(),
-            })
         }
 
-        if line1 == 0 || line2 == 0 {
-            return Err(format!("{} incorrect range: {}-{}", file_path, colon.line1, colon.line2));
+        if vector_of_context_file.is_empty() {
+            return Err(format!("nothing found for {:?}", args));
         }
-        line1 = (line1 - 1).max(0).min(lines_cnt);
-        line2 = line2.max(0).min(lines_cnt);
-        let lines: Vec<&str> = file_text.lines().collect();
-        file_text = lines[line1 .. line2].join("\n");
-
-        let mut vector_of_context_file: Vec<ContextFile> = vec![];
-        vector_of_context_file.push(ContextFile {
-            file_name: file_path.clone(),
-            file_content: file_text,
-            line1: line1 + 1,
-            line2: line2,
-            usefulness: 100.0,
-        });
         Ok(ChatMessage {
             role: "context_file".to_string(),
             content: json!(vector_of_context_file).to_string(),
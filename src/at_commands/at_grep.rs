@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde_json::json;
+use tokio::sync::Mutex as AMutex;
+use tracing::info;
+use url::Url;
+
+use crate::at_commands::at_commands::{AtCommand, AtCommandsContext, AtParam};
+use crate::at_commands::utils::glob_to_regex;
+use crate::call_validation::{ChatMessage, ContextFile};
+use crate::files_in_workspace::{Document, is_this_inside_blacklisted_dir};
+
+const GREP_CONTEXT_LINES: usize = 3;
+const GREP_MAX_MATCHES: usize = 50;
+const GREP_MAX_FILE_SIZE: u64 = 1_000_000;  // bytes, bigger files are most likely generated
+const GREP_MAX_USEFULNESS: f32 = 80.0;
+
+
+fn looks_like_glob(arg: &String) -> bool {
+    // the word after the regex might be the question, only take obvious globs
+    arg.contains(|c| c == '*' || c == '?' || c == '/' || c == '\\')
+}
+
+fn match_windows(text: &str, re: &Regex, max_matches: usize) -> (Vec<(usize, usize)>, usize) {
+    // returns merged 1-based (line1, line2) windows around matching lines, and the number of matching lines
+    let lines: Vec<&str> = text.lines().collect();
+    let mut windows: Vec<(usize, usize)> = vec![];
+    let mut matches_cnt = 0;
+    for (idx, line) in lines.iter().enumerate() {
+        if matches_cnt >= max_matches {
+            break;
+        }
+        if !re.is_match(line) {
+            continue;
+        }
+        matches_cnt += 1;
+        let line1 = idx.saturating_sub(GREP_CONTEXT_LINES) + 1;
+        let line2 = (idx + GREP_CONTEXT_LINES + 1).min(lines.len());
+        match windows.last_mut() {
+            Some(last) if line1 <= last.1 + 1 => last.1 = line2,
+            _ => windows.push((line1, line2)),
+        }
+    }
+    (windows, matches_cnt)
+}
+
+fn results2message(results: &Vec<(String, Vec<String>, Vec<(usize, usize)>)>, matches_cnt: usize) -> ChatMessage {
+    // the more matches, the less each of them tells about the question
+    let usefulness = GREP_MAX_USEFULNESS / (matches_cnt.max(1) as f32).sqrt();
+    let mut vector_of_context_file: Vec<ContextFile> = vec![];
+    for (file_name, lines, windows) in results.iter() {
+        for (line1, line2) in windows.iter() {
+            vector_of_context_file.push(ContextFile {
+                file_name: file_name.clone(),
+                file_content: lines[line1 - 1 .. *line2].join("\n") + "\n",
+                line1: *line1,
+                line2: *line2,
+                usefulness,
+                synthetic: false,
+            });
+        }
+    }
+    ChatMessage {
+        role: "context_file".to_string(),
+        content: json!(vector_of_context_file).to_string(),
+    }
+}
+
+
+#[derive(Debug)]
+pub struct AtParamRegex {
+    pub name: String,
+}
+
+impl AtParamRegex {
+    pub fn new() -> Self {
+        Self {
+            name: "regex".to_string()
+        }
+    }
+}
+
+#[async_trait]
+impl AtParam for AtParamRegex {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    async fn is_value_valid(&self, value: &String, _: &AtCommandsContext) -> bool {
+        Regex::new(value).is_ok()
+    }
+    async fn complete(&self, _value: &String, _context: &AtCommandsContext, _top_n: usize) -> Vec<String> {
+        vec![]
+    }
+}
+
+
+#[derive(Debug)]
+pub struct AtParamGlob {
+    pub name: String,
+}
+
+impl AtParamGlob {
+    pub fn new() -> Self {
+        Self {
+            name: "glob".to_string()
+        }
+    }
+}
+
+#[async_trait]
+impl AtParam for AtParamGlob {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    async fn is_value_valid(&self, value: &String, _: &AtCommandsContext) -> bool {
+        looks_like_glob(value) && glob_to_regex(value).is_ok()
+    }
+    async fn complete(&self, _value: &String, _context: &AtCommandsContext, _top_n: usize) -> Vec<String> {
+        vec![]
+    }
+}
+
+
+pub struct AtGrep {
+    pub name: String,
+    pub params: Vec<Arc<AMutex<dyn AtParam>>>,
+}
+
+impl AtGrep {
+    pub fn new() -> Self {
+        AtGrep {
+            name: "@grep".to_string(),
+            params: vec![
+                Arc::new(AMutex::new(AtParamRegex::new())),
+                Arc::new(AMutex::new(AtParamGlob::new())),
+            ],
+        }
+    }
+}
+
+#[async_trait]
+impl AtCommand for AtGrep {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>> {
+        &self.params
+    }
+    async fn can_execute(&self, args: &Vec<String>, context: &AtCommandsContext) -> bool {
+        if args.is_empty() || args.len() > self.params.len() {
+            return false;
+        }
+        for (arg, param) in args.iter().zip(self.params.iter()) {
+            if !param.lock().await.is_value_valid(arg, context).await {
+                return false;
+            }
+        }
+        true
+    }
+    async fn execute(&self, _query: &String, args: &Vec<String>, _top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
+        let re = match args.get(0) {
+            Some(x) => Regex::new(x).map_err(|e| format!("bad regex: {}", e))?,
+            None => return Err("no regex given".to_string()),
+        };
+        let glob_mb = match args.get(1) {
+            Some(x) => Some(glob_to_regex(x)?),
+            None => None,
+        };
+        info!("execute @grep {:?} glob {:?}", re.as_str(), args.get(1));
+
+        // files open in the IDE might be unsaved, search what the user sees
+        let (workspace_files, memory_docs): (Vec<Url>, HashMap<Url, Document>) = {
+            let gcx_locked = context.global_context.read().await;
+            let workspace_files = gcx_locked.documents_state.workspace_files.lock().unwrap().clone();
+            let memory_docs = gcx_locked.documents_state.document_map.read().await.clone();
+            (workspace_files, memory_docs)
+        };
+        let mut urls: Vec<Url> = memory_docs.keys().cloned().collect();
+        urls.sort();
+        urls.extend(workspace_files.into_iter().filter(|x| !memory_docs.contains_key(x)));
+
+        let mut results: Vec<(String, Vec<String>, Vec<(usize, usize)>)> = vec![];
+        let mut matches_total = 0;
+        for url in urls.iter() {
+            if matches_total >= GREP_MAX_MATCHES {
+                info!("@grep stops at {} matches", matches_total);
+                break;
+            }
+            let path: PathBuf = match url.to_file_path() {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            if is_this_inside_blacklisted_dir(&path) {
+                continue;
+            }
+            let file_name = path.to_string_lossy().to_string();
+            if let Some(glob) = &glob_mb {
+                if !glob.is_match(&file_name) {
+                    continue;
+                }
+            }
+            let text = match memory_docs.get(url) {
+                Some(doc) => doc.text.to_string(),
+                None => {
+                    match tokio::fs::metadata(&path).await {
+                        Ok(meta) if meta.len() <= GREP_MAX_FILE_SIZE => {},
+                        _ => continue,
+                    }
+                    match tokio::fs::read_to_string(&path).await {
+                        Ok(x) => x,
+                        Err(_) => continue,  // binary or gone
+                    }
+                }
+            };
+            let (windows, matches_cnt) = match_windows(&text, &re, GREP_MAX_MATCHES - matches_total);
+            if windows.is_empty() {
+                continue;
+            }
+            matches_total += matches_cnt;
+            results.push((file_name, text.lines().map(String::from).collect(), windows));
+        }
+        if results.is_empty() {
+            return Err(format!("no matches for {:?}", re.as_str()));
+        }
+        info!("@grep found {} matches in {} files", matches_total, results.len());
+        Ok(results2message(&results, matches_total))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_windows() {
+        let text = (1..=30).map(|x| format!("line {}", x)).collect::<Vec<_>>().join("\n");
+        let re = Regex::new(r"^line (2|4|20)$").unwrap();
+        let (windows, cnt) = match_windows(&text, &re, 10);
+        assert_eq!(cnt, 3);
+        assert_eq!(windows, vec![(1, 7), (17, 23)]);
+        let (windows, cnt) = match_windows(&text, &re, 1);
+        assert_eq!(cnt, 1);
+        assert_eq!(windows, vec![(1, 5)]);
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        let re = glob_to_regex("*.rs").unwrap();
+        assert!(re.is_match("/home/user/proj/src/main.rs"));
+        assert!(!re.is_match("/home/user/proj/src/main.rs.bak"));
+        let re = glob_to_regex("src/**/*.rs").unwrap();
+        assert!(re.is_match("/home/user/proj/src/main.rs"));
+        assert!(re.is_match("/home/user/proj/src/a/b/lib.rs"));
+        assert!(!re.is_match("/home/user/proj/tests/main.rs"));
+        let re = glob_to_regex("config?.yaml").unwrap();
+        assert!(re.is_match("C:\\proj\\config1.yaml"));
+        assert!(!re.is_match("/proj/config12.yaml"));
+    }
+}
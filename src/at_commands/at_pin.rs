@@ -0,0 +1,259 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::Mutex as AMutex;
+use tokio::sync::RwLock as ARwLock;
+use tracing::info;
+
+use crate::at_commands::at_commands::{AtCommand, AtCommandsContext, AtParam};
+use crate::at_commands::at_file::{colon_lines_range_from_arg, files_cache_rebuild_as_needed, RangeKind};
+use crate::at_commands::at_params::AtParamSymbolPathQuery;
+use crate::call_validation::{ChatMessage, ContextFile};
+use crate::files_in_workspace::get_file_text_from_memory_or_disk;
+use crate::global_context::GlobalContext;
+
+pub const PIN_USEFULNESS: f32 = 100.0;  // pins go before anything an at-command finds
+const PIN_NOTE_MAX_WORDS: usize = 100;
+
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ContextPin {
+    #[serde(default)]
+    pub file_name: String,
+    #[serde(default)]
+    pub line1: usize,  // line1 == line2 == 0 pins the whole file
+    #[serde(default)]
+    pub line2: usize,
+    #[serde(default)]
+    pub symbol: String,
+    #[serde(default)]
+    pub note: String,
+}
+
+pub async fn pin_from_arg(arg: &String, context: &AtCommandsContext) -> ContextPin {
+    // a file (with an optional :line1-line2), a symbol known to AST, anything else is a note
+    let mut pin = ContextPin::default();
+    let mut file_path = arg.clone();
+    let colon_mb = colon_lines_range_from_arg(&mut file_path);
+    let (cache_correction_arc, _) = files_cache_rebuild_as_needed(context.global_context.clone()).await;
+    if let Some(fixed) = cache_correction_arc.get(&file_path) {
+        pin.file_name = fixed.clone();
+        if let Some(colon) = colon_mb {
+            if colon.kind == RangeKind::Range {
+                pin.line1 = colon.line1;
+                pin.line2 = colon.line2;
+            }
+        }
+        return pin;
+    }
+    let ast_module = context.global_context.read().await.ast_module.clone();
+    let is_symbol = match *ast_module.lock().await {
+        Some(ref ast) => !ast.get_declarations_by_symbol(arg.clone()).await.is_empty(),
+        None => false,
+    };
+    if is_symbol {
+        pin.symbol = arg.clone();
+    } else {
+        pin.note = arg.clone();
+    }
+    pin
+}
+
+pub async fn resolve_pins(global_context: Arc<ARwLock<GlobalContext>>, pins: &Vec<ContextPin>) -> Vec<ContextFile> {
+    // fresh text every time, files change between the turns
+    let mut result: Vec<ContextFile> = vec![];
+    for pin in pins.iter() {
+        if !pin.file_name.is_empty() {
+            let text = match get_file_text_from_memory_or_disk(global_context.clone(), &pin.file_name).await {
+                Ok(x) => x,
+                Err(e) => {
+                    info!("pinned file {} is not available: {}", pin.file_name, e);
+                    continue;
+                }
+            };
+            let lines_cnt = text.lines().count();
+            let (line1, line2) = if pin.line1 == 0 && pin.line2 == 0 {
+                (1, lines_cnt)
+            } else {
+                (pin.line1.max(1), pin.line2.min(lines_cnt))
+            };
+            if line1 > line2 {
+                continue;
+            }
+            result.push(ContextFile {
+                file_name: pin.file_name.clone(),
+                file_content: text.lines().skip(line1 - 1).take(line2 - line1 + 1).collect::<Vec<&str>>().join("\n") + "\n",
+                line1,
+                line2,
+                usefulness: PIN_USEFULNESS,
+                synthetic: false,
+            });
+        } else if !pin.symbol.is_empty() {
+            let ast_module = global_context.read().await.ast_module.clone();
+            let declarations = match *ast_module.lock().await {
+                Some(ref ast) => ast.get_declarations_by_symbol(pin.symbol.clone()).await,
+                None => vec![],
+            };
+            for decl in declarations.iter() {
+                result.push(ContextFile {
+                    file_name: decl.definition_info.path.to_string_lossy().to_string(),
+                    file_content: decl.get_content().await.unwrap_or_default(),
+                    line1: decl.definition_info.range.start_point.row + 1,
+                    line2: decl.definition_info.range.end_point.row + 1,
+                    usefulness: PIN_USEFULNESS,
+                    synthetic: false,
+                });
+            }
+        } else if !pin.note.is_empty() {
+            result.push(ContextFile {
+                file_name: "pinned note".to_string(),
+                file_content: pin.note.clone() + "\n",
+                line1: 1,
+                line2: 1,
+                usefulness: PIN_USEFULNESS,
+                synthetic: true,
+            });
+        }
+    }
+    result
+}
+
+pub fn pins_tokens_limit(reserve_for_context: usize, pins_budget_share: f32) -> usize {
+    // pins can't take the whole context, the at-commands of the new messages need room too
+    (reserve_for_context as f32 * pins_budget_share.clamp(0.0, 1.0)) as usize
+}
+
+pub async fn add_pin(global_context: Arc<ARwLock<GlobalContext>>, chat_id: &String, pin: ContextPin) -> Vec<ContextPin> {
+    let pins_arc = global_context.read().await.context_pins.clone();
+    let mut pins_locked = pins_arc.lock().await;
+    let pins = pins_locked.entry(chat_id.clone()).or_default();
+    if !pins.contains(&pin) {
+        pins.push(pin);
+    }
+    pins.clone()
+}
+
+
+pub struct AtPin {
+    pub name: String,
+    pub params: Vec<Arc<AMutex<dyn AtParam>>>,
+}
+
+impl AtPin {
+    pub fn new() -> Self {
+        AtPin {
+            name: "@pin".to_string(),
+            params: vec![
+                Arc::new(AMutex::new(AtParamSymbolPathQuery::new()))
+            ],
+        }
+    }
+}
+
+#[async_trait]
+impl AtCommand for AtPin {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>> {
+        &self.params
+    }
+    fn max_args(&self) -> usize {
+        PIN_NOTE_MAX_WORDS
+    }
+    async fn can_execute(&self, args: &Vec<String>, context: &AtCommandsContext) -> bool {
+        // several words are a note, unless the first word is a file or a symbol, those are pinned alone
+        match args.len() {
+            0 => false,
+            1 => !args[0].is_empty(),
+            _ => !pin_from_arg(&args[0], context).await.note.is_empty(),
+        }
+    }
+    async fn execute(&self, _query: &String, args: &Vec<String>, _top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
+        if context.chat_id.is_empty() && !context.dry_run {
+            // without a chat_id all the chats would share one list of pins
+            return Err("@pin needs a chat_id, pins are kept per chat".to_string());
+        }
+        let pin = match args.len() {
+            0 => return Err("nothing to pin".to_string()),
+            1 => pin_from_arg(&args[0], context).await,
+            _ => ContextPin { note: args.join(" "), ..Default::default() },
+        };
+        info!("execute @pin {:?} chat_id {:?} dry_run {}", pin, context.chat_id, context.dry_run);
+        let context_files = resolve_pins(context.global_context.clone(), &vec![pin.clone()]).await;
+        if !context.dry_run {
+            // the next turns get it from the pin list, this turn gets it from the result
+            add_pin(context.global_context.clone(), &context.chat_id, pin).await;
+        }
+        Ok(ChatMessage {
+            role: "context_file".to_string(),
+            content: json!(context_files).to_string(),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+    use crate::global_context::create_global_context_for_tests;
+
+    async fn context_with_file(tmp_dir: &tempfile::TempDir, file_name: &str, text: &str) -> (AtCommandsContext, String) {
+        let path = tmp_dir.path().join(file_name);
+        std::fs::write(&path, text).unwrap();
+        let gcx = create_global_context_for_tests(tmp_dir.path().join("cache")).await;
+        gcx.read().await.documents_state.workspace_files.lock().unwrap().push(Url::from_file_path(&path).unwrap());
+        *gcx.read().await.documents_state.cache_dirty.lock().await = true;
+        let mut context = AtCommandsContext::new(gcx).await;
+        context.chat_id = "chat1".to_string();
+        (context, path.to_string_lossy().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_pin_from_arg_and_resolve() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (context, path) = context_with_file(&tmp_dir, "pinned.py", "a = 1\nb = 2\nc = 3\n").await;
+
+        let whole = pin_from_arg(&"pinned.py".to_string(), &context).await;
+        assert_eq!(whole, ContextPin { file_name: path.clone(), ..Default::default() });
+        let lines = pin_from_arg(&"pinned.py:2-5".to_string(), &context).await;
+        assert_eq!((lines.line1, lines.line2), (2, 5));
+        let note = pin_from_arg(&"snake_case".to_string(), &context).await;
+        assert_eq!(note, ContextPin { note: "snake_case".to_string(), ..Default::default() });
+
+        let files = resolve_pins(context.global_context.clone(), &vec![whole, lines, note]).await;
+        assert_eq!(files.len(), 3);
+        assert_eq!((files[0].line1, files[0].line2, files[0].file_content.as_str()), (1, 3, "a = 1\nb = 2\nc = 3\n"));
+        // the range is cut to the lines the file has
+        assert_eq!((files[1].line1, files[1].line2, files[1].file_content.as_str()), (2, 3, "b = 2\nc = 3\n"));
+        assert!(files[2].synthetic);
+        assert_eq!(files[2].file_content, "snake_case\n");
+    }
+
+    #[tokio::test]
+    async fn test_pin_note_and_chat_id() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (mut context, _) = context_with_file(&tmp_dir, "pinned.py", "a = 1\n").await;
+        let cmd = AtPin::new();
+        let words: Vec<String> = ["use", "snake_case", "everywhere"].iter().map(|x| x.to_string()).collect();
+        assert!(cmd.can_execute(&words, &context).await);
+        assert!(!cmd.can_execute(&vec!["pinned.py".to_string(), "explain".to_string()], &context).await);
+
+        cmd.execute(&"".to_string(), &words, 5, &context).await.unwrap();
+        let pins = context.global_context.read().await.context_pins.lock().await.get("chat1").cloned().unwrap();
+        assert_eq!(pins, vec![ContextPin { note: "use snake_case everywhere".to_string(), ..Default::default() }]);
+
+        context.chat_id = "".to_string();
+        assert!(cmd.execute(&"".to_string(), &words, 5, &context).await.is_err());
+    }
+
+    #[test]
+    fn test_pins_tokens_limit() {
+        assert_eq!(pins_tokens_limit(1000, 0.3), 300);
+        assert_eq!(pins_tokens_limit(1000, 2.0), 1000);
+        assert_eq!(pins_tokens_limit(1000, -1.0), 0);
+    }
+}
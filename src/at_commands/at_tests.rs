@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use regex::Regex;
+use structopt::lazy_static::lazy_static;
+use serde_json::json;
+use tokio::sync::Mutex as AMutex;
+use tracing::info;
+
+use crate::ast::treesitter::structs::SymbolDeclarationStruct;
+use crate::at_commands::at_commands::{AtCommand, AtCommandsContext, AtParam};
+use crate::at_commands::at_file::files_cache_rebuild_as_needed;
+use crate::at_commands::at_params::AtParamSymbolPathQuery;
+use crate::call_validation::{ChatMessage, ContextFile};
+use crate::files_in_workspace::{DocumentInfo, get_file_text_from_memory_or_disk};
+
+const TESTS_MAX_RESULTS: usize = 20;
+const TESTS_MAX_FILE_LINES: usize = 300;  // for test files found by name only, there's no AST to pick functions from
+const TESTS_USAGE_USEFULNESS: f32 = 80.0;
+const TESTS_MODULE_USEFULNESS: f32 = 70.0;
+const TESTS_BY_NAME_USEFULNESS: f32 = 50.0;
+
+lazy_static! {
+    static ref JS_SPEC_FILE: Regex = Regex::new(r"\.(spec|test)\.[jt]sx?$").unwrap();
+    // #[cfg(test)], maybe more attributes, then mod name {, a #[cfg(test)] helper or impl is not a test module
+    static ref RUST_TEST_MODULE: Regex = Regex::new(r"(?m)^[ \t]*#\[cfg\(test\)\]\s*(?:#\[[^\]]*\]\s*)*(?:pub(?:\([^)]*\))?\s+)?mod\s+\w+\s*\{").unwrap();
+}
+
+
+fn is_test_file(path: &PathBuf, workspace_folders: &Vec<PathBuf>) -> bool {
+    // tests/, test/ (src/test/java), __tests__/, test_x.py, x_test.go, x.spec.ts, XTest.java, tests.rs
+    // the directories count from the workspace folder, /home/test/project/src/main.rs is not a test
+    let relative = workspace_folders.iter()
+        .filter_map(|folder| path.strip_prefix(folder).ok())
+        .min_by_key(|x| x.components().count())
+        .unwrap_or(path.as_path());
+    let in_test_dir = relative.parent()
+        .map(|p| p.components().any(|c| {
+            let c = c.as_os_str().to_string_lossy();
+            c == "tests" || c == "test" || c == "__tests__"
+        }))
+        .unwrap_or(false);
+    if in_test_dir {
+        return true;
+    }
+    let file_name = path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    file_name.starts_with("test_")
+        || file_name == "tests.rs"
+        || JS_SPEC_FILE.is_match(&file_name)
+        || file_name.ends_with("Test.java")
+        || file_name.ends_with("Tests.java")
+        || ["_test.py", "_test.go", "_test.rs", "_tests.rs", "_test.cpp", "_test.cc", "_unittest.cc"].iter().any(|x| file_name.ends_with(x))
+}
+
+fn test_file_names_for(path: &PathBuf) -> Vec<String> {
+    // the conventional names of the tests for the file, they still need to pass is_test_file()
+    let stem = path.file_stem().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = path.extension().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    match ext.as_str() {
+        "py" => vec![format!("test_{}.py", stem), format!("{}_test.py", stem)],
+        "js" | "jsx" | "ts" | "tsx" => vec![format!("{}.spec.{}", stem, ext), format!("{}.test.{}", stem, ext)],
+        "java" => vec![format!("{}Test.java", stem), format!("{}Tests.java", stem)],
+        "go" => vec![format!("{}_test.go", stem)],
+        "rs" => vec![format!("{}.rs", stem), format!("{}_test.rs", stem), format!("{}_tests.rs", stem)],
+        "c" | "cc" | "cpp" | "h" | "hpp" => vec![
+            format!("{}_test.cpp", stem), format!("{}_test.cc", stem), format!("{}_unittest.cc", stem), format!("test_{}.cpp", stem),
+        ],
+        _ => vec![],
+    }
+}
+
+fn rust_test_module_start(text: &str) -> Option<usize> {
+    // 0-based row of #[cfg(test)], by convention the test module goes to the end of the file
+    RUST_TEST_MODULE.find(text).map(|m| text[..m.start()].matches('\n').count())
+}
+
+struct TestModules {
+    starts: HashMap<PathBuf, Option<usize>>,
+    workspace_folders: Vec<PathBuf>,
+}
+
+impl TestModules {
+    async fn start_of(&mut self, path: &PathBuf, context: &AtCommandsContext) -> Option<usize> {
+        if path.extension().map(|x| x != "rs").unwrap_or(true) {
+            return None;
+        }
+        if let Some(x) = self.starts.get(path) {
+            return *x;
+        }
+        let start = get_file_text_from_memory_or_disk(context.global_context.clone(), &path.to_string_lossy().to_string()).await
+            .ok()
+            .and_then(|text| rust_test_module_start(&text));
+        self.starts.insert(path.clone(), start);
+        start
+    }
+
+    async fn is_test_declaration(&mut self, decl: &SymbolDeclarationStruct, context: &AtCommandsContext) -> bool {
+        if is_test_file(&decl.definition_info.path, &self.workspace_folders) {
+            return true;
+        }
+        match self.start_of(&decl.definition_info.path, context).await {
+            Some(start) => decl.definition_info.range.start_point.row > start,
+            None => false,
+        }
+    }
+}
+
+async fn lines_of_file(path: &PathBuf, line1: usize, max_lines: usize, context: &AtCommandsContext) -> Option<(String, usize)> {
+    // returns the text from line1 (1-based) and the last line number
+    let text = get_file_text_from_memory_or_disk(context.global_context.clone(), &path.to_string_lossy().to_string()).await.ok()?;
+    let lines: Vec<&str> = text.lines().skip(line1 - 1).take(max_lines).collect();
+    if lines.is_empty() {
+        return None;
+    }
+    Some((lines.join("\n") + "\n", line1 + lines.len() - 1))
+}
+
+
+pub struct AtTests {
+    pub name: String,
+    pub params: Vec<Arc<AMutex<dyn AtParam>>>,
+}
+
+impl AtTests {
+    pub fn new() -> Self {
+        AtTests {
+            name: "@tests".to_string(),
+            params: vec![
+                Arc::new(AMutex::new(AtParamSymbolPathQuery::new()))
+            ],
+        }
+    }
+}
+
+#[async_trait]
+impl AtCommand for AtTests {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>> {
+        &self.params
+    }
+    async fn can_execute(&self, args: &Vec<String>, _context: &AtCommandsContext) -> bool {
+        args.len() == 1
+    }
+    async fn execute(&self, _query: &String, args: &Vec<String>, _top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
+        let arg = match args.get(0) {
+            Some(x) => x.clone(),
+            None => return Err("no symbol or file given".to_string()),
+        };
+        let (cache_correction_arc, _) = files_cache_rebuild_as_needed(context.global_context.clone()).await;
+        let ast_module = context.global_context.read().await.ast_module.clone();
+
+        // 1. what is tested: the symbol, or everything declared in the file
+        let target_file: Option<PathBuf> = cache_correction_arc.get(&arg).map(PathBuf::from);
+        let declarations: Vec<SymbolDeclarationStruct> = match *ast_module.lock().await {
+            Some(ref ast) => match &target_file {
+                Some(path) => match DocumentInfo::from_pathbuf(path) {
+                    Ok(doc) => ast.get_file_symbols(&doc).await.map(|x| x.symbols).unwrap_or_default(),
+                    Err(_) => vec![],
+                },
+                None => ast.get_declarations_by_symbol(arg.clone()).await,
+            },
+            None => vec![],
+        };
+        let mut target_files: Vec<PathBuf> = declarations.iter().map(|x| x.definition_info.path.clone()).collect();
+        target_files.extend(target_file.iter().cloned());
+        target_files.sort();
+        target_files.dedup();
+        if target_files.is_empty() {
+            return Err(format!("{} is neither a file nor a symbol in the AST index", arg));
+        }
+        info!("execute @tests {:?}, {} declarations in {} files", arg, declarations.len(), target_files.len());
+
+        let workspace_folders = context.global_context.read().await.documents_state.workspace_folders.lock().unwrap().clone();
+        let mut test_modules = TestModules { starts: HashMap::new(), workspace_folders: workspace_folders.clone() };
+        let mut vector_of_context_file: Vec<ContextFile> = vec![];
+        let mut covered_files: HashSet<PathBuf> = HashSet::new();
+
+        // 2. test functions that use the declarations, found by AST usages
+        let meta_paths: HashSet<String> = declarations.iter().filter(|x| !x.name.is_empty()).map(|x| x.meta_path.clone()).collect();
+        let users: Vec<SymbolDeclarationStruct> = match *ast_module.lock().await {
+            Some(ref ast) if !meta_paths.is_empty() => ast.get_usages_enclosing_declarations(&meta_paths).await,
+            _ => vec![],
+        };
+        for decl in users.iter() {
+            if vector_of_context_file.len() >= TESTS_MAX_RESULTS {
+                break;
+            }
+            if !test_modules.is_test_declaration(decl, context).await {
+                continue;
+            }
+            covered_files.insert(decl.definition_info.path.clone());
+            vector_of_context_file.push(ContextFile {
+                file_name: decl.definition_info.path.to_string_lossy().to_string(),
+                file_content: decl.get_content().await.unwrap_or_default(),
+                line1: decl.definition_info.range.start_point.row + 1,
+                line2: decl.definition_info.range.end_point.row + 1,
+                usefulness: TESTS_USAGE_USEFULNESS,
+                synthetic: false,
+            });
+        }
+
+        // 3. #[cfg(test)] mod tests inside the file itself
+        if let Some(path) = &target_file {
+            if let Some(start) = test_modules.start_of(path, context).await {
+                if !covered_files.contains(path) {
+                    if let Some((text, line2)) = lines_of_file(path, start + 1, TESTS_MAX_FILE_LINES, context).await {
+                        covered_files.insert(path.clone());
+                        vector_of_context_file.push(ContextFile {
+                            file_name: path.to_string_lossy().to_string(),
+                            file_content: text,
+                            line1: start + 1,
+                            line2,
+                            usefulness: TESTS_MODULE_USEFULNESS,
+                            synthetic: false,
+                        });
+                    }
+                }
+            }
+        }
+
+        // 4. test files named after the files, when the AST didn't link them (it.each in spec.ts, for example)
+        let conventional_names: HashSet<String> = target_files.iter().flat_map(test_file_names_for).collect();
+        let mut by_name: Vec<PathBuf> = cache_correction_arc.values()
+            .map(PathBuf::from)
+            .filter(|p| p.file_name().map(|x| conventional_names.contains(&*x.to_string_lossy())).unwrap_or(false))
+            .filter(|p| is_test_file(p, &workspace_folders) && !target_files.contains(p) && !covered_files.contains(p))
+            .collect();
+        by_name.sort();
+        by_name.dedup();
+        for path in by_name.iter() {
+            if vector_of_context_file.len() >= TESTS_MAX_RESULTS {
+                break;
+            }
+            if let Some((text, line2)) = lines_of_file(path, 1, TESTS_MAX_FILE_LINES, context).await {
+                vector_of_context_file.push(ContextFile {
+                    file_name: path.to_string_lossy().to_string(),
+                    file_content: text,
+                    line1: 1,
+                    line2,
+                    usefulness: TESTS_BY_NAME_USEFULNESS,
+                    synthetic: false,
+                });
+            }
+        }
+
+        if vector_of_context_file.is_empty() {
+            return Err(format!("no tests found for {}", arg));
+        }
+        info!("@tests found {} test fragments", vector_of_context_file.len());
+        Ok(ChatMessage {
+            role: "context_file".to_string(),
+            content: json!(vector_of_context_file).to_string(),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_test_file() {
+        let folders = vec![PathBuf::from("/home/test/proj")];
+        assert!(is_test_file(&PathBuf::from("/proj/tests/integration.rs"), &vec![]));
+        assert!(is_test_file(&PathBuf::from("/proj/src/test/java/com/x/FooTest.java"), &vec![]));
+        assert!(is_test_file(&PathBuf::from("/proj/pkg/test_utils.py"), &vec![]));
+        assert!(is_test_file(&PathBuf::from("/proj/web/button.spec.tsx"), &vec![]));
+        assert!(is_test_file(&PathBuf::from("/proj/server/handler_test.go"), &vec![]));
+        assert!(!is_test_file(&PathBuf::from("/proj/src/main.rs"), &vec![]));
+        assert!(!is_test_file(&PathBuf::from("/proj/src/contest.py"), &vec![]));
+        assert!(!is_test_file(&PathBuf::from("/proj/testdata.ts"), &vec![]));
+        // the workspace itself is under a directory called test
+        assert!(!is_test_file(&PathBuf::from("/home/test/proj/src/main.rs"), &folders));
+        assert!(is_test_file(&PathBuf::from("/home/test/proj/tests/integration.rs"), &folders));
+    }
+
+    #[test]
+    fn test_conventional_names() {
+        assert_eq!(test_file_names_for(&PathBuf::from("/p/utils.py")), vec!["test_utils.py", "utils_test.py"]);
+        assert_eq!(test_file_names_for(&PathBuf::from("/p/Foo.java")), vec!["FooTest.java", "FooTests.java"]);
+        let text = "fn f() {}\n\n#[cfg(test)]\nmod tests {\n}\n";
+        assert_eq!(rust_test_module_start(text), Some(2));
+        assert_eq!(rust_test_module_start("fn f() {}\n"), None);
+        let text = "#[cfg(test)]\nfn helper() {}\n\n#[cfg(test)]\nimpl Foo {\n}\n\n#[cfg(test)]\n#[allow(unused)]\nmod unit_tests {\n}\n";
+        assert_eq!(rust_test_module_start(text), Some(7));
+        assert_eq!(rust_test_module_start("#[cfg(test)]\nfn helper() {}\n"), None);
+    }
+}
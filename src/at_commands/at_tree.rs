@@ -0,0 +1,422 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::json;
+use tokio::sync::Mutex as AMutex;
+use tracing::info;
+use url::Url;
+
+use crate::at_commands::at_commands::{AtCommand, AtCommandsContext, AtParam};
+use crate::at_commands::at_file::files_cache_rebuild_as_needed;
+use crate::call_validation::{ChatMessage, ContextFile};
+use crate::files_in_workspace::{get_file_text_from_memory_or_disk, is_this_inside_blacklisted_dir};
+
+const TREE_DEFAULT_DEPTH: usize = 3;
+const TREE_BUDGET_SHARE: usize = 4;  // the tree takes up to 1/4 of the message context, it should leave room for code
+const TREE_CHARS_PER_TOKEN: usize = 4;
+const TREE_DEFAULT_MAX_CHARS: usize = 8000;  // token limit not known, roughly 2k tokens
+const TREE_LINE_COUNTS_MAX_FILES: usize = 1000;  // don't read the whole repo from disk just to count lines
+const TREE_USEFULNESS: f32 = 30.0;
+const TREE_INDENT: &str = "  ";
+
+
+#[derive(Default)]
+struct TreeNode {
+    dirs: BTreeMap<String, TreeNode>,
+    files: BTreeMap<String, PathBuf>,
+    folded: bool,
+}
+
+impl TreeNode {
+    fn insert(&mut self, relative_path: &Path, full_path: &PathBuf) {
+        let components: Vec<String> = relative_path.components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        let mut node = self;
+        for (idx, component) in components.iter().enumerate() {
+            if idx + 1 == components.len() {
+                node.files.insert(component.clone(), full_path.clone());
+            } else {
+                node = node.dirs.entry(component.clone()).or_default();
+            }
+        }
+    }
+
+    fn files_total(&self) -> usize {
+        self.files.len() + self.dirs.values().map(|d| d.files_total()).sum::<usize>()
+    }
+
+    fn all_files(&self) -> Vec<PathBuf> {
+        let mut result: Vec<PathBuf> = self.files.values().cloned().collect();
+        for d in self.dirs.values() {
+            result.extend(d.all_files());
+        }
+        result
+    }
+
+    fn biggest_expanded_dir(&self, depth: usize, max_depth: usize) -> Option<(usize, Vec<String>)> {
+        // among the directories that are currently rendered with their content, find the one with most files inside
+        let mut best: Option<(usize, Vec<String>)> = None;
+        for (name, child) in self.dirs.iter() {
+            if child.folded || depth + 1 >= max_depth {
+                continue;
+            }
+            let mut candidates = vec![(child.files_total(), vec![name.clone()])];
+            if let Some((cnt, mut path)) = child.biggest_expanded_dir(depth + 1, max_depth) {
+                path.insert(0, name.clone());
+                candidates.push((cnt, path));
+            }
+            for c in candidates {
+                // deeper directories win the tie, fold as little as possible
+                if best.as_ref().map(|b| c.0 > b.0 || (c.0 == b.0 && c.1.len() > b.1.len())).unwrap_or(true) {
+                    best = Some(c);
+                }
+            }
+        }
+        best
+    }
+
+    fn fold(&mut self, path: &[String]) {
+        if let Some((first, rest)) = path.split_first() {
+            if let Some(child) = self.dirs.get_mut(first) {
+                if rest.is_empty() {
+                    child.folded = true;
+                } else {
+                    child.fold(rest);
+                }
+            }
+        }
+    }
+}
+
+fn file_stats(path: &PathBuf, line_counts: &HashMap<PathBuf, usize>, symbol_counts: &HashMap<PathBuf, usize>) -> String {
+    let mut stats: Vec<String> = vec![];
+    if let Some(lines) = line_counts.get(path) {
+        stats.push(format!("{} lines", lines));
+    }
+    if let Some(symbols) = symbol_counts.get(path) {
+        stats.push(format!("{} symbols", symbols));
+    }
+    if stats.is_empty() {
+        return "".to_string();
+    }
+    format!("  ({})", stats.join(", "))
+}
+
+fn render_tree(
+    node: &TreeNode,
+    depth: usize,
+    max_depth: usize,
+    line_counts: &HashMap<PathBuf, usize>,
+    symbol_counts: &HashMap<PathBuf, usize>,
+    out: &mut String,
+) {
+    let indent = TREE_INDENT.repeat(depth);
+    for (name, child) in node.dirs.iter() {
+        if child.folded || depth + 1 >= max_depth {
+            out.push_str(&format!("{}{}/  ({} files)\n", indent, name, child.files_total()));
+        } else {
+            out.push_str(&format!("{}{}/\n", indent, name));
+            render_tree(child, depth + 1, max_depth, line_counts, symbol_counts, out);
+        }
+    }
+    for (name, path) in node.files.iter() {
+        out.push_str(&format!("{}{}{}\n", indent, name, file_stats(path, line_counts, symbol_counts)));
+    }
+}
+
+fn tree_max_chars(tokens_limit: usize) -> usize {
+    if tokens_limit == 0 {
+        return TREE_DEFAULT_MAX_CHARS;
+    }
+    tokens_limit / TREE_BUDGET_SHARE * TREE_CHARS_PER_TOKEN
+}
+
+fn render_tree_within_budget(
+    root: &mut TreeNode,
+    max_depth: usize,
+    line_counts: &HashMap<PathBuf, usize>,
+    symbol_counts: &HashMap<PathBuf, usize>,
+    max_chars: usize,
+) -> String {
+    loop {
+        let mut out = String::new();
+        render_tree(root, 0, max_depth, line_counts, symbol_counts, &mut out);
+        if out.len() <= max_chars {
+            return out;
+        }
+        match root.biggest_expanded_dir(0, max_depth) {
+            Some((_, path)) => root.fold(&path),
+            None => {
+                // nothing left to fold, too many files in the root itself
+                let mut cut = out.char_indices()
+                    .take_while(|(idx, _)| *idx < max_chars)
+                    .filter(|(_, c)| *c == '\n')
+                    .last()
+                    .map(|(idx, _)| idx + 1)
+                    .unwrap_or(0);
+                cut = cut.min(out.len());
+                out.truncate(cut);
+                out.push_str("...\n");
+                return out;
+            }
+        }
+    }
+}
+
+fn dirs_from_cache_correction(cache_correction: &HashMap<String, String>) -> HashMap<String, String> {
+    // cache_correction has "dir3/file.ext" -> "/dir1/dir2/dir3/file.ext", directories are parents on both sides
+    let mut result: HashMap<String, String> = HashMap::new();
+    for (short, full) in cache_correction.iter() {
+        let short_parent = Path::new(short).parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+        let full_parent = Path::new(full).parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+        if short_parent.is_empty() || full_parent.is_empty() {
+            continue;
+        }
+        result.insert(short_parent, full_parent);
+    }
+    result
+}
+
+async fn directory_lookup(value: &String, context: &AtCommandsContext) -> Option<String> {
+    let (cache_correction_arc, _) = files_cache_rebuild_as_needed(context.global_context.clone()).await;
+    let value = value.trim_end_matches(|c| c == '/' || c == '\\').to_string();
+    dirs_from_cache_correction(&cache_correction_arc).get(&value).cloned()
+}
+
+
+#[derive(Debug)]
+pub struct AtParamDirectory {
+    pub name: String,
+}
+
+impl AtParamDirectory {
+    pub fn new() -> Self {
+        Self {
+            name: "directory".to_string()
+        }
+    }
+}
+
+#[async_trait]
+impl AtParam for AtParamDirectory {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    async fn is_value_valid(&self, value: &String, context: &AtCommandsContext) -> bool {
+        value.parse::<usize>().is_ok() || directory_lookup(value, context).await.is_some()
+    }
+    async fn complete(&self, value: &String, context: &AtCommandsContext, top_n: usize) -> Vec<String> {
+        let (cache_correction_arc, _) = files_cache_rebuild_as_needed(context.global_context.clone()).await;
+        let value_lower = value.to_lowercase();
+        let mut dirs: Vec<String> = dirs_from_cache_correction(&cache_correction_arc).into_keys()
+            .filter(|x| x.to_lowercase().contains(&value_lower))
+            .collect();
+        // short relative names first, they are the easiest to read
+        dirs.sort_by(|a, b| (a.starts_with("/"), a.len(), a).cmp(&(b.starts_with("/"), b.len(), b)));
+        dirs.into_iter().take(top_n).collect()
+    }
+}
+
+
+#[derive(Debug)]
+pub struct AtParamDepth {
+    pub name: String,
+}
+
+impl AtParamDepth {
+    pub fn new() -> Self {
+        Self {
+            name: "depth".to_string()
+        }
+    }
+}
+
+#[async_trait]
+impl AtParam for AtParamDepth {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    async fn is_value_valid(&self, value: &String, _: &AtCommandsContext) -> bool {
+        value.parse::<usize>().is_ok()
+    }
+    async fn complete(&self, _value: &String, _context: &AtCommandsContext, _top_n: usize) -> Vec<String> {
+        vec![]
+    }
+}
+
+
+pub struct AtTree {
+    pub name: String,
+    pub params: Vec<Arc<AMutex<dyn AtParam>>>,
+}
+
+impl AtTree {
+    pub fn new() -> Self {
+        AtTree {
+            name: "@tree".to_string(),
+            params: vec![
+                Arc::new(AMutex::new(AtParamDirectory::new())),
+                Arc::new(AMutex::new(AtParamDepth::new())),
+            ],
+        }
+    }
+}
+
+#[async_trait]
+impl AtCommand for AtTree {
+    fn name(&self) -> &String {
+        &self.name
+    }
+    fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>> {
+        &self.params
+    }
+    async fn can_execute(&self, args: &Vec<String>, context: &AtCommandsContext) -> bool {
+        if args.len() > self.params.len() {
+            return false;
+        }
+        for (arg, param) in args.iter().zip(self.params.iter()) {
+            if !param.lock().await.is_value_valid(arg, context).await {
+                return false;
+            }
+        }
+        true
+    }
+    async fn execute(&self, _query: &String, args: &Vec<String>, _top_n: usize, context: &AtCommandsContext) -> Result<ChatMessage, String> {
+        // @tree [subdir] [depth], either one can be omitted
+        let mut subdir: Option<PathBuf> = None;
+        let mut depth = TREE_DEFAULT_DEPTH;
+        for (idx, arg) in args.iter().take(2).enumerate() {
+            if let Ok(x) = arg.parse::<usize>() {
+                depth = x.max(1);
+                break;
+            }
+            if idx == 0 {
+                match directory_lookup(arg, context).await {
+                    Some(x) => subdir = Some(PathBuf::from(x)),
+                    None => return Err(format!("directory {:?} not found", arg)),
+                }
+            }
+        }
+        info!("execute @tree {:?} depth {}", subdir, depth);
+
+        let (workspace_folders, workspace_files, ast_module) = {
+            let gcx_locked = context.global_context.read().await;
+            let folders = gcx_locked.documents_state.workspace_folders.lock().unwrap().clone();
+            let files = gcx_locked.documents_state.workspace_files.lock().unwrap().clone();
+            (folders, files, gcx_locked.ast_module.clone())
+        };
+        let roots: Vec<PathBuf> = match subdir {
+            Some(x) => vec![x],
+            None => workspace_folders,
+        };
+        if roots.is_empty() {
+            return Err("no workspace folders".to_string());
+        }
+        let symbol_counts = match *ast_module.lock().await {
+            Some(ref ast) => ast.get_top_level_symbols_count().await,
+            None => HashMap::new(),
+        };
+
+        let max_chars = tree_max_chars(context.tokens_limit) / roots.len();
+        let mut vector_of_context_file: Vec<ContextFile> = vec![];
+        for root in roots.iter() {
+            let mut tree = TreeNode::default();
+            for url in workspace_files.iter() {
+                let path = match Url::to_file_path(url) {
+                    Ok(x) => x,
+                    Err(_) => continue,
+                };
+                if is_this_inside_blacklisted_dir(&path) {
+                    continue;
+                }
+                if let Ok(relative) = path.strip_prefix(root) {
+                    tree.insert(relative, &path);
+                }
+            }
+            let all_files = tree.all_files();
+            if all_files.is_empty() {
+                continue;
+            }
+            let mut line_counts: HashMap<PathBuf, usize> = HashMap::new();
+            if all_files.len() <= TREE_LINE_COUNTS_MAX_FILES {
+                for path in all_files.iter() {
+                    if let Ok(text) = get_file_text_from_memory_or_disk(context.global_context.clone(), &path.to_string_lossy().to_string()).await {
+                        line_counts.insert(path.clone(), text.lines().count());
+                    }
+                }
+            }
+            let text = format!("{}/\n{}",
+                root.display(),
+                render_tree_within_budget(&mut tree, depth, &line_counts, &symbol_counts, max_chars),
+            );
+            vector_of_context_file.push(ContextFile {
+                file_name: root.to_string_lossy().to_string(),
+                line1: 1,
+                line2: text.lines().count(),
+                file_content: text,
+                usefulness: TREE_USEFULNESS,
+                synthetic: true,
+            });
+        }
+        if vector_of_context_file.is_empty() {
+            return Err("no files to show".to_string());
+        }
+        Ok(ChatMessage {
+            role: "context_file".to_string(),
+            content: json!(vector_of_context_file).to_string(),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_tree() -> TreeNode {
+        let mut tree = TreeNode::default();
+        for p in ["Cargo.toml", "src/main.rs", "src/lib.rs", "src/ast/mod.rs", "src/ast/index.rs", "src/ast/parsers/rust.rs", "tests/a.py"] {
+            tree.insert(Path::new(p), &PathBuf::from("/proj").join(p));
+        }
+        tree
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let mut tree = make_tree();
+        let mut line_counts = HashMap::new();
+        line_counts.insert(PathBuf::from("/proj/Cargo.toml"), 80);
+        let mut symbol_counts = HashMap::new();
+        symbol_counts.insert(PathBuf::from("/proj/Cargo.toml"), 0);
+        let text = render_tree_within_budget(&mut tree, 2, &line_counts, &symbol_counts, 10000);
+        assert_eq!(text, "\
+src/
+  ast/  (3 files)
+  lib.rs
+  main.rs
+tests/
+  a.py
+Cargo.toml  (80 lines, 0 symbols)
+");
+    }
+
+    #[test]
+    fn test_fold_to_budget() {
+        let mut tree = make_tree();
+        let empty = HashMap::new();
+        let text = render_tree_within_budget(&mut tree, 10, &empty, &empty, 60);
+        assert!(text.len() <= 60);
+        assert!(text.contains("src/  (5 files)\n") || text.contains("ast/  (3 files)\n"));
+        assert!(text.ends_with("Cargo.toml\n"));
+    }
+
+    #[test]
+    fn test_tree_max_chars() {
+        assert_eq!(tree_max_chars(0), TREE_DEFAULT_MAX_CHARS);
+        assert_eq!(tree_max_chars(2000), 2000);
+        assert!(tree_max_chars(100) < tree_max_chars(8000));
+    }
+}
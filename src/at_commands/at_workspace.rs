@@ -31,6 +31,7 @@ fn results2message(results: &Vec<Record>) -> ChatMessage {
             line1: r.start_line as usize + 1,
             line2: r.end_line as usize + 1,
             usefulness: 100.0 / ((i + 1) as f32),
+            synthetic: false,
         });
     }
     ChatMessage {
@@ -1,10 +1,17 @@
+pub mod at_ast_call_graph;
 pub mod at_ast_definition;
 pub mod at_ast_file_symbols;
 pub mod at_ast_lookup_symbols;
 pub mod at_ast_reference;
 pub mod at_commands;
+pub mod at_custom;
+pub mod at_diff;
 pub mod at_file;
+pub mod at_grep;
+pub mod at_tests;
+pub mod at_tree;
 pub mod at_workspace;
 pub mod at_params;
+pub mod at_pin;
 pub mod utils;
 pub mod query;
@@ -1,3 +1,194 @@
+// Grammar of at-commands in a chat query:
+// - a line is split into tokens by whitespace
+// - a token starting with a quote runs until the matching quote, inside "..." the escapes are \" and \\
+// - outside of quotes a backslash escapes whitespace and quotes: path\ with\ spaces.txt
+// - an unquoted token that is a known command name starts a command, anywhere in the line
+// - the command takes the tokens after it: --key=value flags, and positional arguments up to the number of its params
+// - everything else is the question, it stays in the query
+// Positions are in chars, pos1 inclusive, pos2 exclusive.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryToken {
+    pub value: String,  // unquoted and unescaped
+    pub pos1: usize,
+    pub pos2: usize,
+    pub quoted: bool,
+}
+
+impl QueryToken {
+    pub fn is_command_like(&self) -> bool {
+        !self.quoted && self.value.starts_with("@")
+    }
+
+    pub fn as_flag(&self) -> Option<(String, String)> {
+        if self.quoted {
+            return None;
+        }
+        let (key, value) = self.value.strip_prefix("--")?.split_once('=')?;
+        if key.is_empty() {
+            return None;
+        }
+        Some((key.to_string(), value.to_string()))
+    }
+}
+
+pub fn tokenize(line: &str) -> Vec<QueryToken> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens: Vec<QueryToken> = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let pos1 = i;
+        let quoted = chars[i] == '"' || chars[i] == '\'';
+        let mut value = String::new();
+        let mut quote: Option<char> = None;
+        while i < chars.len() {
+            let ch = chars[i];
+            let next = chars.get(i + 1).cloned();
+            match quote {
+                Some(q) => {
+                    if ch == q {
+                        quote = None;
+                    } else if q == '"' && ch == '\\' && (next == Some('"') || next == Some('\\')) {
+                        value.push(next.unwrap());
+                        i += 1;
+                    } else {
+                        value.push(ch);
+                    }
+                }
+                None => {
+                    if ch.is_whitespace() {
+                        break;
+                    }
+                    // a quote opens only at the token start or right after '=' in a flag, so "don't" stays a word
+                    let can_open = i == pos1 || chars[i - 1] == '=';
+                    if (ch == '"' || ch == '\'') && can_open {
+                        quote = Some(ch);
+                    } else if ch == '\\' && next.map(|c| c.is_whitespace() || c == '"' || c == '\'').unwrap_or(false) {
+                        value.push(next.unwrap());
+                        i += 1;
+                    } else {
+                        value.push(ch);
+                    }
+                }
+            }
+            i += 1;
+        }
+        tokens.push(QueryToken { value, pos1, pos2: i, quoted });
+    }
+    tokens
+}
+
+pub fn quote_if_needed(value: &String) -> String {
+    // the inverse of tokenize(), for completions that go back into the query
+    let needs_quotes = value.is_empty()
+        || value.chars().any(|c| c.is_whitespace())
+        || value.starts_with('"')
+        || value.starts_with('\'');
+    if !needs_quotes {
+        return value.clone();
+    }
+    format!("\"{}\"", value.replace("\\", "\\\\").replace("\"", "\\\""))
+}
+
+#[derive(Clone, Debug)]
+pub struct QueryCommand {
+    pub command: QueryToken,
+    pub items: Vec<QueryToken>,  // flags and positional arguments, in the order they appear
+}
+
+impl QueryCommand {
+    pub fn args(&self) -> Vec<&QueryToken> {
+        self.items.iter().filter(|x| x.as_flag().is_none()).collect()
+    }
+
+    pub fn flags(&self) -> Vec<(String, String)> {
+        self.items.iter().filter_map(|x| x.as_flag()).collect()
+    }
+
+    pub fn truncate_args(&mut self, n: usize) {
+        // keeps the first n positional arguments, and the flags before the first one dropped
+        let mut args_cnt = 0;
+        let mut keep = 0;
+        for item in self.items.iter() {
+            if item.as_flag().is_none() {
+                if args_cnt == n {
+                    break;
+                }
+                args_cnt += 1;
+            }
+            keep += 1;
+        }
+        self.items.truncate(keep);
+    }
+
+    pub fn span(&self) -> (usize, usize) {
+        (self.command.pos1, self.items.last().map(|x| x.pos2).unwrap_or(self.command.pos2))
+    }
+}
+
+pub fn parse_commands_in_line<F>(tokens: &Vec<QueryToken>, args_limit: F) -> Vec<QueryCommand>
+where
+    F: Fn(&String) -> Option<usize>,
+{
+    // args_limit returns None for a word that is not a command
+    let mut result: Vec<QueryCommand> = vec![];
+    let mut idx = 0;
+    while idx < tokens.len() {
+        let token = &tokens[idx];
+        idx += 1;
+        let limit = match token.is_command_like().then(|| args_limit(&token.value)).flatten() {
+            Some(x) => x,
+            None => continue,
+        };
+        let mut cmd = QueryCommand { command: token.clone(), items: vec![] };
+        let mut args_cnt = 0;
+        while idx < tokens.len() {
+            let t = &tokens[idx];
+            if t.is_command_like() && args_limit(&t.value).is_some() {
+                break;
+            }
+            if t.as_flag().is_none() {
+                if args_cnt == limit {
+                    break;
+                }
+                args_cnt += 1;
+            }
+            cmd.items.push(t.clone());
+            idx += 1;
+        }
+        result.push(cmd);
+    }
+    result
+}
+
+pub fn remove_spans(line: &str, spans: &Vec<(usize, usize)>) -> String {
+    // removes the commands from the line, together with the whitespace that separated them from the question
+    let chars: Vec<char> = line.chars().collect();
+    let mut remove = vec![false; chars.len()];
+    for (pos1, pos2) in spans.iter() {
+        let pos1 = (*pos1).min(chars.len());
+        let mut pos2 = (*pos2).min(chars.len());
+        let mut start = pos1;
+        if pos2 < chars.len() && chars[pos2].is_whitespace() {
+            while pos2 < chars.len() && chars[pos2].is_whitespace() {
+                pos2 += 1;
+            }
+        } else {
+            while start > 0 && chars[start - 1].is_whitespace() {
+                start -= 1;
+            }
+        }
+        for r in remove[start..pos2].iter_mut() {
+            *r = true;
+        }
+    }
+    chars.iter().zip(remove.iter()).filter(|(_, r)| !**r).map(|(c, _)| *c).collect()
+}
+
 #[derive(Clone)]
 pub struct QueryLine {
     pub value: String,
@@ -6,16 +197,19 @@ pub struct QueryLine {
 }
 
 impl QueryLine {
-    pub fn new(
+    pub fn new<F>(
         value: String,
         cursor_rel: i64,
-        cursor_line_start: i64
-    ) -> Self {
+        cursor_line_start: i64,
+        args_limit: F,
+    ) -> Self
+    where
+        F: Fn(&String) -> Option<usize>,
+    {
         QueryLine {
             value: value.clone(),
             cursor_line_start,
-            args: parse_args_from_line(&value).iter_mut().map(|x| {
-                x.pos2 += 1;
+            args: parse_args_from_line(&value, args_limit).iter_mut().map(|x| {
                 x.focused = cursor_rel >= x.pos1 && cursor_rel <= x.pos2;
                 x.pos1 += cursor_line_start;
                 x.pos2 += cursor_line_start;
@@ -42,43 +236,95 @@ pub struct QueryLineArg {
     pub type_name: String,
 }
 
-fn parse_args_from_line(line: &String) -> Vec<QueryLineArg> {
-    let mut pos1: i64 = -1;
-    let mut value: String = "".to_string();
-    let mut args: Vec<QueryLineArg> = vec![];
-    for (idx, ch) in line.chars().enumerate() {
-        let idx = idx as i64;
-        if value.is_empty() && ch.to_string() != " " {
-            pos1 = idx;
-        }
+fn parse_args_from_line<F>(line: &String, args_limit: F) -> Vec<QueryLineArg>
+where
+    F: Fn(&String) -> Option<usize>,
+{
+    // the line is cut at the cursor, the command being typed is the last one; a known command takes
+    // its arguments the same way parse_commands_in_line() does for execution, an unknown one is a name being typed
+    let tokens = tokenize(line);
+    let cmd_idx = match tokens.iter().rposition(|t| t.is_command_like()) {
+        Some(x) => x,
+        None => return vec![],
+    };
+    let commands = parse_commands_in_line(&tokens, &args_limit);
+    let args: Vec<&QueryToken> = match commands.last().filter(|c| c.command.pos1 == tokens[cmd_idx].pos1) {
+        Some(cmd) => std::iter::once(&cmd.command).chain(cmd.args()).collect(),
+        None => vec![&tokens[cmd_idx]],
+    };
+    args.iter()
+        .map(|t| QueryLineArg {
+            value: t.value.clone(),
+            pos1: t.pos1 as i64,
+            pos2: t.pos2 as i64,
+            focused: false,
+            type_name: if t.is_command_like() { "command".to_string() } else { "arg".to_string() },
+        })
+        .collect()
+}
 
-        if ch.to_string() != " " {
-            value.push(ch);
-        }
 
-        if pos1 != -1 && (ch.to_string() == " " || idx == (line.len() -1) as i64) {
-            args.push(QueryLineArg{
-                value: value.clone(),
-                pos1,
-                pos2: {
-                    if ch.to_string() != " " {
-                        idx
-                    } else {
-                        idx - 1
-                    }
-                },
-                focused: false,
-                type_name: {
-                    if value.starts_with("@") {
-                        "command".to_string()
-                    } else {
-                        "arg".to_string()
-                    }
-                }
-            });
-            pos1 = -1;
-            value = "".to_string();
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(tokens: &Vec<QueryToken>) -> Vec<String> {
+        tokens.iter().map(|t| t.value.clone()).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let tokens = tokenize(r#"explain @file "my dir/a b.rs" don't --top-n=3 path\ x.rs "say \"hi\"" C:\Users\a.rs"#);
+        assert_eq!(values(&tokens), vec![
+            "explain", "@file", "my dir/a b.rs", "don't", "--top-n=3", "path x.rs", "say \"hi\"", "C:\\Users\\a.rs",
+        ]);
+        assert_eq!((tokens[2].pos1, tokens[2].pos2), (14, 29));
+        assert!(tokens[2].quoted);
+        assert_eq!(tokens[4].as_flag(), Some(("top-n".to_string(), "3".to_string())));
+        assert_eq!(tokenize(r#"--lang="rust lang""#)[0].as_flag(), Some(("lang".to_string(), "rust lang".to_string())));
+        assert_eq!(values(&tokenize("@file \"unfinished quo")), vec!["@file", "unfinished quo"]);
+        assert_eq!(tokenize(&quote_if_needed(&"C:\\my dir\\x".to_string()))[0].value, "C:\\my dir\\x");
+    }
+
+    #[test]
+    fn test_parse_commands_in_line() {
+        let limit = |name: &String| match name.as_str() {
+            "@file" => Some(1),
+            "@diff" => Some(2),
+            _ => None,
+        };
+        let line = "explain @file a.rs please, and @diff --top-n=2 main and @unknown x";
+        let tokens = tokenize(line);
+        let mut cmds = parse_commands_in_line(&tokens, limit);
+        assert_eq!(cmds.len(), 2);
+        assert_eq!(values(&cmds[0].args().into_iter().cloned().collect()), vec!["a.rs"]);
+        assert_eq!(values(&cmds[1].args().into_iter().cloned().collect()), vec!["main", "and"]);
+        assert_eq!(cmds[1].flags(), vec![("top-n".to_string(), "2".to_string())]);
+        cmds[1].truncate_args(1);
+        let spans: Vec<(usize, usize)> = cmds.iter().map(|c| c.span()).collect();
+        assert_eq!(remove_spans(line, &spans), "explain please, and and @unknown x");
+        assert_eq!(remove_spans("@file a.rs", &vec![(0, 10)]), "");
+        assert_eq!(remove_spans("look at @file a.rs", &vec![(8, 18)]), "look at");
+    }
+
+    #[test]
+    fn test_query_line() {
+        let limit = |name: &String| if name == "@file" { Some(1) } else { None };
+        let line = QueryLine::new("hi @file \"a b".to_string(), 13, 100, limit);
+        assert_eq!(line.command().unwrap().value, "@file");
+        let args = line.get_args();
+        assert_eq!(args.len(), 1);
+        assert_eq!(args[0].value, "a b");
+        assert_eq!((args[0].pos1, args[0].pos2), (109, 113));
+        assert!(args[0].focused);
+
+        // the words past max_args are the question, the same as in parse_commands_in_line()
+        let line = QueryLine::new("@file a.rs --top-n=2 explain it".to_string(), 31, 0, limit);
+        assert_eq!(line.get_args().iter().map(|x| x.value.clone()).collect::<Vec<_>>(), vec!["a.rs"]);
+
+        // an unknown command is a name being typed, it gets no arguments
+        let line = QueryLine::new("@file a.rs @fi".to_string(), 14, 0, limit);
+        assert_eq!(line.command().unwrap().value, "@fi");
+        assert!(line.get_args().is_empty());
     }
-    args
 }
@@ -1,53 +1,159 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use regex::Regex;
+use serde_json::json;
 use tokio::sync::Mutex as AMutex;
-use tracing::info;
+use tracing::{info, warn};
 
+use crate::ast::comments_wrapper::get_language_id_by_filename;
 use crate::at_commands::at_commands::{AtCommandCall, AtCommandsContext, AtParam};
+use crate::at_commands::query::{parse_commands_in_line, remove_spans, tokenize};
+use crate::call_validation::{ChatMessage, ContextFile};
 
 
+pub async fn at_commands_args_limit(context: &AtCommandsContext) -> HashMap<String, usize> {
+    // max_args() of every command, for parse_commands_in_line()
+    let mut args_limit: HashMap<String, usize> = HashMap::new();
+    for (name, cmd) in context.at_commands.iter() {
+        args_limit.insert(name.clone(), cmd.lock().await.max_args());
+    }
+    args_limit
+}
+
 pub async fn find_valid_at_commands_in_query(
     query: &mut String,
     context: &AtCommandsContext,
 ) -> Vec<AtCommandCall> {
+    // see query.rs for the grammar
+    let args_limit = at_commands_args_limit(context).await;
     let mut results = vec![];
-    let mut valid_command_lines = vec![];
-    for (idx, line) in query.lines().enumerate() {
-        let line_words: Vec<&str> = line.split_whitespace().collect();
-        let q_cmd_args = line_words.iter().skip(1).map(|x|x.to_string()).collect::<Vec<String>>();
-
-        let q_cmd = match line_words.first() {
-            Some(x) => x,
-            None => continue,
-        };
+    let mut new_lines: Vec<String> = vec![];
+    for line in query.lines() {
+        let tokens = tokenize(line);
+        let mut spans = vec![];
+        for mut q_cmd in parse_commands_in_line(&tokens, |name| args_limit.get(name).cloned()) {
+            let cmd = match context.at_commands.get(&q_cmd.command.value) {
+                Some(x) => x.clone(),
+                None => continue,
+            };
+            let q_cmd_args: Vec<String> = q_cmd.args().iter().map(|x| x.value.clone()).collect();
+            // the words after the command might be the question, not arguments:
+            // 1. the longest list of arguments the command accepts as is
+            // 2. typo correction, the shortest list first, so the question doesn't get "corrected" into arguments
+            // 3. no arguments at all
+            let mut accepted: Option<(usize, Vec<String>)> = None;
+            for n in (1..=q_cmd_args.len()).rev() {
+                let args = q_cmd_args[..n].to_vec();
+                if cmd.lock().await.can_execute(&args, context).await {
+                    accepted = Some((n, args));
+                    break;
+                }
+            }
+            if accepted.is_none() {
+                for n in 1..=q_cmd_args.len() {
+                    let args = q_cmd_args[..n].to_vec();
+                    let corrected = {
+                        let cmd_locked = cmd.lock().await;
+                        correct_arguments_if_needed(cmd_locked.params(), cmd_locked.max_args(), &args, false, context).await
+                    };
+                    match corrected {
+                        Ok(args_corrected) => {
+                            if cmd.lock().await.can_execute(&args_corrected, context).await {
+                                accepted = Some((n, args_corrected));
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            info!("command {:?} is not executable with arguments {:?}; error: {:?}", q_cmd.command.value, args, e);
+                        }
+                    }
+                }
+            }
+            if accepted.is_none() && cmd.lock().await.can_execute(&vec![], context).await {
+                accepted = Some((0, vec![]));
+            }
+            let (n, args) = match accepted {
+                Some(x) => x,
+                None => continue,
+            };
+            q_cmd.truncate_args(n);
+            info!("command {:?} is perfectly good, args {:?} flags {:?}", q_cmd.command.value, args, q_cmd.flags());
+            results.push(AtCommandCall::new(Arc::clone(&cmd), args, q_cmd.flags().into_iter().collect()));
+            spans.push(q_cmd.span());
+        }
+        if spans.is_empty() {
+            new_lines.push(line.to_string());
+            continue;
+        }
+        // a line that had nothing but commands goes away completely
+        let line_without_commands = remove_spans(line, &spans);
+        if !line_without_commands.trim().is_empty() {
+            new_lines.push(line_without_commands);
+        }
+    }
+    // remove the valid commands from query
+    *query = new_lines.join("\n");
+    results
+}
 
-        let (_, cmd) = match context.at_commands.iter().find(|&(k, _v)| k == q_cmd) {
-            Some(x) => x,
-            None => continue,
-        };
-        let can_execute = cmd.lock().await.can_execute(&q_cmd_args, context).await;
-        let q_cmd_args = match correct_arguments_if_needed(cmd.lock().await.params(), &q_cmd_args, can_execute, context).await {
-            Ok(x) => x,
+pub async fn execute_at_commands_in_query(
+    query: &mut String,
+    context: &AtCommandsContext,
+    top_n: usize,
+) -> Vec<ChatMessage> {
+    let valid_commands = find_valid_at_commands_in_query(query, context).await;
+    let mut messages = vec![];
+    for cmd in valid_commands {
+        let mut cmd_top_n = top_n;
+        let mut lang_mb: Option<String> = None;
+        for (key, value) in cmd.flags.iter() {
+            match key.as_str() {
+                "top-n" => match value.parse::<usize>() {
+                    Ok(x) if x > 0 => cmd_top_n = x,
+                    _ => warn!("ignoring --top-n={:?}, expected a positive number", value),
+                },
+                "lang" => lang_mb = Some(value.to_lowercase()),
+                _ => warn!("unknown flag --{}={:?}, ignoring", key, value),
+            }
+        }
+        match cmd.command.lock().await.execute(query, &cmd.args, cmd_top_n, context).await {
+            Ok(msg) => {
+                match &lang_mb {
+                    Some(lang) => messages.push(filter_context_files_by_lang(msg, lang)),
+                    None => messages.push(msg),
+                }
+            },
             Err(e) => {
-                info!("command {:?} is not executable with arguments {:?}; error: {:?}", q_cmd, q_cmd_args, e);
-                continue;
+                warn!("can't execute command that indicated it can execute: {}", e);
             }
-        };
+        }
+    }
+    messages
+}
 
-        info!("command {:?} is perfectly good", q_cmd);
-        results.push(AtCommandCall::new(Arc::clone(&cmd), q_cmd_args.clone()));
-        valid_command_lines.push(idx);
+fn filter_context_files_by_lang(msg: ChatMessage, lang: &String) -> ChatMessage {
+    // --lang=rust matches the language name as well as the file extension, --lang=rs works too
+    let context_files: Vec<ContextFile> = match serde_json::from_str(&msg.content) {
+        Ok(x) => x,
+        Err(_) => return msg,
+    };
+    let filtered: Vec<ContextFile> = context_files.into_iter().filter(|x| {
+        let path = PathBuf::from(&x.file_name);
+        let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let lang_id = get_language_id_by_filename(&path).map(|l| l.to_string()).unwrap_or_default();
+        ext == *lang || lang_id == *lang
+    }).collect();
+    ChatMessage {
+        role: msg.role,
+        content: json!(filtered).to_string(),
     }
-    // remove the lines that are valid commands from query
-    *query = query.lines().enumerate()
-        .filter(|(idx, _line)| !valid_command_lines.contains(idx))
-        .map(|(_idx, line)| line)
-        .collect::<Vec<_>>().join("\n");
-    results
 }
 
 pub async fn correct_arguments_if_needed(
     params: &Vec<Arc<AMutex<dyn AtParam>>>,
+    max_args: usize,
     args: &Vec<String>,
     can_execute: bool,
     context: &AtCommandsContext,
@@ -58,12 +164,16 @@ pub async fn correct_arguments_if_needed(
     if can_execute {
         return Ok(args.clone());
     }
-    if params.len() != args.len() {
-        return Err(format!("incorrect number of arguments: {} given; {} required", args.len(), params.len()));
+    if args.len() > max_args {
+        return Err(format!("incorrect number of arguments: {} given; {} at most", args.len(), max_args));
     }
     let mut args_new = vec![];
-    for (param, arg) in params.iter().zip(args.iter()) {
-        let param = param.lock().await;
+    for (idx, arg) in args.iter().enumerate() {
+        // arguments past params() go through the last param, as in the completion
+        let param = match params.get(idx).or(params.last()) {
+            Some(x) => x.lock().await,
+            None => return Err(format!("arg '{}' has no param to check it", arg)),
+        };
         if param.is_value_valid(arg, context).await {
             args_new.push(arg.clone());
             continue;
@@ -130,3 +240,103 @@ pub fn split_file_into_chunks_from_line_inside(
 
     (result_above, result_below)
 }
+
+pub fn glob_to_regex(glob: &str) -> Result<Regex, String> {
+    // "*.rs" matches a file name, "src/**/*.rs" matches the tail of a path, both slash kinds are accepted
+    let mut re = String::from(r"(^|[/\\])");
+    let mut chars = glob.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re.push_str(r"(.*[/\\])?");
+                } else {
+                    re.push_str(".*");
+                }
+            },
+            '*' => re.push_str(r"[^/\\]*"),
+            '?' => re.push_str(r"[^/\\]"),
+            '/' | '\\' => re.push_str(r"[/\\]"),
+            _ => re.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).map_err(|e| format!("bad glob {:?}: {}", glob, e))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::at_commands::at_commands::AtCommand;
+    use crate::global_context::create_global_context_for_tests;
+
+    struct AtParamRustFile {
+        name: String,
+    }
+
+    #[async_trait]
+    impl AtParam for AtParamRustFile {
+        fn name(&self) -> &String {
+            &self.name
+        }
+        async fn is_value_valid(&self, value: &String, _context: &AtCommandsContext) -> bool {
+            value.ends_with(".rs")
+        }
+        async fn complete(&self, _value: &String, _context: &AtCommandsContext, _top_n: usize) -> Vec<String> {
+            // like the fuzzy file search, always finds something
+            vec!["other.rs".to_string()]
+        }
+    }
+
+    struct AtTwoFiles {
+        name: String,
+        params: Vec<Arc<AMutex<dyn AtParam>>>,
+    }
+
+    #[async_trait]
+    impl AtCommand for AtTwoFiles {
+        fn name(&self) -> &String {
+            &self.name
+        }
+        fn params(&self) -> &Vec<Arc<AMutex<dyn AtParam>>> {
+            &self.params
+        }
+        async fn can_execute(&self, args: &Vec<String>, _context: &AtCommandsContext) -> bool {
+            !args.is_empty() && args.iter().all(|x| x.ends_with(".rs"))
+        }
+        async fn execute(&self, _query: &String, _args: &Vec<String>, _top_n: usize, _context: &AtCommandsContext) -> Result<ChatMessage, String> {
+            Err("not used".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_command_then_free_text() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let gcx = create_global_context_for_tests(tmp_dir.path().join("cache")).await;
+        let param: Arc<AMutex<dyn AtParam>> = Arc::new(AMutex::new(AtParamRustFile { name: "file_path".to_string() }));
+        let cmd = AtTwoFiles { name: "@two".to_string(), params: vec![param.clone(), param] };
+        let mut context = AtCommandsContext::new(gcx).await;
+        context.at_commands = HashMap::from([
+            ("@two".to_string(), Arc::new(AMutex::new(Box::new(cmd) as Box<dyn AtCommand + Send>))),
+        ]);
+
+        // the question after the command must not be "corrected" into the optional second argument
+        let mut query = "@two main.rs explain the risk".to_string();
+        let calls = find_valid_at_commands_in_query(&mut query, &context).await;
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].args, vec!["main.rs".to_string()]);
+        assert_eq!(query, "explain the risk");
+
+        // a typo in the first argument is still corrected, the rest stays the question
+        let mut query = "@two mian explain".to_string();
+        let calls = find_valid_at_commands_in_query(&mut query, &context).await;
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].args, vec!["other.rs".to_string()]);
+        assert_eq!(query, "explain");
+    }
+}
@@ -3,8 +3,13 @@ use std::sync::Arc;
 use std::vec;
 
 use tokio::sync::RwLock as ARwLock;
+use tokio::sync::Mutex as AMutex;
 use tokio::task::JoinHandle;
-use crate::files_in_workspace::file_watcher_thread;
+use tracing::info;
+use url::Url;
+
+use crate::ast::ast_module::AstModule;
+use crate::files_in_workspace::DocumentInfo;
 
 use crate::vecdb;
 use crate::global_context::GlobalContext;
@@ -34,25 +39,32 @@ impl BackgroundTasksHolder {
         self.tasks.extend(tasks);
     }
 
-    pub async fn abort(self) {
-        for task in self.tasks {
+    pub async fn abort(mut self) {
+        for task in std::mem::take(&mut self.tasks) {
             task.abort();
             let _ = task.await;
         }
     }
 }
 
+impl Drop for BackgroundTasksHolder {
+    fn drop(&mut self) {
+        // a task that owns a holder takes its tasks along when it's aborted, see vecdb_background_reload
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+    }
+}
+
 pub async fn start_background_tasks(gcx: Arc<ARwLock<GlobalContext>>) -> BackgroundTasksHolder {
     let mut bg = BackgroundTasksHolder::new(vec![
         tokio::spawn(basic_transmit::telemetry_background_task(gcx.clone())),
         tokio::spawn(snippets_transmit::tele_snip_background_task(gcx.clone())),
-        tokio::spawn(vecdb::vecdb::vecdb_background_reload(gcx.clone())),   // this in turn can create global_context::vec_db
     ]);
-    match *gcx.clone().read().await.ast_module.lock().await {
-        Some(ref ast) => bg.extend(ast.ast_start_background_tasks().await),
-        None => ()
-    };
-    
+    // AST and vecdb can be switched on and off at runtime, they keep their tasks in GlobalContext
+    start_ast_background_tasks(gcx.clone()).await;
+    start_vecdb_background_tasks(gcx.clone()).await;
+
     let files_jsonl_path = gcx.clone().read().await.cmdline.files_jsonl_path.clone();
     if !files_jsonl_path.is_empty() {
         bg.extend(vec![
@@ -61,3 +73,70 @@ pub async fn start_background_tasks(gcx: Arc<ARwLock<GlobalContext>>) -> Backgro
     }
     bg
 }
+
+async fn replace_tasks(holder: Arc<AMutex<BackgroundTasksHolder>>, tasks: Vec<JoinHandle<()>>) {
+    let old = std::mem::replace(&mut *holder.lock().await, BackgroundTasksHolder::new(tasks));
+    old.abort().await;
+}
+
+pub async fn start_ast_background_tasks(gcx: Arc<ARwLock<GlobalContext>>) {
+    let (ast_module, holder) = {
+        let gcx_locked = gcx.read().await;
+        (gcx_locked.ast_module.clone(), gcx_locked.ast_background_tasks.clone())
+    };
+    let tasks = match *ast_module.lock().await {
+        Some(ref ast) => ast.ast_start_background_tasks().await,
+        None => vec![],
+    };
+    replace_tasks(holder, tasks).await;
+}
+
+pub async fn start_vecdb_background_tasks(gcx: Arc<ARwLock<GlobalContext>>) {
+    let holder = gcx.read().await.vecdb_background_tasks.clone();
+    // this in turn can create global_context::vec_db
+    replace_tasks(holder, vec![tokio::spawn(vecdb::vecdb::vecdb_background_reload(gcx.clone()))]).await;
+}
+
+pub async fn stop_ast_background_tasks(gcx: Arc<ARwLock<GlobalContext>>) {
+    let holder = gcx.read().await.ast_background_tasks.clone();
+    replace_tasks(holder, vec![]).await;
+}
+
+pub async fn stop_vecdb_background_tasks(gcx: Arc<ARwLock<GlobalContext>>) {
+    let holder = gcx.read().await.vecdb_background_tasks.clone();
+    replace_tasks(holder, vec![]).await;
+}
+
+pub async fn switch_ast(gcx: Arc<ARwLock<GlobalContext>>, enabled: bool) -> Result<(), String> {
+    stop_ast_background_tasks(gcx.clone()).await;
+    let ast_module = gcx.read().await.ast_module.clone();
+    if !enabled {
+        *ast_module.lock().await = None;
+        info!("AST switched off");
+        return Ok(());
+    }
+    let module = AstModule::ast_indexer_init(gcx.clone()).await?;
+    *ast_module.lock().await = Some(module);
+    start_ast_background_tasks(gcx.clone()).await;
+    // the workspace was listed already, the AST only needs to see it
+    let docs: Vec<DocumentInfo> = {
+        let gcx_locked = gcx.read().await;
+        let workspace_files: Vec<Url> = gcx_locked.documents_state.workspace_files.lock().unwrap().clone();
+        workspace_files.into_iter().map(DocumentInfo::new).collect()
+    };
+    if let Some(ref ast) = *ast_module.lock().await {
+        ast.ast_indexer_enqueue_files(&docs, false).await;
+    }
+    info!("AST switched on, {} files enqueued", docs.len());
+    Ok(())
+}
+
+pub async fn switch_vecdb(gcx: Arc<ARwLock<GlobalContext>>, enabled: bool) {
+    // vecdb_background_reload creates the database again from caps, with the current api key
+    stop_vecdb_background_tasks(gcx.clone()).await;
+    gcx.write().await.vec_db = Arc::new(AMutex::new(None));
+    if enabled {
+        start_vecdb_background_tasks(gcx.clone()).await;
+    }
+    info!("vecdb switched {}", if enabled { "on" } else { "off" });
+}
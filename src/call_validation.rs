@@ -183,6 +183,25 @@ mod tests {
         };
         assert!(validate_post(post).is_err());
     }
+
+    #[test]
+    fn test_context_selection() {
+        let selection: ContextSelection = serde_json::from_str(
+            r#"{"pinned": [{"file_name": "/a.rs", "line1": 10, "line2": 20}], "excluded": [{"file_name": "/b.rs"}]}"#
+        ).unwrap();
+        let cf = |file_name: &str, line1: usize, line2: usize| ContextFile {
+            file_name: file_name.to_string(),
+            file_content: "".to_string(),
+            line1,
+            line2,
+            usefulness: 0.0,
+            synthetic: false,
+        };
+        assert!(selection.is_pinned(&cf("/a.rs", 10, 20)));
+        assert!(!selection.is_pinned(&cf("/a.rs", 10, 21)));
+        assert!(selection.is_excluded(&cf("/b.rs", 1, 5)));
+        assert!(!selection.is_excluded(&cf("/a.rs", 10, 20)));
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -193,6 +212,8 @@ pub struct ContextFile {
     pub line2: usize,   // starts from 1
     #[serde(default)]
     pub usefulness: f32,  // the higher the better
+    #[serde(default)]
+    pub synthetic: bool,  // file_content is generated (outline, diff, ...), not a copy of lines line1..line2
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -211,4 +232,52 @@ pub struct ChatPost {
     #[serde(default)]
     pub scratchpad: String,
     pub stream: Option<bool>,
+    #[serde(default)]
+    pub context_selection: ContextSelection,
+    #[serde(default)]
+    pub chat_id: String,
+    #[serde(default)]
+    pub active_file: Option<ChatActiveFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatActiveFile {
+    pub file_name: String,
+    #[serde(default)]
+    pub line: usize,  // the cursor, the same numbering as %CURSOR_LINE% in toolbox commands
+    #[serde(default)]
+    pub selection: Option<(usize, usize)>,  // 1-based lines, inclusive
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContextItemRef {
+    pub file_name: String,
+    #[serde(default)]
+    pub line1: usize,  // line1 == line2 == 0 refers to all items from the file
+    #[serde(default)]
+    pub line2: usize,
+}
+
+impl ContextItemRef {
+    pub fn matches(&self, x: &ContextFile) -> bool {
+        self.file_name == x.file_name && ((self.line1 == 0 && self.line2 == 0) || (self.line1 == x.line1 && self.line2 == x.line2))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ContextSelection {
+    #[serde(default)]
+    pub pinned: Vec<ContextItemRef>,    // taken before anything else, as long as they fit
+    #[serde(default)]
+    pub excluded: Vec<ContextItemRef>,  // never taken
+}
+
+impl ContextSelection {
+    pub fn is_pinned(&self, x: &ContextFile) -> bool {
+        self.pinned.iter().any(|p| p.matches(x))
+    }
+
+    pub fn is_excluded(&self, x: &ContextFile) -> bool {
+        self.excluded.iter().any(|p| p.matches(x))
+    }
 }
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::io;
 use std::path::PathBuf;
@@ -12,12 +12,17 @@ use ropey::Rope;
 use tokio::fs::read_to_string;
 use tokio::runtime::Runtime;
 use tokio::sync::{RwLock as ARwLock, Mutex as AMutex, RwLock};
+use tower_lsp::lsp_types::{Position, TextDocumentContentChangeEvent};
 
 use tracing::info;
+use tree_sitter::{InputEdit, Point};
 use url::Url;
 use walkdir::WalkDir;
 use which::which;
 
+use crate::ast::treesitter::document_trees::{DocumentTrees, parse_document_tree};
+use crate::ast::treesitter::parsers::get_parser_by_filename;
+use crate::files_priority::FilesPriority;
 use crate::global_context;
 use crate::telemetry;
 use crate::vecdb::file_filter::is_valid_file;
@@ -33,6 +38,75 @@ impl Document {
     pub fn new(language_id: String, text: Rope) -> Self {
         Self { language_id, text }
     }
+
+    pub fn apply_change(&mut self, change: &TextDocumentContentChangeEvent) -> Option<InputEdit> {
+        // the same change for the tree-sitter tree of the document, None if the whole text is replaced
+        match change.range {
+            Some(range) => {
+                let start = utf16_position_to_char_idx(&self.text, &range.start);
+                let end = utf16_position_to_char_idx(&self.text, &range.end).max(start);
+                let start_byte = self.text.char_to_byte(start);
+                let old_end_byte = self.text.char_to_byte(end);
+                let start_position = byte_to_point(&self.text, start_byte);
+                let old_end_position = byte_to_point(&self.text, old_end_byte);
+                self.text.remove(start..end);
+                self.text.insert(start, &change.text);
+                let new_end_byte = start_byte + change.text.len();
+                Some(InputEdit {
+                    start_byte,
+                    old_end_byte,
+                    new_end_byte,
+                    start_position,
+                    old_end_position,
+                    new_end_position: byte_to_point(&self.text, new_end_byte),
+                })
+            }
+            None => {
+                self.text = Rope::from_str(&change.text);
+                None
+            }
+        }
+    }
+}
+
+fn byte_to_point(text: &Rope, byte: usize) -> Point {
+    // tree-sitter columns are in bytes
+    let line = text.byte_to_line(byte);
+    Point::new(line, byte - text.line_to_byte(line))
+}
+
+fn line_len_chars(text: &Rope, line: usize) -> usize {
+    // without the line break
+    let slice = text.line(line);
+    let mut len = slice.len_chars();
+    if len > 0 && slice.char(len - 1) == '\n' {
+        len -= 1;
+        if len > 0 && slice.char(len - 1) == '\r' {
+            len -= 1;
+        }
+    }
+    len
+}
+
+pub fn utf16_position_to_char_idx(text: &Rope, position: &Position) -> usize {
+    // LSP positions count UTF-16 code units, a character past the end of the line means the end of the line
+    let line = position.line as usize;
+    if line >= text.len_lines() {
+        return text.len_chars();
+    }
+    let line_start = text.line_to_char(line);
+    let line_end = line_start + line_len_chars(text, line);
+    let line_start_cu = text.char_to_utf16_cu(line_start);
+    let line_end_cu = text.char_to_utf16_cu(line_end);
+    let cu = (line_start_cu + position.character as usize).min(line_end_cu);
+    text.utf16_cu_to_char(cu)
+}
+
+pub fn char_idx_to_utf16_position(text: &Rope, char_idx: usize) -> Position {
+    let char_idx = char_idx.min(text.len_chars());
+    let line = text.char_to_line(char_idx);
+    let character = text.char_to_utf16_cu(char_idx) - text.char_to_utf16_cu(text.line_to_char(line));
+    Position::new(line as u32, character as u32)
 }
 
 #[derive(Debug, Clone, Eq)]
@@ -115,6 +189,8 @@ pub struct DocumentsState {
     pub cache_correction: Arc<HashMap<String, String>>,  // map dir3/file.ext -> to /dir1/dir2/dir3/file.ext
     pub cache_fuzzy: Arc<Vec<String>>,                   // slow linear search
     pub fs_watcher: Arc<ARwLock<RecommendedWatcher>>,
+    pub document_trees: Arc<StdMutex<DocumentTrees>>,  // last parsed tree of each open document, reused by the next parse
+    pub files_priority: Arc<StdMutex<FilesPriority>>,  // AST and vecdb queues index the files the user works on first
 }
 
 
@@ -129,6 +205,8 @@ impl DocumentsState {
             cache_correction: Arc::new(HashMap::<String, String>::new()),
             cache_fuzzy: Arc::new(Vec::<String>::new()),
             fs_watcher: Arc::new(ARwLock::new(watcher)),
+            document_trees: Arc::new(StdMutex::new(DocumentTrees::new())),
+            files_priority: Arc::new(StdMutex::new(FilesPriority::new())),
         }
     }
 
@@ -380,12 +458,13 @@ pub async fn on_did_open(
     language_id: &String,
 ) {
     let doc = Document::new(language_id.clone(), Rope::from_str(&text));
-    let (document_map_arc, cache_dirty_arc) = {
+    let (document_map_arc, cache_dirty_arc, files_priority_arc) = {
         let gcx_locked = gcx.read().await;
-        (gcx_locked.documents_state.document_map.clone(), gcx_locked.documents_state.cache_dirty.clone())
+        (gcx_locked.documents_state.document_map.clone(), gcx_locked.documents_state.cache_dirty.clone(), gcx_locked.documents_state.files_priority.clone())
     };
     let doc_info = DocumentInfo { uri: file_url.clone(), document: Some(doc.clone()) };
     info!("on_did_open {}", crate::nicer_logs::last_n_chars(&doc_info.get_path().display().to_string(), 30));
+    files_priority_arc.lock().unwrap().open(&doc_info.get_path());
     {
         let mut document_map_locked = document_map_arc.write().await;
         document_map_locked.insert(file_url.clone(), doc);
@@ -397,32 +476,73 @@ pub async fn on_did_change(
     gcx: Arc<ARwLock<global_context::GlobalContext>>,
     file_url: &Url,
     text: &String,
+) {
+    let change = TextDocumentContentChangeEvent { range: None, range_length: None, text: text.clone() };
+    on_did_change_incremental(gcx, file_url, &[change]).await
+}
+
+pub async fn on_did_change_incremental(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    file_url: &Url,
+    changes: &[TextDocumentContentChangeEvent],
 ) {
     let t0 = Instant::now();
-    let (document_map_arc, cache_dirty_arc) = {
+    let (document_map_arc, cache_dirty_arc, document_trees_arc, files_priority_arc) = {
         let gcx_locked = gcx.read().await;
-        (gcx_locked.documents_state.document_map.clone(), gcx_locked.documents_state.cache_dirty.clone())
+        (gcx_locked.documents_state.document_map.clone(), gcx_locked.documents_state.cache_dirty.clone(),
+         gcx_locked.documents_state.document_trees.clone(), gcx_locked.documents_state.files_priority.clone())
     };
     let mut mark_dirty: bool = false;
-    let doc_info = {
+    let (doc_info, edits) = {
         let mut document_map_locked = document_map_arc.write().await;
-        let doc = if document_map_locked.contains_key(file_url) {
+        let (doc, edits) = if document_map_locked.contains_key(file_url) {
             let tmp = document_map_locked.get_mut(file_url).unwrap();
-            tmp.text = Rope::from_str(&text);
-            tmp.clone()
+            let edits: Vec<Option<InputEdit>> = changes.iter().map(|x| tmp.apply_change(x)).collect();
+            (tmp.clone(), edits)
         } else {
             info!("WARNING: file {} reported changed, but this binary has no record of this file.", crate::nicer_logs::last_n_chars(&file_url.path().to_string(), 30));
-            let tmp = &Document::new("unknown".to_owned(), Rope::from_str(&text));
+            // ranged changes apply to what the IDE had before, the file on disk is the best guess
+            let mut tmp = Document::new("unknown".to_owned(), Rope::new());
+            if changes.first().map(|x| x.range.is_some()).unwrap_or(false) {
+                let on_disk = DocumentInfo::new(file_url.clone()).read_file().await.unwrap_or_default();
+                tmp.text = Rope::from_str(&on_disk);
+            }
+            changes.iter().for_each(|x| { tmp.apply_change(x); });
             document_map_locked.insert(file_url.clone(), tmp.clone());
             mark_dirty = true;
-            tmp.clone()
+            (tmp, vec![None])
         };
-        DocumentInfo { uri: file_url.clone(), document: Some(doc.clone()) }
+        (DocumentInfo { uri: file_url.clone(), document: Some(doc) }, edits)
     };
     if mark_dirty {
         *(cache_dirty_arc.lock().await) = true;
     }
+    // tree-sitter and telemetry want the whole text, one copy for both
+    let text = doc_info.document.as_ref().map(|x| x.text.to_string()).unwrap_or_default();
+    let has_tree = {
+        let mut document_trees = document_trees_arc.lock().unwrap();
+        match edits.into_iter().collect::<Option<Vec<InputEdit>>>() {
+            Some(edits) => document_trees.edit(file_url, &edits, &text),
+            None => {
+                // the whole text is new, nothing of the old tree can be reused
+                document_trees.forget(file_url);
+                document_trees.edit(file_url, &[], &text)
+            }
+        }
+    };
+    files_priority_arc.lock().unwrap().touch(&doc_info.get_path());
     if is_valid_file(&doc_info.get_path()).is_ok() {
+        if has_tree {
+            // only the edited part is parsed again, the AST indexer and the completion pick the tree up from here
+            let (document_trees_arc, uri, code) = (document_trees_arc.clone(), file_url.clone(), text.clone());
+            tokio::task::spawn_blocking(move || {
+                if let Ok(mut parser) = get_parser_by_filename(&uri.to_file_path().unwrap_or_default()) {
+                    if let Err(e) = parse_document_tree(&document_trees_arc, &uri, parser.as_mut(), &code) {
+                        info!("cannot parse {}: {}", crate::nicer_logs::last_n_chars(&uri.path().to_string(), 30), e);
+                    }
+                }
+            });
+        }
         let (ast_module, vecdb_module) = {
             let cx_locked = gcx.read().await;
             (cx_locked.ast_module.clone(), cx_locked.vec_db.clone())
@@ -439,23 +559,59 @@ pub async fn on_did_change(
     telemetry::snippets_collection::sources_changed(
         gcx.clone(),
         &doc_info.uri.to_file_path().unwrap_or_default().to_string_lossy().to_string(),
-        text,
+        &text,
     ).await;
-    info!("on_did_change {}, total time {:.3}s", crate::nicer_logs::last_n_chars(&file_url.path().to_string(), 30), t0.elapsed().as_secs_f32());
+    info!("on_did_change {}, {} changes, total time {:.3}s", crate::nicer_logs::last_n_chars(&file_url.path().to_string(), 30), changes.len(), t0.elapsed().as_secs_f32());
+}
+
+fn known_files_under(known: &Vec<Url>, url: &Url) -> Vec<Url> {
+    // a deleted directory is not on disk anymore, its files are the ones we know to be under it
+    let mut result: Vec<Url> = match url.to_file_path() {
+        Ok(root) => known.iter()
+            .filter(|x| x.to_file_path().map(|path| path.starts_with(&root)).unwrap_or(false))
+            .cloned()
+            .collect(),
+        Err(_) => vec![],
+    };
+    if !result.contains(url) {
+        result.push(url.clone());
+    }
+    result
 }
 
 pub async fn on_did_delete(
     gcx: Arc<ARwLock<global_context::GlobalContext>>,
-    file_url: &Url,
+    file_urls: &Vec<Url>,
 ) {
-    info!("on_did_delete {}", crate::nicer_logs::last_n_chars(&file_url.path().to_string(), 30));
-    let cache_dirty_arc = {
+    let (cache_dirty_arc, deleted) = {
         let gcx_locked = gcx.read().await;
-        let document_map = &gcx_locked.documents_state.document_map;
-        let mut document_map_locked = document_map.write().await;
-        document_map_locked.remove(file_url);
-        gcx_locked.documents_state.cache_dirty.clone()
+        let documents_state = &gcx_locked.documents_state;
+        let mut document_map_locked = documents_state.document_map.write().await;
+        let mut workspace_files = documents_state.workspace_files.lock().unwrap();
+        let mut known: Vec<Url> = workspace_files.clone();
+        known.extend(document_map_locked.keys().cloned());
+        let mut deleted: Vec<Url> = vec![];
+        for file_url in file_urls.iter() {
+            for url in known_files_under(&known, file_url) {
+                if !deleted.contains(&url) {
+                    deleted.push(url);
+                }
+            }
+        }
+        let deleted_set: HashSet<&Url> = deleted.iter().collect();
+        workspace_files.retain(|x| !deleted_set.contains(x));
+        let mut document_trees = documents_state.document_trees.lock().unwrap();
+        let mut files_priority = documents_state.files_priority.lock().unwrap();
+        for url in deleted.iter() {
+            document_map_locked.remove(url);
+            document_trees.forget(url);
+            if let Ok(path) = url.to_file_path() {
+                files_priority.close(&path);
+            }
+        }
+        (documents_state.cache_dirty.clone(), deleted)
     };
+    info!("on_did_delete {} urls, {} files", file_urls.len(), deleted.len());
     *(cache_dirty_arc.lock().await) = true;
     let (ast_module, vecdb_module) = {
         let cx_locked = gcx.read().await;
@@ -464,8 +620,9 @@ pub async fn on_did_delete(
     {
         match *vecdb_module.lock().await {
             Some(ref mut db) => {
-                let file_path = PathBuf::from(file_url.path());
-                db.remove_file(&file_path).await
+                for url in deleted.iter() {
+                    db.remove_file(&PathBuf::from(url.path())).await;
+                }
             }
             None => {}
         };
@@ -473,17 +630,131 @@ pub async fn on_did_delete(
     {
         match *ast_module.lock().await {
             Some(ref mut ast) => {
-                let doc = DocumentInfo {
-                    uri: file_url.clone(),
-                    document: None,
-                };
-                ast.remove_file(&doc).await
+                let docs: Vec<DocumentInfo> = deleted.iter().map(|x| DocumentInfo::new(x.clone())).collect();
+                ast.remove_files(&docs).await
             }
             None => {}
         };
     }
 }
 
+fn files_under(path: &PathBuf) -> Vec<PathBuf> {
+    // a file, or every file of a directory, that we would index
+    if path.is_file() {
+        return vec![path.clone()];
+    }
+    WalkDir::new(path).into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path().to_path_buf())
+        .filter(|x| x.is_file() && !is_this_inside_blacklisted_dir(x))
+        .collect()
+}
+
+pub async fn on_did_create(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    file_urls: &Vec<Url>,
+) {
+    let docs: Vec<DocumentInfo> = file_urls.iter()
+        .filter_map(|x| x.to_file_path().ok())
+        .flat_map(|x| files_under(&x))
+        .filter(|x| is_valid_file(x).is_ok())
+        .filter_map(|x| DocumentInfo::from_pathbuf(&x).ok())
+        .collect();
+    info!("on_did_create {} files", docs.len());
+    if docs.is_empty() {
+        return;
+    }
+    {
+        let gcx_locked = gcx.read().await;
+        let mut workspace_files = gcx_locked.documents_state.workspace_files.lock().unwrap();
+        for doc in docs.iter() {
+            if !workspace_files.contains(&doc.uri) {
+                workspace_files.push(doc.uri.clone());
+            }
+        }
+    }
+    *gcx.read().await.documents_state.cache_dirty.lock().await = true;
+    enqueue_files(gcx, docs).await;
+}
+
+pub async fn on_did_rename(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    old_url: &Url,
+    new_url: &Url,
+) {
+    // the file is already at the new path, a renamed directory moves every file in it
+    let (old_root, new_root) = match (old_url.to_file_path(), new_url.to_file_path()) {
+        (Ok(old_root), Ok(new_root)) => (old_root, new_root),
+        _ => return,
+    };
+    let renamed: Vec<(PathBuf, PathBuf)> = files_under(&new_root).into_iter()
+        .filter_map(|new_path| {
+            let relative = new_path.strip_prefix(&new_root).ok()?;
+            let old_path = if relative.as_os_str().is_empty() { old_root.clone() } else { old_root.join(relative) };
+            Some((old_path, new_path))
+        })
+        .collect();
+    info!("on_did_rename {} -> {}, {} files", crate::nicer_logs::last_n_chars(&old_root.display().to_string(), 30),
+        crate::nicer_logs::last_n_chars(&new_root.display().to_string(), 30), renamed.len());
+
+    let mut docs: Vec<DocumentInfo> = vec![];
+    {
+        let gcx_locked = gcx.read().await;
+        let documents_state = &gcx_locked.documents_state;
+        let mut document_map_locked = documents_state.document_map.write().await;
+        let mut workspace_files = documents_state.workspace_files.lock().unwrap();
+        let mut document_trees = documents_state.document_trees.lock().unwrap();
+        let mut files_priority = documents_state.files_priority.lock().unwrap();
+        for (old_path, new_path) in renamed.iter() {
+            let (old_uri, new_uri) = match (pathbuf_to_url(old_path), pathbuf_to_url(new_path)) {
+                (Ok(old_uri), Ok(new_uri)) => (old_uri, new_uri),
+                _ => continue,
+            };
+            // the IDE keeps the buffer open under the new name
+            if let Some(document) = document_map_locked.remove(&old_uri) {
+                document_map_locked.insert(new_uri.clone(), document);
+            }
+            document_trees.forget(&old_uri);
+            files_priority.close(old_path);
+            workspace_files.retain(|x| *x != old_uri);
+            if is_valid_file(new_path).is_ok() {
+                if !workspace_files.contains(&new_uri) {
+                    workspace_files.push(new_uri.clone());
+                }
+                docs.push(DocumentInfo::new(new_uri));
+            }
+        }
+    }
+    *gcx.read().await.documents_state.cache_dirty.lock().await = true;
+
+    let (ast_module, vecdb_module) = {
+        let cx_locked = gcx.read().await;
+        (cx_locked.ast_module.clone(), cx_locked.vec_db.clone())
+    };
+    match *ast_module.lock().await {
+        Some(ref mut ast) => {
+            let renamed_docs: Vec<(DocumentInfo, DocumentInfo)> = renamed.iter()
+                .filter_map(|(old_path, new_path)| Some((DocumentInfo::from_pathbuf(old_path).ok()?, DocumentInfo::from_pathbuf(new_path).ok()?)))
+                .collect();
+            // the AST compares the content hashes of the files it parsed, the rest count as changed,
+            // for those the vectorizer reuses the windows it has anyway
+            let changed: HashSet<Url> = ast.rename_files(&renamed_docs).await.into_iter().map(|x| x.uri).collect();
+            docs.retain(|x| changed.contains(&x.uri));
+        }
+        None => {}
+    };
+    match *vecdb_module.lock().await {
+        Some(ref mut db) => {
+            for (old_path, new_path) in renamed.iter() {
+                db.rename_file(old_path, new_path).await;
+            }
+        }
+        None => {}
+    };
+    // only the files that changed along with the rename
+    enqueue_files(gcx, docs).await;
+}
+
 pub async fn add_folder(gcx: Arc<ARwLock<GlobalContext>>, path: &PathBuf) {
     {
         let documents_state = &mut gcx.write().await.documents_state;
@@ -494,6 +765,13 @@ pub async fn add_folder(gcx: Arc<ARwLock<GlobalContext>>, path: &PathBuf) {
 
     let (ast_module, vecdb_module) = {
         let cx_locked = gcx.read().await;
+        {
+            *cx_locked.documents_state.cache_dirty.lock().await = true;
+        }
+        // the new folder might be inside one we already have, its files are known then
+        let workspace_files: &mut Vec<Url> = &mut cx_locked.documents_state.workspace_files.lock().unwrap();
+        let known: HashSet<Url> = workspace_files.iter().cloned().collect();
+        workspace_files.extend(docs.iter().map(|x| x.uri.clone()).filter(|x| !known.contains(x)));
         (cx_locked.ast_module.clone(), cx_locked.vec_db.clone())
     };
     match *ast_module.lock().await {
@@ -572,3 +850,55 @@ pub async fn file_watcher_thread(event: Event, gcx: Weak<RwLock<GlobalContext>>)
         _ => {}
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::Range;
+
+    use super::*;
+
+    fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_document_apply_change_utf16() {
+        // "😀" is two UTF-16 code units, "é" is one
+        let mut doc = Document::new("python".to_string(), Rope::from_str("s = \"😀é\"\r\nprint(s)\n"));
+        doc.apply_change(&change((0, 7), (0, 8), "e"));
+        assert_eq!(doc.text.to_string(), "s = \"😀e\"\r\nprint(s)\n");
+        doc.apply_change(&change((0, 9), (1, 0), "\n"));
+        assert_eq!(doc.text.to_string(), "s = \"😀e\"\nprint(s)\n");
+        // past the end of the line is the end of the line, the line break stays
+        doc.apply_change(&change((1, 8), (1, 100), "  # ok"));
+        assert_eq!(doc.text.to_string(), "s = \"😀e\"\nprint(s)  # ok\n");
+        doc.apply_change(&change((2, 0), (2, 0), "exit()"));
+        assert_eq!(doc.text.to_string(), "s = \"😀e\"\nprint(s)  # ok\nexit()");
+        doc.apply_change(&TextDocumentContentChangeEvent { range: None, range_length: None, text: "x".to_string() });
+        assert_eq!(doc.text.to_string(), "x");
+    }
+
+    #[test]
+    fn test_utf16_positions_roundtrip() {
+        let text = Rope::from_str("a😀b\nc\n");
+        let idx = utf16_position_to_char_idx(&text, &Position::new(0, 3));
+        assert_eq!(idx, 2);
+        assert_eq!(char_idx_to_utf16_position(&text, idx), Position::new(0, 3));
+        assert_eq!(char_idx_to_utf16_position(&text, 5), Position::new(1, 1));
+        assert_eq!(utf16_position_to_char_idx(&text, &Position::new(5, 0)), text.len_chars());
+    }
+
+    #[test]
+    fn test_known_files_under() {
+        let url = |path: &str| Url::from_file_path(path).unwrap();
+        let known = vec![url("/tmp/ws/src/a.rs"), url("/tmp/ws/src/sub/b.rs"), url("/tmp/ws/srcx/c.rs"), url("/tmp/ws/d.rs")];
+        assert_eq!(known_files_under(&known, &url("/tmp/ws/src")), vec![url("/tmp/ws/src/a.rs"), url("/tmp/ws/src/sub/b.rs"), url("/tmp/ws/src")]);
+        assert_eq!(known_files_under(&known, &url("/tmp/ws/d.rs")), vec![url("/tmp/ws/d.rs")]);
+        assert_eq!(known_files_under(&known, &url("/tmp/ws/e.rs")), vec![url("/tmp/ws/e.rs")]);
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use crate::files_in_workspace::DocumentInfo;
+
+const RECENT_FILES_MAX: usize = 20;
+
+
+// What the user works on right now is indexed first: open and recently edited files, then the
+// files next to them, then everything else. The indexing queues sort themselves again when the
+// generation changes.
+#[derive(Debug, Default)]
+pub struct FilesPriority {
+    recent: VecDeque<PathBuf>,  // opened or edited, the latest first
+    open: HashSet<PathBuf>,
+    generation: u64,
+}
+
+impl FilesPriority {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn touch(&mut self, path: &PathBuf) {
+        // opened, edited or switched to
+        if self.recent.front() == Some(path) {
+            return;
+        }
+        self.recent.retain(|x| x != path);
+        self.recent.push_front(path.clone());
+        self.recent.truncate(RECENT_FILES_MAX);
+        self.generation += 1;
+    }
+
+    pub fn open(&mut self, path: &PathBuf) {
+        self.open.insert(path.clone());
+        self.touch(path);
+    }
+
+    pub fn close(&mut self, path: &PathBuf) {
+        if self.open.remove(path) {
+            self.generation += 1;
+        }
+    }
+
+    pub fn sort<T, F>(&self, items: &mut [T], get_path: F)
+        where F: Fn(&T) -> PathBuf
+    {
+        // stable, the order within the same rank stays as it was enqueued
+        let dirs: HashSet<&Path> = self.recent.iter().chain(self.open.iter()).filter_map(|x| x.parent()).collect();
+        items.sort_by_cached_key(|x| self.rank(&get_path(x), &dirs));
+    }
+
+    pub fn sort_docs(&self, docs: &mut [DocumentInfo]) {
+        self.sort(docs, |x| x.get_path());
+    }
+
+    fn rank(&self, path: &PathBuf, dirs: &HashSet<&Path>) -> usize {
+        if let Some(pos) = self.recent.iter().position(|x| x == path) {
+            return pos;
+        }
+        if self.open.contains(path) {
+            return RECENT_FILES_MAX;
+        }
+        match path.parent() {
+            Some(parent) if dirs.contains(parent) => RECENT_FILES_MAX + 1,
+            _ => RECENT_FILES_MAX + 2,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files_priority_sort() {
+        let mut priority = FilesPriority::new();
+        let mut paths: Vec<PathBuf> = vec![
+            "/ws/a/1.py", "/ws/b/2.py", "/ws/c/3.py", "/ws/b/4.py", "/ws/a/5.py",
+        ].into_iter().map(PathBuf::from).collect();
+
+        priority.open(&PathBuf::from("/ws/c/3.py"));
+        priority.touch(&PathBuf::from("/ws/b/2.py"));
+        priority.sort(&mut paths, |x| x.clone());
+        let sorted: Vec<&str> = paths.iter().map(|x| x.to_str().unwrap()).collect();
+        assert_eq!(sorted, vec!["/ws/b/2.py", "/ws/c/3.py", "/ws/b/4.py", "/ws/a/1.py", "/ws/a/5.py"]);
+
+        // the user switched to another file
+        let generation = priority.generation();
+        priority.touch(&PathBuf::from("/ws/a/5.py"));
+        assert!(priority.generation() > generation);
+        priority.close(&PathBuf::from("/ws/c/3.py"));
+        priority.sort(&mut paths, |x| x.clone());
+        let sorted: Vec<&str> = paths.iter().map(|x| x.to_str().unwrap()).collect();
+        assert_eq!(sorted, vec!["/ws/a/5.py", "/ws/b/2.py", "/ws/c/3.py", "/ws/b/4.py", "/ws/a/1.py"]);
+    }
+}
@@ -16,11 +16,14 @@ use tokio::sync::RwLock as ARwLock;
 use tracing::{error, info};
 
 use crate::ast::ast_module::AstModule;
+use crate::at_commands::at_pin::ContextPin;
+use crate::background_tasks::BackgroundTasksHolder;
 use crate::caps::CodeAssistantCaps;
 use crate::completion_cache::CompletionCache;
 use crate::custom_error::ScratchError;
 use crate::files_in_workspace::DocumentsState;
 use crate::telemetry::telemetry_structs;
+use crate::toolbox::toolbox_config::ToolboxConfigCache;
 use crate::vecdb::vecdb::VecDb;
 
 #[derive(Debug, StructOpt, Clone)]
@@ -57,6 +60,10 @@ pub struct CommandLine {
     pub vecdb_forced_path: String,
     #[structopt(long, short="w", default_value="", help="Workspace folder to find files for vecdb and AST. An LSP or HTTP request can override this later.")]
     pub workspace_folder: String,
+    #[structopt(long, default_value="0.3", help="Share of the chat context budget that pinned context can take, see /v1/context-pins and @pin.")]
+    pub pins_budget_share: f32,
+    #[structopt(long, number_of_values=1, help="A program that custom at-commands from customization.yaml are allowed to run, repeat for several programs. The yaml itself can't allow anything.")]
+    pub allow_executable: Vec<String>,
 }
 impl CommandLine {
     fn create_hash(msg: String) -> String {
@@ -84,8 +91,12 @@ pub struct GlobalContext {
     pub telemetry: Arc<StdRwLock<telemetry_structs::Storage>>,
     pub vec_db: Arc<AMutex<Option<VecDb>>>,
     pub ast_module: Arc<AMutex<Option<AstModule>>>,   // TODO: don't use AMutex, use StdMutex
+    pub ast_background_tasks: Arc<AMutex<BackgroundTasksHolder>>,    // replaced when AST is switched on or off
+    pub vecdb_background_tasks: Arc<AMutex<BackgroundTasksHolder>>,  // same for vecdb
     pub ask_shutdown_sender: Arc<StdMutex<std::sync::mpsc::Sender<String>>>,
     pub documents_state: DocumentsState,
+    pub context_pins: Arc<AMutex<HashMap<String, Vec<ContextPin>>>>,  // chat_id -> pins
+    pub toolbox_config_cache: Arc<AMutex<ToolboxConfigCache>>,  // customization.yaml, reloaded when it changes on disk
 }
 
 pub type SharedGlobalContext = Arc<ARwLock<GlobalContext>>;  // TODO: remove this type alias, confusing
@@ -121,8 +132,10 @@ pub async fn try_load_caps_quickly_if_not_present(
             let global_context_locked = global_context.write().await;
             return Err(ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, global_context_locked.caps_last_error.clone()));
         }
+        // not from_args(), the address and the key can be changed at runtime, see /v1/settings
+        let cmdline = global_context.read().await.cmdline.clone();
         let caps_result = crate::caps::load_caps(
-            CommandLine::from_args(),
+            cmdline,
             global_context.clone()
         ).await;
         {
@@ -213,6 +226,14 @@ pub async fn create_global_context(
     cache_dir: PathBuf,
 ) -> (Arc<ARwLock<GlobalContext>>, std::sync::mpsc::Receiver<String>, CommandLine) {
     let cmdline = CommandLine::from_args();
+    let (gcx, ask_shutdown_receiver) = create_global_context_with_cmdline(cache_dir, cmdline.clone()).await;
+    (gcx, ask_shutdown_receiver, cmdline)
+}
+
+pub async fn create_global_context_with_cmdline(
+    cache_dir: PathBuf,
+    cmdline: CommandLine,
+) -> (Arc<ARwLock<GlobalContext>>, std::sync::mpsc::Receiver<String>) {
     let (ask_shutdown_sender, ask_shutdown_receiver) = std::sync::mpsc::channel::<String>();
     let mut http_client_builder = reqwest::Client::builder();
     if cmdline.insecure {
@@ -234,8 +255,12 @@ pub async fn create_global_context(
         telemetry: Arc::new(StdRwLock::new(telemetry_structs::Storage::new())),
         vec_db: Arc::new(AMutex::new(None)),
         ast_module: Arc::new(AMutex::new(None)),
+        ast_background_tasks: Arc::new(AMutex::new(BackgroundTasksHolder::new(vec![]))),
+        vecdb_background_tasks: Arc::new(AMutex::new(BackgroundTasksHolder::new(vec![]))),
         ask_shutdown_sender: Arc::new(StdMutex::new(ask_shutdown_sender)),
-        documents_state: DocumentsState::empty(if cmdline.workspace_folder.is_empty() { vec![] } else { vec![PathBuf::from(cmdline.workspace_folder.clone())] })
+        documents_state: DocumentsState::empty(if cmdline.workspace_folder.is_empty() { vec![] } else { vec![PathBuf::from(cmdline.workspace_folder.clone())] }),
+        context_pins: Arc::new(AMutex::new(HashMap::new())),
+        toolbox_config_cache: Arc::new(AMutex::new(ToolboxConfigCache::default())),
     };
     let gcx = Arc::new(ARwLock::new(cx));
     if cmdline.ast {
@@ -248,5 +273,22 @@ pub async fn create_global_context(
         gcx.write().await.documents_state.init_watcher(gcx.clone());
     }
 
-    (gcx, ask_shutdown_receiver, cmdline)
+    (gcx, ask_shutdown_receiver)
+}
+
+#[cfg(test)]
+pub async fn create_global_context_for_tests(cache_dir: PathBuf) -> Arc<ARwLock<GlobalContext>> {
+    // no workspace, no AST, no vecdb, the address is never contacted
+    let cmdline = CommandLine::from_iter(&["refact-lsp", "--address-url", "http://127.0.0.1:1"]);
+    create_global_context_with_cmdline(cache_dir, cmdline).await.0
+}
+
+#[cfg(test)]
+pub fn words_tokenizer_for_tests() -> Arc<StdRwLock<Tokenizer>> {
+    // every word and every punctuation mark is one token
+    use std::str::FromStr;
+    let tokenizer = Tokenizer::from_str(r#"{"version": "1.0", "truncation": null, "padding": null, "added_tokens": [], "normalizer": null,
+        "pre_tokenizer": {"type": "Whitespace"}, "post_processor": null, "decoder": null,
+        "model": {"type": "WordLevel", "vocab": {"[UNK]": 0}, "unk_token": "[UNK]"}}"#).unwrap();
+    Arc::new(StdRwLock::new(tokenizer))
 }
@@ -14,7 +14,7 @@ use crate::global_context::SharedGlobalContext;
 use crate::http::routers::v1::ast::{handle_v1_ast_declarations_cursor_search, handle_v1_ast_declarations_query_search,
                                     handle_v1_ast_references_cursor_search, handle_v1_ast_references_query_search,
                                     handle_v1_ast_file_symbols, handle_v1_ast_index_file,
-                                    handle_v1_ast_clear_index};
+                                    handle_v1_ast_clear_index, handle_v1_ast_status};
 use crate::http::routers::v1::caps::handle_v1_caps;
 use crate::http::routers::v1::chat::handle_v1_chat;
 use crate::http::routers::v1::code_completion::handle_v1_code_completion_web;
@@ -29,6 +29,8 @@ use crate::http::utils::telemetry_wrapper;
 use crate::http::routers::v1::dashboard::get_dashboard_plots;
 use crate::http::routers::v1::vecdb::{handle_v1_vecdb_search, handle_v1_vecdb_status, handle_v1_vecdb_caps};
 use crate::http::routers::v1::at_commands::{handle_v1_command_completion, handle_v1_command_preview};
+use crate::http::routers::v1::context_pins::handle_v1_context_pins;
+use crate::http::routers::v1::settings::{handle_v1_settings_get, handle_v1_settings_post};
 
 pub mod code_completion;
 pub mod chat;
@@ -41,7 +43,9 @@ pub mod lsp_like_handlers;
 pub mod toolbox;
 pub mod vecdb;
 mod at_commands;
+mod context_pins;
 mod ast;
+mod settings;
 
 pub fn make_v1_router() -> Router {
     Router::new()
@@ -52,12 +56,14 @@ pub fn make_v1_router() -> Router {
 
         .route("/caps", telemetry_get!(handle_v1_caps))
         .route("/graceful-shutdown", telemetry_get!(handle_v1_graceful_shutdown))
+        .route("/settings", telemetry_get!(handle_v1_settings_get).merge(telemetry_post!(handle_v1_settings_post)))
 
         .route("/vdb-search", telemetry_post!(handle_v1_vecdb_search))
         .route("/vdb-status", telemetry_get!(handle_v1_vecdb_status))
         .route("/vdb-caps", telemetry_get!(handle_v1_vecdb_caps))
         .route("/at-command-completion", telemetry_post!(handle_v1_command_completion))
         .route("/at-command-preview", telemetry_post!(handle_v1_command_preview))
+        .route("/context-pins", telemetry_post!(handle_v1_context_pins))
 
         .route("/lsp-initialize", telemetry_post!(handle_v1_lsp_initialize))
         .route("/lsp-did-changed", telemetry_post!(handle_v1_lsp_did_change))
@@ -73,6 +79,7 @@ pub fn make_v1_router() -> Router {
         .route("/ast-file-symbols", telemetry_post!(handle_v1_ast_file_symbols))
         .route("/ast-index-file", telemetry_post!(handle_v1_ast_index_file))
         .route("/ast-clear-index", telemetry_post!(handle_v1_ast_clear_index))
+        .route("/ast-status", telemetry_get!(handle_v1_ast_status))
 
         // experimental
         .route("/customization", telemetry_get!(handle_v1_customization))
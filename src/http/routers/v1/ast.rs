@@ -311,3 +311,22 @@ pub async fn handle_v1_ast_clear_index(
     };
     x
 }
+
+pub async fn handle_v1_ast_status(
+    Extension(global_context): Extension<SharedGlobalContext>,
+    _: hyper::body::Bytes,
+) -> Result<Response<Body>, ScratchError> {
+    let cx_locked = global_context.read().await;
+    let status = match *cx_locked.ast_module.lock().await {
+        Some(ref ast) => ast.ast_index_status().await,
+        None => {
+            return Err(ScratchError::new(
+                StatusCode::INTERNAL_SERVER_ERROR, "Ast module is not available".to_string(),
+            ));
+        }
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(serde_json::to_string_pretty(&status).unwrap()))
+        .unwrap())
+}
@@ -12,7 +12,9 @@ use tokenizers::Tokenizer;
 
 use crate::cached_tokenizers;
 use crate::at_commands::at_commands::AtCommandsContext;
-use crate::at_commands::query::QueryLine;
+use crate::at_commands::query::{QueryLine, QueryLineArg, quote_if_needed, tokenize};
+use crate::at_commands::utils::at_commands_args_limit;
+use crate::call_validation::ContextSelection;
 use crate::custom_error::ScratchError;
 use crate::global_context::GlobalContext;
 
@@ -35,6 +37,8 @@ struct CommandPreviewPost {
     query: String,
     #[serde(default)]
     model: String,
+    #[serde(default)]
+    context_selection: ContextSelection,
 }
 
 pub async fn handle_v1_command_completion(
@@ -51,7 +55,8 @@ pub async fn handle_v1_command_completion(
 
     if let Ok((query_line_val, cursor_rel, cursor_line_start)) = get_line_with_cursor(&post.query, post.cursor) {
         let query_line_val = query_line_val.chars().take(cursor_rel as usize).collect::<String>();
-        let query_line = QueryLine::new(query_line_val, cursor_rel, cursor_line_start);
+        let args_limit = at_commands_args_limit(&context).await;
+        let query_line = QueryLine::new(query_line_val, cursor_rel, cursor_line_start, |name| args_limit.get(name).cloned());
         (completions, is_cmd_executable, pos1, pos2) = command_completion(&query_line, &context, post.cursor, post.top_n).await;
     }
 
@@ -99,42 +104,43 @@ pub async fn handle_v1_command_preview(
         }
     };
 
-    let mut messages_for_postprocessing = vec![];
     let top_n = 5;
-    let at_context = AtCommandsContext::new(global_context.clone()).await;
-    let valid_commands = crate::at_commands::utils::find_valid_at_commands_in_query(&mut query, &at_context).await;
-    for cmd in valid_commands {
-        match cmd.command.lock().await.execute(&query, &cmd.args, top_n, &at_context).await {
-            Ok(msg) => {
-                messages_for_postprocessing.push(msg);
-            },
-            Err(e) => {
-                tracing::warn!("can't execute command that indicated it can execute: {}", e);
-            }
-        }
-    }
-    let processed = crate::scratchpads::chat_utils_rag::postprocess_at_results(
+    let mut at_context = AtCommandsContext::new(global_context.clone()).await;
+    at_context.dry_run = true;
+    at_context.tokens_limit = recommended_model_record.n_ctx;
+    let messages_for_postprocessing = crate::at_commands::utils::execute_at_commands_in_query(&mut query, &at_context, top_n).await;
+    let (processed, report) = crate::scratchpads::chat_utils_rag::postprocess_at_results_with_report(
         global_context.clone(),
         messages_for_postprocessing,
         tokenizer_arc.clone(),
         recommended_model_record.n_ctx,
+        &post.context_selection,
     ).await;
     let reloaded = crate::scratchpads::chat_utils_rag::reload_files(global_context.clone(), &processed, false).await;
 
+    // items tell the client what each hit costs and what happened to it, to pin or exclude them in /v1/chat
     Ok(Response::builder()
         .status(StatusCode::OK)
         .body(Body::from(serde_json::to_string(
-            &json!({"messages": reloaded, "model": model_name})
+            &json!({
+                "messages": reloaded,
+                "model": model_name,
+                "items": report.items,
+                "tokens_limit": report.tokens_limit,
+                "tokens_used": report.tokens_used,
+                "tokens_left": report.tokens_left,
+            })
         ).unwrap()))
         .unwrap())
 }
 
 fn get_line_with_cursor(query: &String, cursor: i64) -> Result<(String, i64, i64), ScratchError> {
+    // positions are in chars, the same as in at_commands::query
     let mut cursor_rel = cursor;
     for line in query.lines() {
-        let line_length = line.len() as i64;
+        let line_length = line.chars().count() as i64;
         if cursor_rel <= line_length {
-            if !line.starts_with("@") {
+            if !tokenize(line).iter().any(|t| t.is_command_like()) {
                 return Err(ScratchError::new(StatusCode::OK, "no command provided".to_string()));
             }
             return Ok((line.to_string(), cursor_rel, cursor - cursor_rel));
@@ -166,20 +172,33 @@ async fn command_completion(
         }
     };
 
-    let can_execute = cmd.lock().await.can_execute(&query_line.get_args().iter().map(|x|x.value.clone()).collect(), context).await;
-
-    for (arg, param) in query_line.get_args().iter().zip(cmd.lock().await.params()) {
+    // QueryLine took at most max_args() arguments, the rest is the question, the same way find_valid_at_commands_in_query sees it
+    let args: Vec<&QueryLineArg> = query_line.get_args();
+    let can_execute = cmd.lock().await.can_execute(&args.iter().map(|x|x.value.clone()).collect(), context).await;
+    let cmd_locked = cmd.lock().await;
+    let params = cmd_locked.params();
+
+    for (idx, arg) in args.iter().enumerate() {
+        // arguments past params() repeat the last param, or they are free text the command accepts as is
+        let param = match params.get(idx).or(params.last()) {
+            Some(x) => x,
+            None => break,
+        };
+        let free_text = idx >= params.len() && can_execute;
         let param_locked = param.lock().await;
         let is_valid = param_locked.is_value_valid(&arg.value, context).await;
         if !is_valid {
+            if free_text {
+                continue;
+            }
             return if arg.focused {
-                (param_locked.complete(&arg.value, context, top_n).await, can_execute, arg.pos1, arg.pos2)
+                (quote_completions(param_locked.complete(&arg.value, context, top_n).await), can_execute, arg.pos1, arg.pos2)
             } else {
                 (vec![], false, -1, -1)
             }
         }
         if is_valid && arg.focused && param_locked.complete_if_valid() {
-            return (param_locked.complete(&arg.value, context, top_n).await, can_execute, arg.pos1, arg.pos2);
+            return (quote_completions(param_locked.complete(&arg.value, context, top_n).await), can_execute, arg.pos1, arg.pos2);
         }
     }
 
@@ -188,10 +207,10 @@ async fn command_completion(
     }
 
     // if command is not focused, and the argument is empty we should make suggestions
-    if !q_cmd.focused {
-        match cmd.lock().await.params().get(query_line.get_args().len()) {
+    if !q_cmd.focused && args.len() < cmd_locked.max_args() {
+        match params.get(args.len()).or(params.last()) {
             Some(param) => {
-                return (param.lock().await.complete(&"".to_string(), context, top_n).await, false, cursor_abs, cursor_abs);
+                return (quote_completions(param.lock().await.complete(&"".to_string(), context, top_n).await), false, cursor_abs, cursor_abs);
             },
             None => {}
         }
@@ -200,6 +219,10 @@ async fn command_completion(
     (vec![], false, -1, -1)
 }
 
+fn quote_completions(completions: Vec<String>) -> Vec<String> {
+    // completions replace the whole argument, quote them so the query parser reads them back as one argument
+    completions.iter().map(|x| quote_if_needed(x)).collect()
+}
 
 async fn command_completion_options(
     q_cmd: &String,
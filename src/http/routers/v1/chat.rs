@@ -74,14 +74,27 @@ pub async fn handle_v1_chat(
     )?;
     // info!("chat prompt {:?}\n{}", t1.elapsed(), prompt);
     info!("chat prompt {:?}", t1.elapsed());
-    crate::restream::scratchpad_interaction_stream(
-        global_context.clone(),
-        scratchpad,
-        "chat-stream".to_string(),
-        prompt,
-        model_name,
-        client1,
-        api_key,
-        chat_post.parameters.clone(),
-    ).await
+    if chat_post.stream == Some(false) {
+        crate::restream::scratchpad_interaction_not_stream(
+            global_context.clone(),
+            scratchpad,
+            "chat".to_string(),
+            &prompt,
+            model_name,
+            client1,
+            api_key,
+            &chat_post.parameters,
+        ).await
+    } else {
+        crate::restream::scratchpad_interaction_stream(
+            global_context.clone(),
+            scratchpad,
+            "chat-stream".to_string(),
+            prompt,
+            model_name,
+            client1,
+            api_key,
+            chat_post.parameters.clone(),
+        ).await
+    }
 }
\ No newline at end of file
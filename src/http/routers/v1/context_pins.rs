@@ -0,0 +1,60 @@
+use axum::response::Result;
+use axum::Extension;
+use hyper::{Body, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::at_commands::at_pin::ContextPin;
+use crate::custom_error::ScratchError;
+use crate::global_context::SharedGlobalContext;
+
+
+#[derive(Serialize, Deserialize, Clone)]
+struct ContextPinsPost {
+    #[serde(default)]
+    chat_id: String,
+    #[serde(default)]
+    action: String,  // "list" (default), "add", "remove", "clear"
+    #[serde(default)]
+    pins: Vec<ContextPin>,
+}
+
+pub async fn handle_v1_context_pins(
+    Extension(global_context): Extension<SharedGlobalContext>,
+    body_bytes: hyper::body::Bytes,
+) -> Result<Response<Body>, ScratchError> {
+    let post = serde_json::from_slice::<ContextPinsPost>(&body_bytes)
+        .map_err(|e| ScratchError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("JSON problem: {}", e)))?;
+    if post.chat_id.is_empty() {
+        return Err(ScratchError::new(StatusCode::BAD_REQUEST, "chat_id is required, pins are kept per chat".to_string()));
+    }
+    for pin in post.pins.iter() {
+        if pin.file_name.is_empty() && pin.symbol.is_empty() && pin.note.is_empty() {
+            return Err(ScratchError::new(StatusCode::BAD_REQUEST, "a pin needs one of file_name, symbol or note".to_string()));
+        }
+    }
+    let pins_arc = global_context.read().await.context_pins.clone();
+    let mut pins_locked = pins_arc.lock().await;
+    let pins = pins_locked.entry(post.chat_id.clone()).or_default();
+    match post.action.as_str() {
+        "" | "list" => {},
+        "add" => {
+            for pin in post.pins.iter() {
+                if !pins.contains(pin) {
+                    pins.push(pin.clone());
+                }
+            }
+        },
+        "remove" => pins.retain(|x| !post.pins.contains(x)),
+        "clear" => pins.clear(),
+        _ => return Err(ScratchError::new(StatusCode::BAD_REQUEST, format!("unknown action {:?}, expected list, add, remove or clear", post.action))),
+    }
+    let pins = pins.clone();
+    if pins.is_empty() {
+        pins_locked.remove(&post.chat_id);
+    }
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(serde_json::to_string_pretty(&json!({"chat_id": post.chat_id, "pins": pins})).unwrap()))
+        .unwrap())
+}
@@ -0,0 +1,39 @@
+use axum::Extension;
+use axum::response::Result;
+use hyper::{Body, Response, StatusCode};
+
+use crate::custom_error::ScratchError;
+use crate::global_context::SharedGlobalContext;
+use crate::settings::{apply_settings, get_settings, SettingsPatch};
+
+
+pub async fn handle_v1_settings_get(
+    Extension(global_context): Extension<SharedGlobalContext>,
+    _: hyper::body::Bytes,
+) -> Result<Response<Body>, ScratchError> {
+    let settings = get_settings(global_context).await;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(serde_json::to_string_pretty(&settings).unwrap()))
+        .unwrap())
+}
+
+pub async fn handle_v1_settings_post(
+    Extension(global_context): Extension<SharedGlobalContext>,
+    body_bytes: hyper::body::Bytes,
+) -> Result<Response<Body>, ScratchError> {
+    // only the fields present change
+    let patch = serde_json::from_slice::<SettingsPatch>(&body_bytes)
+        .map_err(|e| ScratchError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("JSON problem: {}", e)))?;
+    // any web page can post to localhost, it must not point the binary to another server or read the key
+    if patch.changes_endpoint() {
+        return Err(ScratchError::new(StatusCode::FORBIDDEN,
+            "address_url and api_key can only be changed over LSP, workspace/didChangeConfiguration".to_string()));
+    }
+    let settings = apply_settings(global_context, patch).await
+        .map_err(|e| ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(serde_json::to_string_pretty(&settings).unwrap()))
+        .unwrap())
+}
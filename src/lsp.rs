@@ -2,24 +2,39 @@ use std::collections::HashMap;
 use std::fmt::Display;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use axum::Extension;
+use itertools::Itertools;
+use ropey::Rope;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tokio::net::TcpListener;
 use tokio::sync::RwLock as ARwLock;
 use tokio::task::JoinHandle;
-use tower_lsp::{ClientSocket, LanguageServer, LspService};
+use tower::{Service, ServiceExt};
+use tower_lsp::{ClientSocket, ExitedError, LanguageServer, LspService};
+use tower_lsp::jsonrpc;
 use tower_lsp::jsonrpc::{Error, Result};
 use tower_lsp::lsp_types::*;
 use tracing::{error, info, warn};
+use tree_sitter::Point;
 
-use crate::call_validation::{CodeCompletionInputs, CodeCompletionPost, CursorPosition, SamplingParameters};
+use crate::ast::comments_wrapper::get_language_id_by_filename;
+use crate::ast::structs::AstIndexState;
+use crate::ast::treesitter::structs::{SymbolDeclarationStruct, SymbolType};
+use crate::call_validation::{ChatMessage, CodeCompletionInputs, CodeCompletionPost, CursorPosition, SamplingParameters};
 use crate::files_in_workspace;
-use crate::files_in_workspace::on_did_delete;
+use crate::files_in_workspace::{char_idx_to_utf16_position, DocumentInfo, on_did_delete, utf16_position_to_char_idx};
 use crate::global_context;
 use crate::global_context::CommandLine;
+use crate::http::routers::v1::chat::handle_v1_chat;
 use crate::http::routers::v1::code_completion::handle_v1_code_completion;
+use crate::settings::{apply_settings, SettingsPatch};
 use crate::telemetry;
 use crate::telemetry::snippets_collection;
+use crate::toolbox::toolbox_config::{load_customization_cached, ToolboxCommand, ToolboxConfig};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -40,6 +55,8 @@ impl Display for APIError {
 pub struct Backend {
     pub gcx: Arc<ARwLock<global_context::GlobalContext>>,
     pub client: tower_lsp::Client,
+    pub client_capabilities: Arc<StdMutex<ClientCapabilities>>,  // from initialize()
+    pub inline_completion_dynamic_registration: Arc<AtomicBool>,  // from initialize(), lsp-types drops it from ClientCapabilities
 }
 
 
@@ -115,52 +132,96 @@ pub struct SuccessRes {
     pub success: bool,
 }
 
+// LSP 3.18 textDocument/inlineCompletion, lsp-types in tower-lsp 0.20 does not have it yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct InlineCompletionTriggerKind(u32);
+
+impl InlineCompletionTriggerKind {
+    pub const INVOKED: InlineCompletionTriggerKind = InlineCompletionTriggerKind(1);
+    pub const AUTOMATIC: InlineCompletionTriggerKind = InlineCompletionTriggerKind(2);
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectedCompletionInfo {
+    pub range: Range,  // what the completion selected in the popup will replace
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionContext {
+    pub trigger_kind: InlineCompletionTriggerKind,
+    pub selected_completion_info: Option<SelectedCompletionInfo>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionParams {
+    #[serde(flatten)]
+    pub text_document_position: TextDocumentPositionParams,
+    pub context: InlineCompletionContext,
+    #[serde(flatten)]
+    pub work_done_progress_params: WorkDoneProgressParams,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionItem {
+    pub insert_text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InlineCompletionList {
+    pub items: Vec<InlineCompletionItem>,
+}
+
+const INLINE_COMPLETION_ACCEPTED_COMMAND: &str = "refact.inlineCompletionAccepted";
+const INLINE_COMPLETION_MAX_NEW_TOKENS: usize = 50;
+
+// what codeAction/resolve needs to run the toolbox command, the client sends it back as is
+#[derive(Debug, Deserialize, Serialize)]
+struct ToolboxActionData {
+    toolbox_command: String,
+    uri: Url,
+    range: Range,
+}
+
 impl Backend {
+    async fn open_document_text(&self, uri: &Url) -> Result<String> {
+        let document_map = self.gcx.read().await.documents_state.document_map.clone();  // Arc::ARwLock
+        let document_map = document_map.read().await;
+        match document_map.get(uri) {
+            None => Err(internal_error("document not found")),
+            Some(doc) => Ok(doc.text.clone()),
+        }
+    }
+
     async fn flat_params_to_code_completion_post(&self, params: &CompletionParams1) -> Result<CodeCompletionPost> {
-        let txt = {
-            let document_map = self.gcx.read().await.documents_state.document_map.clone();  // Arc::ARwLock
-            let document_map = document_map.read().await;
-            let document = document_map.get(&params.text_document_position.text_document.uri);
-            match document {
-                None => {
-                    return Err(internal_error("document not found"));
-                }
-                Some(doc) => {
-                    doc.text.clone()
-                }
-            }
-        };
-        // url -> String method should be the same as in telemetry::snippets_collection::sources_changed
-        let path_string = params.text_document_position.text_document.uri.to_file_path().unwrap_or_default().to_string_lossy().to_string();
-        Ok(CodeCompletionPost {
-            inputs: CodeCompletionInputs {
-                sources: HashMap::from([(path_string.clone(), (&txt).to_string())]),
-                cursor: CursorPosition {
-                    file: path_string.clone(),
-                    line: params.text_document_position.position.line as i32,
-                    character: params.text_document_position.position.character as i32,
-                },
-                multiline: params.multiline,
-            },
-            parameters: SamplingParameters {
+        let txt = self.open_document_text(&params.text_document_position.text_document.uri).await?;
+        Ok(code_completion_post(
+            &params.text_document_position.text_document.uri,
+            txt,
+            &params.text_document_position.position,
+            params.multiline,
+            SamplingParameters {
                 max_new_tokens: params.parameters.max_new_tokens as usize,
                 temperature: Option::from(params.parameters.temperature),
                 top_p: None,
                 stop: None,
             },
-            model: "".to_string(),
-            scratchpad: "".to_string(),
-            stream: false,
-            no_cache: false,
-            use_ast: false,
-            use_vecdb: false,
-        })
+        ))
     }
 
-    pub async fn get_completions(&self, params: CompletionParams1) -> Result<CompletionRes> {
-        let mut post = self.flat_params_to_code_completion_post(&params).await?;
-
-        let res = handle_v1_code_completion(self.gcx.clone(), &mut post)
+    async fn code_completion(&self, post: &mut CodeCompletionPost) -> Result<CompletionRes> {
+        let res = handle_v1_code_completion(self.gcx.clone(), post)
             .await.map_err(|e| internal_error(e))?;
 
         let body_bytes = hyper::body::to_bytes(res.into_body()).await.map_err(|e| internal_error(e))?;
@@ -171,6 +232,35 @@ impl Backend {
         Ok(value)
     }
 
+    pub async fn get_completions(&self, params: CompletionParams1) -> Result<CompletionRes> {
+        let mut post = self.flat_params_to_code_completion_post(&params).await?;
+        self.code_completion(&mut post).await
+    }
+
+    pub async fn inline_completion(&self, params: InlineCompletionParams) -> Result<Option<InlineCompletionList>> {
+        let uri = &params.text_document_position.text_document.uri;
+        let mut text = Rope::from_str(&self.open_document_text(uri).await?);
+        let position = params.text_document_position.position;
+        let selected = params.context.selected_completion_info.as_ref();
+        let (cursor, multiline) = inline_completion_cursor(&mut text, &position, selected);
+
+        let mut post = code_completion_post(uri, text.to_string(), &cursor, multiline, SamplingParameters {
+            max_new_tokens: INLINE_COMPLETION_MAX_NEW_TOKENS,
+            temperature: None,
+            top_p: None,
+            stop: None,
+        });
+        // explicitly asked for a completion, the cached one was likely dismissed already
+        post.no_cache = params.context.trigger_kind == InlineCompletionTriggerKind::INVOKED;
+        let res = self.code_completion(&mut post).await?;
+
+        let items = res.choices.iter()
+            .filter(|x| !x.code_completion.is_empty())
+            .map(|x| inline_completion_item(&x.code_completion, &position, selected, res.snippet_telemetry_id))
+            .collect();
+        Ok(Some(InlineCompletionList { items }))
+    }
+
     pub async fn accept_snippet(&self, params: SnippetAcceptedParams) -> Result<SuccessRes> {
         let success = snippets_collection::snippet_accepted(self.gcx.clone(), params.snippet_telemetry_id).await;
         Ok(SuccessRes { success })
@@ -196,6 +286,7 @@ impl Backend {
 impl LanguageServer for Backend {
     async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
         info!("LSP client_info {:?}", params.client_info);
+        *self.client_capabilities.lock().unwrap() = params.capabilities.clone();
         let mut folders: Vec<PathBuf> = vec![];
         if let Some(nonzero_folders) = params.workspace_folders {
             folders = nonzero_folders.iter().map(|x| PathBuf::from(x.uri.path())).collect();
@@ -235,9 +326,22 @@ impl LanguageServer for Backend {
             }),
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 completion_provider: Some(completion_options),
+                definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+                    code_action_kinds: Some(vec![CodeActionKind::REFACTOR, CodeActionKind::REFACTOR_REWRITE]),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                    resolve_provider: Some(true),
+                })),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![INLINE_COMPLETION_ACCEPTED_COMMAND.to_string()],
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
                 workspace: Some(WorkspaceServerCapabilities {
                     workspace_folders: Some(WorkspaceFoldersServerCapabilities {
                         supported: Some(true),
@@ -262,6 +366,22 @@ impl LanguageServer for Backend {
             .log_message(MessageType::INFO, "rust LSP received initialized()")
             .await;
         let _ = info!("rust LSP received initialized()");
+        let work_done_progress = self.client_capabilities.lock().unwrap().window.as_ref()
+            .and_then(|x| x.work_done_progress).unwrap_or(false);
+        if work_done_progress {
+            tokio::spawn(ast_indexing_progress(self.gcx.clone(), self.client.clone()));
+        }
+        // without dynamic registration, inlineCompletionProvider is in the initialize result already
+        if self.inline_completion_dynamic_registration.load(Ordering::SeqCst) {
+            let registration = Registration {
+                id: "refact-inline-completion".to_string(),
+                method: "textDocument/inlineCompletion".to_string(),
+                register_options: None,
+            };
+            if let Err(e) = self.client.register_capability(vec![registration]).await {
+                info!("LSP client did not register inline completion: {}", e);
+            }
+        }
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
@@ -274,10 +394,10 @@ impl LanguageServer for Backend {
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        files_in_workspace::on_did_change(
+        files_in_workspace::on_did_change_incremental(
             self.gcx.clone(),
             &params.text_document.uri,
-            &params.content_changes[0].text  // TODO: This text could be just a part of the whole file
+            &params.content_changes,
         ).await
     }
 
@@ -293,49 +413,650 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "{refact-lsp} file closed")
             .await;
+        let (document_trees, files_priority) = {
+            let gcx_locked = self.gcx.read().await;
+            (gcx_locked.documents_state.document_trees.clone(), gcx_locked.documents_state.files_priority.clone())
+        };
+        document_trees.lock().unwrap().forget(&params.text_document.uri);
+        if let Ok(path) = params.text_document.uri.to_file_path() {
+            files_priority.lock().unwrap().close(&path);
+        }
         let uri = params.text_document.uri.to_string();
         info!("{uri} closed");
     }
 
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        // {"refact": {"vecdb": true, ...}} or the same fields without the section
+        let settings = match params.settings.get("refact") {
+            Some(section) if section.is_object() => section.clone(),
+            _ => params.settings,
+        };
+        let patch: SettingsPatch = match serde_json::from_value(settings) {
+            Ok(x) => x,
+            Err(e) => {
+                info!("LSP didChangeConfiguration: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = apply_settings(self.gcx.clone(), patch).await {
+            self.client.log_message(MessageType::ERROR, format!("refact settings: {}", e)).await;
+        }
+    }
+
     async fn shutdown(&self) -> Result<()> {
         let _ = info!("shutdown");
         Ok(())
     }
 
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        match params.command.as_str() {
+            INLINE_COMPLETION_ACCEPTED_COMMAND => {
+                let accepted: SnippetAcceptedParams = params.arguments.into_iter().next()
+                    .and_then(|x| serde_json::from_value(x).ok())
+                    .ok_or_else(|| Error::invalid_params("expected {\"snippet_telemetry_id\": ...}"))?;
+                let res = self.accept_snippet(accepted).await?;
+                Ok(Some(json!(res)))
+            }
+            _ => Err(Error::invalid_params(format!("unknown command {}", params.command))),
+        }
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        // the actions are cheap to list, the model runs only when the user picks one
+        self.open_document_text(&params.text_document.uri).await?;
+        let config = self.toolbox_config().await?;
+        let lines = selected_lines(&params.range);
+        let mut actions: CodeActionResponse = vec![];
+        for (name, command) in config.toolbox_commands.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+            if !toolbox_command_applies(command, lines) {
+                continue;
+            }
+            let kind = if command.insert_at_cursor { CodeActionKind::REFACTOR } else { CodeActionKind::REFACTOR_REWRITE };
+            let wanted = params.context.only.as_ref()
+                .map(|only| only.iter().any(|x| kind.as_str().starts_with(x.as_str())))
+                .unwrap_or(true);
+            if !wanted {
+                continue;
+            }
+            let data = ToolboxActionData {
+                toolbox_command: name.clone(),
+                uri: params.text_document.uri.clone(),
+                range: params.range,
+            };
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: command.description.clone(),
+                kind: Some(kind),
+                data: Some(json!(data)),
+                ..Default::default()
+            }));
+        }
+        Ok(Some(actions))
+    }
+
+    async fn code_action_resolve(&self, mut action: CodeAction) -> Result<CodeAction> {
+        let data: ToolboxActionData = action.data.clone()
+            .and_then(|x| serde_json::from_value(x).ok())
+            .ok_or_else(|| Error::invalid_params("not a refact code action"))?;
+        let config = self.toolbox_config().await?;
+        let command = config.toolbox_commands.get(&data.toolbox_command)
+            .ok_or_else(|| Error::invalid_params(format!("unknown toolbox command {}", data.toolbox_command)))?;
+        let text = Rope::from_str(&self.open_document_text(&data.uri).await?);
+        let start = utf16_position_to_char_idx(&text, &data.range.start);
+        let end = utf16_position_to_char_idx(&text, &data.range.end).max(start);
+        let selection = text.slice(start..end).to_string();
+
+        let file_name = data.uri.to_file_path().unwrap_or_default().to_string_lossy().to_string();
+        let messages = toolbox_messages(command, &file_name, data.range.start.line as usize + 1, &selection);
+        let answer = self.chat_not_stream(messages).await?;
+        let mut new_text = code_block_from_answer(&answer)
+            .ok_or_else(|| internal_error(format!("\"{}\" answered without a code block", command.description)))?;
+        if !selection.is_empty() && !selection.ends_with('\n') {
+            // the selection stops in the middle of a line, the rest of the line stays after the new code
+            new_text = new_text.trim_end_matches('\n').to_string();
+        }
+        let range = if command.insert_at_cursor { Range::new(data.range.start, data.range.start) } else { data.range };
+        action.edit = Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(data.uri.clone(), vec![TextEdit { range, new_text }])])),
+            ..Default::default()
+        });
+        Ok(action)
+    }
+
     async fn completion(&self, _: CompletionParams) -> Result<Option<CompletionResponse>> {
         info!("LSP asked for popup completions");
         Ok(Some(CompletionResponse::Array(vec![])))
     }
 
     async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
-        for add_folder in params.event.added {
-            // TODO
+        for folder in params.event.removed {
+            match folder.uri.to_file_path() {
+                Ok(path) => files_in_workspace::remove_folder(self.gcx.clone(), &path).await,
+                Err(_) => info!("LSP cannot remove workspace folder {}", folder.uri),
+            }
+        }
+        for folder in params.event.added {
+            match folder.uri.to_file_path() {
+                Ok(path) => files_in_workspace::add_folder(self.gcx.clone(), &path).await,
+                Err(_) => info!("LSP cannot add workspace folder {}", folder.uri),
+            }
         }
-        for delete_folder in params.event.removed {
-            // TODO
+    }
+
+    async fn did_create_files(&self, params: CreateFilesParams) {
+        let urls: Vec<Url> = params.files.iter().filter_map(|x| Url::parse(&x.uri).ok()).collect();
+        files_in_workspace::on_did_create(self.gcx.clone(), &urls).await;
+    }
+
+    async fn did_rename_files(&self, params: RenameFilesParams) {
+        for file in params.files {
+            match (Url::parse(&file.old_uri), Url::parse(&file.new_uri)) {
+                (Ok(old_uri), Ok(new_uri)) => files_in_workspace::on_did_rename(self.gcx.clone(), &old_uri, &new_uri).await,
+                _ => info!("LSP cannot rename {} -> {}", file.old_uri, file.new_uri),
+            }
         }
     }
+
+    async fn did_delete_files(&self, params: DeleteFilesParams) {
+        // a deleted directory takes the files under it along
+        let urls: Vec<Url> = params.files.iter().filter_map(|x| Url::parse(&x.uri).ok()).collect();
+        on_did_delete(self.gcx.clone(), &urls).await;
+    }
     async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
-        for event in params.changes {
-            let uri = event.uri;
-            if event.typ == FileChangeType::DELETED {
-                on_did_delete(self.gcx.clone(), &uri).await;
+        let urls: Vec<Url> = params.changes.into_iter()
+            .filter(|x| x.typ == FileChangeType::DELETED)
+            .map(|x| x.uri)
+            .collect();
+        if !urls.is_empty() {
+            on_did_delete(self.gcx.clone(), &urls).await;
+        }
+    }
+
+    async fn goto_definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>> {
+        let declarations = self.find_declarations(
+            &params.text_document_position_params.text_document.uri,
+            &params.text_document_position_params.position,
+        ).await?;
+        let texts = self.file_texts(declarations.iter().map(|x| &x.definition_info.path).collect()).await;
+        let locations: Vec<Location> = declarations.iter().filter_map(|x| declaration_location(&texts, x)).collect();
+        if locations.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(GotoDefinitionResponse::Array(locations)))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let uri = &params.text_document_position.text_document.uri;
+        let (doc, text) = self.document_and_text(uri).await?;
+        let name = match identifier_at(&Rope::from_str(&text), &params.text_document_position.position) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        // the declarations scope the usages, the calls resolving to other declarations with this name are left out
+        let mut declarations = self.find_declarations(uri, &params.text_document_position.position).await?;
+        let declaration_paths: Vec<String> = declarations.iter().map(|x| x.meta_path.clone()).collect();
+        if !params.context.include_declaration {
+            declarations.clear();
+        }
+        let ast_module = self.gcx.read().await.ast_module.clone();
+        let usages = match *ast_module.lock().await {
+            Some(ref ast) => ast.search_usage_locations(
+                &name, get_language_id_by_filename(&doc.get_path()), &declaration_paths, LSP_REFERENCES_TOP_N,
+            ).await,
+            None => return Ok(None),
+        };
+        let texts = self.file_texts(
+            declarations.iter().map(|x| &x.definition_info.path).chain(usages.iter().map(|(path, _)| path)).collect()
+        ).await;
+        let mut locations: Vec<Location> = declarations.iter().filter_map(|x| declaration_location(&texts, x)).collect();
+        locations.extend(usages.iter().filter_map(|(path, range)| {
+            Some(Location::new(Url::from_file_path(path).ok()?, lsp_range(texts.get(path), range)))
+        }));
+        Ok(Some(locations))
+    }
+
+    async fn document_symbol(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>> {
+        let doc = DocumentInfo { uri: params.text_document.uri.clone(), document: None };
+        let ast_module = self.gcx.read().await.ast_module.clone();
+        let symbols = match *ast_module.lock().await {
+            Some(ref ast) => ast.get_file_symbols(&doc).await,
+            None => return Ok(None),
+        };
+        match symbols {
+            Ok(res) => {
+                let texts = self.file_texts(res.symbols.iter().map(|x| &x.definition_info.path).collect()).await;
+                Ok(Some(DocumentSymbolResponse::Flat(
+                    res.symbols.iter().filter_map(|x| symbol_information(&texts, x)).collect()
+                )))
+            }
+            Err(e) => {
+                info!("LSP documentSymbol: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn symbol(&self, params: WorkspaceSymbolParams) -> Result<Option<Vec<SymbolInformation>>> {
+        if params.query.is_empty() {
+            return Ok(Some(vec![]));
+        }
+        let ast_module = self.gcx.read().await.ast_module.clone();
+        let found = match *ast_module.lock().await {
+            Some(ref ast) => ast.search_declarations_by_symbol_path(params.query.clone(), LSP_SYMBOLS_TOP_N).await,
+            None => return Ok(None),
+        };
+        match found {
+            Ok(res) => {
+                let texts = self.file_texts(res.search_results.iter().map(|x| &x.symbol_declaration.definition_info.path).collect()).await;
+                Ok(Some(
+                    res.search_results.iter().filter_map(|x| symbol_information(&texts, &x.symbol_declaration)).collect()
+                ))
+            }
+            Err(e) => {
+                info!("LSP workspace/symbol {:?}: {}", params.query, e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+const LSP_CURSOR_USAGES_TOP_N: usize = 10;
+const LSP_SYMBOLS_TOP_N: usize = 50;
+const LSP_REFERENCES_TOP_N: usize = 500;
+
+impl Backend {
+    async fn toolbox_config(&self) -> Result<Arc<ToolboxConfig>> {
+        // read again only when customization.yaml changes, code actions are asked for on every cursor move
+        load_customization_cached(self.gcx.clone()).await.map_err(internal_error)
+    }
+
+    async fn chat_not_stream(&self, messages: Vec<ChatMessage>) -> Result<String> {
+        // no chat_id, the pins belong to the chats in the IDE and run_at_commands adds none here
+        let post = json!({
+            "messages": messages,
+            "stream": false,
+        });
+        let res = handle_v1_chat(Extension(self.gcx.clone()), hyper::body::Bytes::from(post.to_string()))
+            .await.map_err(|e| internal_error(e))?;
+        let body_bytes = hyper::body::to_bytes(res.into_body()).await.map_err(|e| internal_error(e))?;
+        let value: Value = serde_json::from_slice(&body_bytes).map_err(|e| internal_error(e))?;
+        value["choices"][0]["message"]["content"].as_str()
+            .map(|x| x.to_string())
+            .ok_or_else(|| internal_error(format!("unexpected chat response {}", value)))
+    }
+
+    async fn document_and_text(&self, uri: &Url) -> Result<(DocumentInfo, String)> {
+        // the text open in IDE, or the file on disk if the client never opened it
+        let document_map = self.gcx.read().await.documents_state.document_map.clone();
+        let document = document_map.read().await.get(uri).cloned();
+        let doc = DocumentInfo { uri: uri.clone(), document };
+        let text = doc.read_file().await.map_err(internal_error)?;
+        Ok((doc, text))
+    }
+
+    async fn file_texts(&self, paths: Vec<&PathBuf>) -> HashMap<PathBuf, Rope> {
+        // the AST ranges count bytes, LSP counts UTF-16 code units, converting them needs the text
+        let document_map = self.gcx.read().await.documents_state.document_map.clone();
+        let mut texts: HashMap<PathBuf, Rope> = HashMap::new();
+        let mut to_read: Vec<PathBuf> = vec![];
+        {
+            let document_map_locked = document_map.read().await;
+            for path in paths.into_iter().unique() {
+                match Url::from_file_path(path).ok().and_then(|uri| document_map_locked.get(&uri)) {
+                    Some(document) => { texts.insert(path.clone(), document.text.clone()); }
+                    None => to_read.push(path.clone()),
+                }
+            }
+        }
+        // the files not open in IDE are read all at once
+        let read_results = futures::future::join_all(to_read.iter().map(|path| tokio::fs::read_to_string(path))).await;
+        for (path, text) in to_read.into_iter().zip(read_results) {
+            if let Ok(text) = text {
+                texts.insert(path, Rope::from_str(&text));
+            }
+        }
+        texts
+    }
+
+    async fn find_declarations(&self, uri: &Url, position: &Position) -> Result<Vec<SymbolDeclarationStruct>> {
+        let (doc, text) = self.document_and_text(uri).await?;
+        let rope = Rope::from_str(&text);
+        let name = match identifier_at(&rope, position) {
+            Some(x) => x,
+            None => return Ok(vec![]),
+        };
+        let language = get_language_id_by_filename(&doc.get_path());
+        let ast_module = self.gcx.read().await.ast_module.clone();
+        let mut ast_module_locked = ast_module.lock().await;
+        let ast = match ast_module_locked.as_mut() {
+            Some(ast) => ast,
+            None => return Ok(vec![]),
+        };
+        // the usages near the cursor resolve through imports and the call's object, keep the one under the cursor
+        let cursor = tree_sitter_point(&rope, position);
+        let mut declarations: Vec<SymbolDeclarationStruct> = match ast.search_declarations_by_cursor(&doc, &text, cursor, LSP_CURSOR_USAGES_TOP_N, true).await {
+            Ok(res) => res.search_results.into_iter().map(|x| x.symbol_declaration).filter(|x| x.name == name).collect(),
+            Err(e) => {
+                info!("LSP definition: {}", e);
+                vec![]
+            }
+        };
+        if declarations.is_empty() {
+            // a declaration in the same file, the cursor on a declaration itself, or a symbol not called anywhere near
+            if let Ok(res) = ast.search_declarations_by_symbol_path(name.clone(), LSP_SYMBOLS_TOP_N).await {
+                declarations = res.search_results.into_iter()
+                    .map(|x| x.symbol_declaration)
+                    .filter(|x| x.name == name && language.map(|l| l == x.language).unwrap_or(true))
+                    .collect();
             }
         }
+        Ok(declarations.into_iter().unique_by(|x| x.meta_path.clone()).collect())
+    }
+}
+
+fn code_completion_post(uri: &Url, text: String, position: &Position, multiline: bool, parameters: SamplingParameters) -> CodeCompletionPost {
+    // url -> String method should be the same as in telemetry::snippets_collection::sources_changed
+    let path_string = uri.to_file_path().unwrap_or_default().to_string_lossy().to_string();
+    CodeCompletionPost {
+        inputs: CodeCompletionInputs {
+            sources: HashMap::from([(path_string.clone(), text)]),
+            cursor: CursorPosition {
+                file: path_string.clone(),
+                line: position.line as i32,
+                character: position.character as i32,
+            },
+            multiline,
+        },
+        parameters,
+        model: "".to_string(),
+        scratchpad: "".to_string(),
+        stream: false,
+        no_cache: false,
+        use_ast: false,
+        use_vecdb: false,
+    }
+}
+
+fn selected_lines(range: &Range) -> usize {
+    if range.start == range.end {
+        return 0;
+    }
+    let mut lines = (range.end.line - range.start.line) as usize + 1;
+    if range.end.character == 0 && range.end.line > range.start.line {
+        lines -= 1;  // the selection ends at the start of the next line
+    }
+    lines
+}
+
+fn toolbox_command_applies(command: &ToolboxCommand, selected_lines: usize) -> bool {
+    if command.messages.is_empty() {
+        return false;  // help
+    }
+    // a code action cannot ask for arguments, without them the prompt would be empty
+    if command.messages.iter().any(|x| x.content.contains("%ARGS%")) {
+        return false;
+    }
+    if command.selection_unwanted && selected_lines > 0 {
+        return false;
+    }
+    match command.selection_needed.as_slice() {
+        [min, max] => *min <= selected_lines && selected_lines <= *max,
+        _ => true,
+    }
+}
+
+fn toolbox_messages(command: &ToolboxCommand, file_name: &str, cursor_line: usize, selection: &str) -> Vec<ChatMessage> {
+    // the same magic keys the plugins expand, see COMPILED_IN_CUSTOMIZATION_YAML
+    let mut code_selection = selection.to_string();
+    if !code_selection.is_empty() && !code_selection.ends_with('\n') {
+        code_selection.push('\n');
+    }
+    command.messages.iter().map(|x| ChatMessage {
+        role: x.role.clone(),
+        content: x.content
+            .replace("%CURRENT_FILE%", file_name)
+            .replace("%CURSOR_LINE%", &cursor_line.to_string())
+            .replace("%CODE_SELECTION%", &code_selection),
+    }).collect()
+}
+
+fn code_block_from_answer(answer: &str) -> Option<String> {
+    // the first ``` block, without the language after the opening backquotes
+    let is_fence = |line: &&str| line.trim_start().starts_with("```");
+    let mut lines = answer.split_inclusive('\n').skip_while(|x| !is_fence(x));
+    lines.next()?;
+    // an unterminated block goes till the end, the model ran out of tokens
+    Some(lines.take_while(|x| !is_fence(x)).collect())
+}
+
+fn identifier_at(text: &Rope, position: &Position) -> Option<String> {
+    let cursor = utf16_position_to_char_idx(text, position);
+    let line = text.char_to_line(cursor);
+    let chars: Vec<char> = text.line(line).chars().collect();
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+    let cursor = cursor - text.line_to_char(line);
+    let mut start = cursor;
+    while start > 0 && is_identifier(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = cursor;
+    while end < chars.len() && is_identifier(chars[end]) {
+        end += 1;
     }
+    if start == end {
+        return None;
+    }
+    Some(chars[start..end].iter().collect())
+}
+
+fn tree_sitter_point(text: &Rope, position: &Position) -> Point {
+    // tree-sitter columns are in bytes
+    let byte = text.char_to_byte(utf16_position_to_char_idx(text, position));
+    let row = text.byte_to_line(byte);
+    Point::new(row, byte - text.line_to_byte(row))
+}
+
+fn lsp_position(text: Option<&Rope>, point: &Point) -> Position {
+    // without the text, the byte column is right for ASCII lines
+    let text = match text {
+        Some(text) if point.row < text.len_lines() => text,
+        _ => return Position::new(point.row as u32, point.column as u32),
+    };
+    let byte = (text.line_to_byte(point.row) + point.column).min(text.len_bytes());
+    char_idx_to_utf16_position(text, text.byte_to_char(byte))
+}
+
+fn lsp_range(text: Option<&Rope>, range: &tree_sitter::Range) -> Range {
+    Range::new(lsp_position(text, &range.start_point), lsp_position(text, &range.end_point))
+}
+
+fn declaration_location(texts: &HashMap<PathBuf, Rope>, declaration: &SymbolDeclarationStruct) -> Option<Location> {
+    let path = &declaration.definition_info.path;
+    let uri = Url::from_file_path(path).ok()?;
+    Some(Location::new(uri, lsp_range(texts.get(path), &declaration.definition_info.range)))
+}
+
+fn symbol_kind(symbol_type: &SymbolType) -> SymbolKind {
+    match symbol_type {
+        SymbolType::GlobalVar => SymbolKind::VARIABLE,
+        SymbolType::Function => SymbolKind::FUNCTION,
+        SymbolType::Class => SymbolKind::CLASS,
+        SymbolType::Enum => SymbolKind::ENUM_MEMBER,
+        SymbolType::Method => SymbolKind::METHOD,
+        SymbolType::Unknown => SymbolKind::VARIABLE,
+    }
+}
+
+#[allow(deprecated)]  // SymbolInformation::deprecated
+fn symbol_information(texts: &HashMap<PathBuf, Rope>, declaration: &SymbolDeclarationStruct) -> Option<SymbolInformation> {
+    // meta_path is "file::Class::method", the container is what goes between the file and the name
+    let prefix = format!("{}::", declaration.definition_info.path.to_string_lossy());
+    let container_name = declaration.meta_path.strip_prefix(&prefix)
+        .and_then(|x| x.rsplit_once("::"))
+        .map(|(container, _)| container.to_string());
+    Some(SymbolInformation {
+        name: declaration.name.clone(),
+        kind: symbol_kind(&declaration.symbol_type),
+        tags: None,
+        deprecated: None,
+        location: declaration_location(texts, declaration)?,
+        container_name,
+    })
+}
+
+fn inline_completion_cursor(text: &mut Rope, position: &Position, selected: Option<&SelectedCompletionInfo>) -> (Position, bool) {
+    // the completion must continue what is selected in the popup, the text is completed as if it was already accepted
+    let mut cursor = utf16_position_to_char_idx(text, position);
+    if let Some(selected) = selected {
+        let start = utf16_position_to_char_idx(text, &selected.range.start);
+        let end = utf16_position_to_char_idx(text, &selected.range.end).max(start);
+        text.remove(start..end);
+        text.insert(start, &selected.text);
+        cursor = start + selected.text.chars().count();
+    }
+    // the scratchpads count characters, not UTF-16 code units
+    let line = text.char_to_line(cursor);
+    let character = cursor - text.line_to_char(line);
+    // nothing but whitespace to the right of the cursor, the model can write several lines
+    let multiline = text.line(line).chars().skip(character).all(|c| c.is_whitespace());
+    (Position::new(line as u32, character as u32), multiline)
+}
+
+fn inline_completion_item(code_completion: &str, position: &Position, selected: Option<&SelectedCompletionInfo>, snippet_telemetry_id: u32) -> InlineCompletionItem {
+    // with a selected completion, the item replaces what it replaces, in the original text
+    let (insert_text, range) = match selected {
+        Some(selected) => (format!("{}{}", selected.text, code_completion), selected.range),
+        None => (code_completion.to_string(), Range::new(*position, *position)),
+    };
+    InlineCompletionItem {
+        insert_text,
+        filter_text: None,
+        range: Some(range),
+        command: Some(Command {
+            title: "Accept completion".to_string(),
+            command: INLINE_COMPLETION_ACCEPTED_COMMAND.to_string(),
+            arguments: Some(vec![json!({"snippet_telemetry_id": snippet_telemetry_id})]),
+        }),
+    }
+}
+
+async fn ast_indexing_progress(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    client: tower_lsp::Client,
+) {
+    // reports the initial indexing only, after that the status is in /v1/ast-status
+    let token = NumberOrString::String("refact-ast-indexing".to_string());
+    if let Err(e) = client.send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams { token: token.clone() }).await {
+        info!("LSP client refused progress reporting: {}", e);
+        return;
+    }
+    let mut begun = false;
+    let mut max_remaining: usize = 0;
+    let mut idle_polls = 0;
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        let ast_module = gcx.read().await.ast_module.clone();
+        let status = match *ast_module.lock().await {
+            Some(ref ast) => ast.ast_index_status().await,
+            None => return,
+        };
+        let remaining = status.files_queued + status.files_in_progress;
+        max_remaining = max_remaining.max(remaining);
+        let message = format!("{} files indexed, {} to go", status.files_indexed, remaining);
+        let percentage = if max_remaining > 0 { ((max_remaining - remaining) * 100 / max_remaining) as u32 } else { 0 };
+        let progress = if !begun {
+            if remaining == 0 && status.state != AstIndexState::Starting {
+                // nothing to index, or the initial indexing is over before the client asked
+                idle_polls += 1;
+                if idle_polls > 10 {
+                    return;
+                }
+                continue;
+            }
+            begun = true;
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: "Indexing".to_string(),
+                cancellable: Some(false),
+                message: Some(message),
+                percentage: Some(percentage),
+            })
+        } else if remaining == 0 && status.state == AstIndexState::Idle {
+            WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: Some(format!("{} files, {} symbols, {} parse errors", status.files_indexed, status.symbols_count, status.parse_errors.len())),
+            })
+        } else {
+            WorkDoneProgress::Report(WorkDoneProgressReport {
+                cancellable: Some(false),
+                message: Some(message),
+                percentage: Some(percentage),
+            })
+        };
+        let finished = matches!(progress, WorkDoneProgress::End(_));
+        client.send_notification::<notification::Progress>(ProgressParams {
+            token: token.clone(),
+            value: ProgressParamsValue::WorkDone(progress),
+        }).await;
+        if finished {
+            return;
+        }
+    }
+}
+
+fn inline_completion_dynamic_registration(initialize_params: &Value) -> bool {
+    initialize_params.pointer("/capabilities/textDocument/inlineCompletion/dynamicRegistration")
+        .and_then(|x| x.as_bool())
+        .unwrap_or(false)
+}
+
+fn is_initialize_result(response: &jsonrpc::Response) -> bool {
+    response.result().map(|x| x.get("capabilities").is_some() && x.get("serverInfo").is_some()).unwrap_or(false)
+}
+
+fn add_inline_completion_provider(response: jsonrpc::Response) -> jsonrpc::Response {
+    // ServerCapabilities in lsp-types 0.94 has no inlineCompletionProvider, it is added to the serialized initialize result
+    let (id, body) = response.into_parts();
+    let body = body.map(|mut result| {
+        if let Some(capabilities) = result.get_mut("capabilities").and_then(|x| x.as_object_mut()) {
+            capabilities.insert("inlineCompletionProvider".to_string(), json!(true));
+        }
+        result
+    });
+    jsonrpc::Response::from_parts(id, body)
 }
 
 async fn build_lsp_service(
     gcx: Arc<ARwLock<global_context::GlobalContext>>,
-) -> (LspService::<Backend>, ClientSocket) {
+) -> (impl Service<jsonrpc::Request, Response = Option<jsonrpc::Response>, Error = ExitedError, Future = impl Send> + Send + 'static, ClientSocket) {
+    let dynamic_registration = Arc::new(AtomicBool::new(false));
+    let dynamic_registration_backend = dynamic_registration.clone();
     let (lsp_service, socket) = LspService::build(|client| Backend {
         gcx,
         client,
+        client_capabilities: Arc::new(StdMutex::new(ClientCapabilities::default())),
+        inline_completion_dynamic_registration: dynamic_registration_backend,
     })
         .custom_method("refact/getCompletions", Backend::get_completions)
         .custom_method("refact/acceptCompletion", Backend::accept_snippet)
+        .custom_method("textDocument/inlineCompletion", Backend::inline_completion)
         .custom_method("refact/test_if_head_tail_equal_return_added_text", Backend::test_if_head_tail_equal_return_added_text)
         .finish();
+    let dynamic_registration_response = dynamic_registration.clone();
+    let lsp_service = lsp_service
+        .map_request(move |request: jsonrpc::Request| {
+            if request.method() == "initialize" {
+                let dynamic = request.params().map(inline_completion_dynamic_registration).unwrap_or(false);
+                dynamic_registration.store(dynamic, Ordering::SeqCst);
+            }
+            request
+        })
+        .map_response(move |response: Option<jsonrpc::Response>| {
+            response.map(|x| {
+                if is_initialize_result(&x) && !dynamic_registration_response.load(Ordering::SeqCst) {
+                    add_inline_completion_provider(x)
+                } else {
+                    x
+                }
+            })
+        });
     (lsp_service, socket)
 }
 
@@ -381,3 +1102,144 @@ pub async fn spawn_lsp_task(
 
     None
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_block_from_answer() {
+        let answer = "Here it is:\n\n```python\ndef f(x):\n    return x\n```\n\nAnd another:\n```\npass\n```\n";
+        assert_eq!(code_block_from_answer(answer).unwrap(), "def f(x):\n    return x\n");
+        assert_eq!(code_block_from_answer("```\nunterminated\n").unwrap(), "unterminated\n");
+        assert!(code_block_from_answer("no code here").is_none());
+    }
+
+    #[test]
+    fn test_toolbox_command_applies() {
+        let message = |content: &str| ChatMessage { role: "user".to_string(), content: content.to_string() };
+        let shorter = ToolboxCommand {
+            description: "Make code shorter".to_string(),
+            messages: vec![message("```\n%CODE_SELECTION%```\n")],
+            selection_needed: vec![1, 50],
+            selection_unwanted: false,
+            insert_at_cursor: false,
+        };
+        let gen = ToolboxCommand {
+            description: "Create new code".to_string(),
+            messages: vec![message("%ARGS%")],
+            selection_needed: vec![],
+            selection_unwanted: true,
+            insert_at_cursor: true,
+        };
+        let edit = ToolboxCommand { messages: vec![message("make this edit: %ARGS%")], ..shorter.clone() };
+
+        let nothing = Range::new(Position::new(3, 4), Position::new(3, 4));
+        let two_lines = Range::new(Position::new(3, 0), Position::new(5, 0));
+        assert_eq!(selected_lines(&nothing), 0);
+        assert_eq!(selected_lines(&two_lines), 2);
+        assert!(!toolbox_command_applies(&shorter, 0));
+        assert!(toolbox_command_applies(&shorter, 2));
+        assert!(!toolbox_command_applies(&shorter, 51));
+        assert!(!toolbox_command_applies(&gen, 0));
+        assert!(!toolbox_command_applies(&gen, 2));
+        assert!(!toolbox_command_applies(&edit, 2));
+
+        // the compiled-in gen would send an empty user message, the description goes after the command in the chat
+        let config: ToolboxConfig = serde_yaml::from_str(crate::toolbox::toolbox_compiled_in::COMPILED_IN_CUSTOMIZATION_YAML).unwrap();
+        let compiled_in_gen = config.toolbox_commands.get("gen").unwrap();
+        assert!(!toolbox_command_applies(compiled_in_gen, 0));
+    }
+
+    #[test]
+    fn test_identifier_at() {
+        let text = Rope::from_str("😀 foo.bar_1(x)\n  = y\n");
+        // LSP columns count UTF-16 code units, the emoji is two of them
+        assert_eq!(identifier_at(&text, &Position::new(0, 3)).unwrap(), "foo");
+        assert_eq!(identifier_at(&text, &Position::new(0, 6)).unwrap(), "foo");
+        assert_eq!(identifier_at(&text, &Position::new(0, 9)).unwrap(), "bar_1");
+        assert_eq!(identifier_at(&text, &Position::new(1, 5)).unwrap(), "y");
+        assert!(identifier_at(&text, &Position::new(1, 1)).is_none());
+        assert!(identifier_at(&text, &Position::new(7, 0)).is_none());
+    }
+
+    #[test]
+    fn test_lsp_range() {
+        let text = Rope::from_str("😀x = 1\n");
+        // tree-sitter counts bytes, the emoji is four bytes and two UTF-16 code units
+        let range = tree_sitter::Range {
+            start_byte: 4,
+            end_byte: 5,
+            start_point: Point::new(0, 4),
+            end_point: Point::new(0, 5),
+        };
+        assert_eq!(lsp_range(Some(&text), &range), Range::new(Position::new(0, 2), Position::new(0, 3)));
+        assert_eq!(lsp_range(None, &range), Range::new(Position::new(0, 4), Position::new(0, 5)));
+    }
+
+    #[test]
+    fn test_symbol_information() {
+        let path = PathBuf::from("/tmp/lsp_symbols/shapes.py");
+        let code = "class Circle:\n    def area(self):\n        return 1\n";
+        let doc = DocumentInfo::from_pathbuf(&path).unwrap();
+        let (declarations, _, _) = crate::ast::ast_index::AstIndex::get_declarations_and_usages_from_text(&doc, &code.to_string(), None).unwrap();
+        let texts: HashMap<PathBuf, Rope> = HashMap::from([(path.clone(), Rope::from_str(code))]);
+        let find = |name: &str| declarations.values().find(|x| x.name == name).unwrap();
+
+        let class = symbol_information(&texts, find("Circle")).unwrap();
+        assert_eq!(class.kind, SymbolKind::CLASS);
+        assert_eq!(class.container_name, None);
+        assert_eq!(class.location.uri, Url::from_file_path(&path).unwrap());
+        assert_eq!(class.location.range.start, Position::new(0, 0));
+
+        let method = symbol_information(&texts, find("area")).unwrap();
+        assert_eq!(method.kind, SymbolKind::METHOD);
+        assert_eq!(method.container_name.as_deref(), Some("Circle"));
+        assert_eq!(method.location.range.start.line, 1);
+    }
+
+    #[test]
+    fn test_inline_completion_selected() {
+        // the popup has "println!" selected for "pri", the cursor is after "pri"
+        let mut text = Rope::from_str("fn main() {\n    😀 pri\n}\n");
+        let position = Position::new(1, 10);
+        let selected = SelectedCompletionInfo {
+            range: Range::new(Position::new(1, 7), Position::new(1, 10)),
+            text: "println!".to_string(),
+        };
+        let (cursor, multiline) = inline_completion_cursor(&mut text, &position, Some(&selected));
+        assert_eq!(text.to_string(), "fn main() {\n    😀 println!\n}\n");
+        // in characters, the emoji is one
+        assert_eq!(cursor, Position::new(1, 14));
+        assert!(multiline);
+
+        let item = inline_completion_item("(\"hello\");", &position, Some(&selected), 7);
+        assert_eq!(item.insert_text, "println!(\"hello\");");
+        assert_eq!(item.range, Some(selected.range));
+
+        let mut text = Rope::from_str("let x = f(a);\n");
+        let position = Position::new(0, 10);
+        let (cursor, multiline) = inline_completion_cursor(&mut text, &position, None);
+        assert_eq!(cursor, position);
+        assert!(!multiline);
+        let item = inline_completion_item("b, ", &position, None, 7);
+        assert_eq!(item.range, Some(Range::new(position, position)));
+    }
+
+    #[test]
+    fn test_inline_completion_capability() {
+        let params = json!({"capabilities": {"textDocument": {"inlineCompletion": {"dynamicRegistration": true}}}});
+        assert!(inline_completion_dynamic_registration(&params));
+        assert!(!inline_completion_dynamic_registration(&json!({"capabilities": {}})));
+
+        let response = jsonrpc::Response::from_ok(jsonrpc::Id::Number(1), json!({"capabilities": {"hoverProvider": true}, "serverInfo": {"name": "refact"}}));
+        assert!(is_initialize_result(&response));
+        assert!(!is_initialize_result(&jsonrpc::Response::from_ok(jsonrpc::Id::Number(2), json!({"items": []}))));
+        let (_, body) = add_inline_completion_provider(response).into_parts();
+        let result = body.unwrap();
+        assert_eq!(result["capabilities"]["inlineCompletionProvider"], json!(true));
+        assert_eq!(result["capabilities"]["hoverProvider"], json!(true));
+        assert!(result["capabilities"].get("experimental").is_none());
+    }
+}
@@ -4,7 +4,7 @@ use tokio::task::JoinHandle;
 use tracing::{info, Level};
 use tracing_appender;
 
-use crate::background_tasks::start_background_tasks;
+use crate::background_tasks::{start_background_tasks, stop_ast_background_tasks, stop_vecdb_background_tasks};
 use crate::lsp::spawn_lsp_task;
 use crate::telemetry::{basic_transmit, snippets_transmit};
 
@@ -27,6 +27,8 @@ mod known_models;
 mod dashboard;
 mod files_in_workspace;
 mod files_in_jsonl;
+mod files_priority;
+mod settings;
 mod vecdb;
 mod fetch_embedding;
 mod at_commands;
@@ -98,6 +100,8 @@ async fn main() {
     }
 
     background_tasks.abort().await;
+    stop_ast_background_tasks(gcx.clone()).await;
+    stop_vecdb_background_tasks(gcx.clone()).await;
     info!("saving telemetry without sending, so should be quick");
     basic_transmit::basic_telemetry_compress(gcx.clone()).await;
     info!("bb\n");
@@ -87,7 +87,9 @@ pub async fn scratchpad_interaction_not_stream(
     } else if let Some(oai_choices) = model_says.get("choices") {
         let choices = oai_choices.as_array().unwrap().iter()
             .map(|x| {
-                x.get("text").unwrap().as_str().unwrap().to_string()
+                // chat passthrough answers with a message instead of text
+                x.get("text").or_else(|| x.get("message").and_then(|m| m.get("content")))
+                    .and_then(|t| t.as_str()).unwrap_or("").to_string()
             }).collect::<Vec<_>>();
         let stopped = oai_choices.as_array().unwrap().iter()
             .map(|x| {
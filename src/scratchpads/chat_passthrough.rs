@@ -102,10 +102,22 @@ impl ScratchpadAbstract for ChatPassthrough {
 
     fn response_n_choices(
         &mut self,
-        _choices: Vec<String>,
-        _stopped: Vec<bool>,
+        choices: Vec<String>,
+        stopped: Vec<bool>,
     ) -> Result<serde_json::Value, String> {
-        unimplemented!()
+        let json_choices = choices.iter().enumerate().map(|(i, x)| {
+            serde_json::json!({
+                "index": i,
+                "message": {
+                    "role": "assistant",
+                    "content": x
+                },
+                "finish_reason": (if stopped[i] { "stop" } else { "length" }).to_string(),
+            })
+        }).collect::<Vec<_>>();
+        Ok(serde_json::json!({
+            "choices": json_choices,
+        }))
     }
 
     fn response_streaming(
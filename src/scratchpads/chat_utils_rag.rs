@@ -2,12 +2,14 @@ use std::sync::Arc;
 use std::sync::RwLock;
 use std::cmp::Ordering;
 use tracing::info;
+use serde::Serialize;
 use serde_json::{json, Value};
 use tokenizers::Tokenizer;
 use tokio::sync::RwLock as ARwLock;
 use crate::at_commands::at_commands::AtCommandsContext;
+use crate::at_commands::at_pin::{ContextPin, pins_tokens_limit, resolve_pins};
 
-use crate::call_validation::{ChatMessage, ChatPost, ContextFile};
+use crate::call_validation::{ChatActiveFile, ChatMessage, ChatPost, ContextFile, ContextSelection};
 use crate::global_context::GlobalContext;
 
 
@@ -25,12 +27,43 @@ pub fn count_tokens(
     }
 }
 
+#[derive(Serialize, Clone, Debug)]
+pub struct ContextItemReport {
+    pub file_name: String,
+    pub line1: usize,
+    pub line2: usize,
+    pub usefulness: f32,
+    pub tokens: usize,
+    pub pinned: bool,
+    pub decision: String,  // "taken", "dropped" (over the budget), "merged" (into another item) or "excluded" (by the user)
+    pub merged_into: Option<usize>,  // index in items
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct PostprocessReport {
+    pub items: Vec<ContextItemReport>,
+    pub tokens_limit: usize,
+    pub tokens_used: usize,
+    pub tokens_left: usize,
+}
+
 pub async fn postprocess_at_results(
     global_context: Arc<ARwLock<GlobalContext>>,
     messages: Vec<ChatMessage>,
     tokenizer: Arc<RwLock<Tokenizer>>,
     tokens_limit: usize,
 ) -> Vec<ContextFile> {
+    let (merged, _report) = postprocess_at_results_with_report(global_context, messages, tokenizer, tokens_limit, &ContextSelection::default()).await;
+    merged
+}
+
+pub async fn postprocess_at_results_with_report(
+    global_context: Arc<ARwLock<GlobalContext>>,
+    messages: Vec<ChatMessage>,
+    tokenizer: Arc<RwLock<Tokenizer>>,
+    tokens_limit: usize,
+    selection: &ContextSelection,
+) -> (Vec<ContextFile>, PostprocessReport) {
     // 1. Decode all
     let mut cxfile_list: Vec<ContextFile> = vec![];
     for msg in messages {
@@ -38,28 +71,42 @@ pub async fn postprocess_at_results(
     }
     // This check_only==true is for debugging only, can be safely removed (the result is already ignored)
     let _ = reload_files(global_context.clone(), &cxfile_list, true).await;
-    // 2. Sort by usefulness
+    // 2. Drop what the user has deselected, sort by usefulness, pinned go first
+    let (excluded, mut cxfile_list): (Vec<ContextFile>, Vec<ContextFile>) = cxfile_list.into_iter().partition(|x| selection.is_excluded(x));
     cxfile_list.sort_by(|a, b| {
-        b.usefulness.partial_cmp(&a.usefulness).unwrap_or(Ordering::Equal)
+        selection.is_pinned(b).cmp(&selection.is_pinned(a))
+            .then(b.usefulness.partial_cmp(&a.usefulness).unwrap_or(Ordering::Equal))
     });
     for cxfile in cxfile_list.iter() {
         info!("sorted file {}:{}-{} usefulness {:.1}", crate::nicer_logs::last_n_chars(&cxfile.file_name, 30), cxfile.line1, cxfile.line2, cxfile.usefulness);
     }
-    // 3. Truncate less useful to tokens_limit
-    let mut total_tokens: usize = 0;
-    let mut idx = 0;
-    while idx < cxfile_list.len() {
-        let x: ContextFile = cxfile_list[idx].clone();
-        let tokens_count = if total_tokens < tokens_limit { count_tokens(&tokenizer.read().unwrap(), x.file_content.as_str()) } else { 0 };
-        total_tokens += tokens_count;
-        if total_tokens > tokens_limit {
-            cxfile_list.remove(idx);
-            info!("drop less useful {}:{}-{} because {} tokens greater than limit {}", crate::nicer_logs::last_n_chars(&x.file_name, 30), x.line1, x.line2, total_tokens, tokens_limit);
+    // 3. Truncate less useful to tokens_limit, everything after the first item that doesn't fit is dropped
+    let mut report = PostprocessReport { tokens_limit, ..Default::default() };
+    let mut taken: Vec<ContextFile> = vec![];
+    let mut taken_report_idx: Vec<usize> = vec![];
+    let mut over_limit = false;
+    for x in cxfile_list.iter() {
+        let tokens_count = count_tokens(&tokenizer.read().unwrap(), x.file_content.as_str());
+        let decision = if !over_limit && report.tokens_used + tokens_count <= tokens_limit {
+            report.tokens_used += tokens_count;
+            info!("take {}:{}-{} tokens {} <= {}", crate::nicer_logs::last_n_chars(&x.file_name, 30), x.line1, x.line2, report.tokens_used, tokens_limit);
+            taken.push(x.clone());
+            taken_report_idx.push(report.items.len());
+            "taken"
         } else {
-            info!("take {}:{}-{} tokens {} < {}", crate::nicer_logs::last_n_chars(&x.file_name, 30), x.line1, x.line2, total_tokens, tokens_limit);
-            idx += 1;
-        }
+            over_limit = true;
+            info!("drop less useful {}:{}-{} because {} more tokens don't fit into limit {}", crate::nicer_logs::last_n_chars(&x.file_name, 30), x.line1, x.line2, tokens_count, tokens_limit);
+            "dropped"
+        };
+        report.items.push(ContextItemReport::new(x, tokens_count, selection.is_pinned(x), decision));
     }
+    for x in excluded.iter() {
+        info!("excluded by the user {}:{}-{}", crate::nicer_logs::last_n_chars(&x.file_name, 30), x.line1, x.line2);
+        let tokens_count = count_tokens(&tokenizer.read().unwrap(), x.file_content.as_str());
+        report.items.push(ContextItemReport::new(x, tokens_count, false, "excluded"));
+    }
+    report.tokens_left = tokens_limit.saturating_sub(report.tokens_used);
+    let mut cxfile_list = taken;
     // 4. Remove small gaps in lines and deduplicate
     let mut merged: Vec<ContextFile> = vec![];
     let list_len = cxfile_list.len();
@@ -77,7 +124,7 @@ pub async fn postprocess_at_results(
                     continue;
                 }
                 let y: &ContextFile = cxfile_list_copy.get(j).unwrap();
-                if x.file_name != y.file_name {
+                if x.file_name != y.file_name || x.synthetic || y.synthetic {
                     continue;
                 }
                 let possible_merge_line1 = x.line1.min(y.line1);
@@ -86,6 +133,8 @@ pub async fn postprocess_at_results(
                     // good, makes sense to merge
                     info!("merging file {} range {}-{} with range {}-{}", crate::nicer_logs::last_n_chars(&x.file_name, 30), x.line1, x.line2, y.line1, y.line2);
                     eaten[j] = true;
+                    report.items[taken_report_idx[j]].decision = "merged".to_string();
+                    report.items[taken_report_idx[j]].merged_into = Some(taken_report_idx[i]);
                     x.line1 = possible_merge_line1;
                     x.line2 = possible_merge_line2;
                     x.usefulness = x.usefulness.max(y.usefulness);
@@ -105,7 +154,22 @@ pub async fn postprocess_at_results(
         merged.push(cxfile_list[i].clone());
         info!("merged {}:{}-{}", crate::nicer_logs::last_n_chars(&cxfile_list[i].file_name, 30), cxfile_list[i].line1, cxfile_list[i].line2);
     }
-    merged
+    (merged, report)
+}
+
+impl ContextItemReport {
+    fn new(x: &ContextFile, tokens: usize, pinned: bool, decision: &str) -> Self {
+        ContextItemReport {
+            file_name: x.file_name.clone(),
+            line1: x.line1,
+            line2: x.line2,
+            usefulness: x.usefulness,
+            tokens,
+            pinned,
+            decision: decision.to_string(),
+            merged_into: None,
+        }
+    }
 }
 
 pub async fn reload_files(
@@ -117,6 +181,13 @@ pub async fn reload_files(
     // drop old text in file_content, load new using get_file_text_from_memory_or_disk
     let mut was_able_to_reload: Vec<ContextFile> = vec![];
     for m in merged.iter() {
+        if m.synthetic {
+            // nothing to reload, the text was generated by an at-command
+            if !check_only {
+                was_able_to_reload.push(m.clone());
+            }
+            continue;
+        }
         let file_path = m.file_name.clone();
         let file_text_maybe: Result<String, String> = crate::files_in_workspace::get_file_text_from_memory_or_disk(global_context.clone(), &file_path).await;
         if file_text_maybe.is_err() {
@@ -150,6 +221,7 @@ pub async fn reload_files(
             line1: m.line1,
             line2: m.line2,
             usefulness: m.usefulness,
+            synthetic: false,
         });
     }
 
@@ -166,6 +238,53 @@ pub async fn reload_files(
     processed_messages
 }
 
+async fn implicit_context_messages(
+    active_file: &ChatActiveFile,
+    query: &String,
+    context: &AtCommandsContext,
+    top_n: usize,
+) -> Vec<ChatMessage> {
+    // the user didn't say what to look at, so look around the cursor: the code there and the symbols used there,
+    // the same as "@file file:line" and "@symbols-at file:line" would
+    let arg = format!("{}:{}", active_file.file_name, active_file.line);
+    let mut messages: Vec<ChatMessage> = vec![];
+    if let Some((line1, line2)) = active_file.selection {
+        match crate::files_in_workspace::get_file_text_from_memory_or_disk(context.global_context.clone(), &active_file.file_name).await {
+            Ok(text) if line1 >= 1 && line1 <= line2 => {
+                let lines: Vec<&str> = text.lines().skip(line1 - 1).take(line2 - line1 + 1).collect();
+                let selected = ContextFile {
+                    file_name: active_file.file_name.clone(),
+                    file_content: lines.join("\n") + "\n",
+                    line1,
+                    line2: line1 + lines.len().max(1) - 1,
+                    usefulness: 100.0,
+                    synthetic: false,
+                };
+                messages.push(ChatMessage { role: "context_file".to_string(), content: json!(vec![selected]).to_string() });
+            }
+            Ok(_) => info!("implicit context: bad selection {}-{}", line1, line2),
+            Err(e) => info!("implicit context: {}", e),
+        }
+    }
+    for cmd_name in ["@file", "@symbols-at"] {
+        let cmd = match context.at_commands.get(cmd_name) {
+            Some(x) => x.clone(),
+            None => continue,
+        };
+        let cmd_locked = cmd.lock().await;
+        if !cmd_locked.can_execute(&vec![arg.clone()], context).await {
+            info!("implicit context: {} can't execute with {:?}", cmd_name, arg);
+            continue;
+        }
+        match cmd_locked.execute(query, &vec![arg.clone()], top_n, context).await {
+            Ok(msg) => messages.push(msg),
+            Err(e) => info!("implicit context: {} failed: {}", cmd_name, e),
+        }
+    }
+    info!("implicit context for {:?}: {} messages", arg, messages.len());
+    messages
+}
+
 pub async fn run_at_commands(
     global_context: Arc<ARwLock<GlobalContext>>,
     tokenizer: Arc<RwLock<Tokenizer>>,
@@ -176,7 +295,8 @@ pub async fn run_at_commands(
     stream_back_to_user: &mut HasVecdbResults,
 ) -> usize {
     // TODO: don't operate on `post`, return a copy of the messages
-    let context = AtCommandsContext::new(global_context.clone()).await;
+    let mut context = AtCommandsContext::new(global_context.clone()).await;
+    context.chat_id = post.chat_id.clone();
 
     let mut user_msg_starts = post.messages.len();
     let mut user_messages_with_at: usize = 0;
@@ -195,7 +315,7 @@ pub async fn run_at_commands(
         }
     }
     user_messages_with_at = user_messages_with_at.max(1);
-    let reserve_for_context = n_ctx - maxgen - RESERVE_FOR_QUESTION_AND_FOLLOWUP;
+    let mut reserve_for_context = n_ctx - maxgen - RESERVE_FOR_QUESTION_AND_FOLLOWUP;
     info!("reserve_for_context {} tokens", reserve_for_context);
 
     // Token limit works like this:
@@ -204,6 +324,34 @@ pub async fn run_at_commands(
     // This is useful to give prefix and suffix of the same file precisely the position necessary for FIM-like operation of a chat model
 
     let mut rebuilt_messages: Vec<ChatMessage> = post.messages.iter().take(user_msg_starts).map(|m| m.clone()).collect();
+
+    // Pinned context goes before the new user messages, reloaded every turn, limited by its own share of the budget.
+    // A chat without chat_id has no pins, otherwise all such chats would share them.
+    let pins: Vec<ContextPin> = if post.chat_id.is_empty() {
+        vec![]
+    } else {
+        let pins_arc = global_context.read().await.context_pins.clone();
+        let pins_locked = pins_arc.lock().await;
+        pins_locked.get(&post.chat_id).cloned().unwrap_or_default()
+    };
+    if !pins.is_empty() {
+        let pins_limit = pins_tokens_limit(reserve_for_context, global_context.read().await.cmdline.pins_budget_share);
+        let pinned_files = resolve_pins(global_context.clone(), &pins).await;
+        info!("{} pins resolved into {} context files, limit {} tokens", pins.len(), pinned_files.len(), pins_limit);
+        let processed = postprocess_at_results(
+            global_context.clone(),
+            vec![ChatMessage { role: "context_file".to_string(), content: json!(pinned_files).to_string() }],
+            tokenizer.clone(),
+            pins_limit,
+        ).await;
+        let reloaded = reload_files(global_context.clone(), &processed, false).await;
+        for msg in reloaded {
+            // not streamed back, the client would store it in the history and get it twice next turn
+            reserve_for_context = reserve_for_context.saturating_sub(count_tokens(&tokenizer.read().unwrap(), &msg.content));
+            rebuilt_messages.push(msg);
+        }
+    }
+    let mut any_at_commands = false;
     for msg_idx in user_msg_starts..post.messages.len() {
         let mut user_posted = post.messages[msg_idx].content.clone();
         let user_posted_ntokens = count_tokens(&tokenizer.read().unwrap(), &user_posted);
@@ -216,23 +364,21 @@ pub async fn run_at_commands(
         info!("msg {} user_posted {:?} that's {} tokens", msg_idx, user_posted, user_posted_ntokens);
         info!("that leaves {} tokens for context of this message", context_limit);
 
-        let valid_commands = crate::at_commands::utils::find_valid_at_commands_in_query(&mut user_posted, &context).await;
-        let mut messages_for_postprocessing = vec![];
-        for cmd in valid_commands {
-            match cmd.command.lock().await.execute(&user_posted, &cmd.args, top_n, &context).await {
-                Ok(msg) => {
-                    messages_for_postprocessing.push(msg);
-                },
-                Err(e) => {
-                    tracing::warn!("can't execute command that indicated it can execute: {}", e);
-                }
+        let user_posted_original = user_posted.clone();
+        context.tokens_limit = context_limit;
+        let mut messages_for_postprocessing = crate::at_commands::utils::execute_at_commands_in_query(&mut user_posted, &context, top_n).await;
+        any_at_commands |= !messages_for_postprocessing.is_empty() || user_posted != user_posted_original;
+        if msg_idx == post.messages.len() - 1 && !any_at_commands {
+            if let Some(active_file) = &post.active_file {
+                messages_for_postprocessing = implicit_context_messages(active_file, &user_posted, &context, top_n).await;
             }
         }
-        let processed = postprocess_at_results(
+        let (processed, _report) = postprocess_at_results_with_report(
             global_context.clone(),
             messages_for_postprocessing,
             tokenizer.clone(),
-            context_limit
+            context_limit,
+            &post.context_selection,
         ).await;
         let reloaded = reload_files(global_context.clone(), &processed, false).await;
         for msg in reloaded {
@@ -280,3 +426,75 @@ impl HasVecdbResults {
         Ok(self.in_json.clone())
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use super::*;
+    use crate::global_context::{create_global_context_for_tests, words_tokenizer_for_tests};
+
+    async fn run_chat(global_context: Arc<ARwLock<GlobalContext>>, n_ctx: usize, question: &str, active_file: &str, chat_id: &str) -> Vec<ContextFile> {
+        let tokenizer = words_tokenizer_for_tests();
+        let mut post: ChatPost = serde_json::from_value(json!({
+            "messages": [{"role": "user", "content": question}],
+            "stream": false,
+            "active_file": {"file_name": active_file, "line": 100},
+            "chat_id": chat_id,
+        })).unwrap();
+        run_at_commands(global_context, tokenizer, 0, n_ctx, &mut post, 5, &mut HasVecdbResults::new()).await;
+        post.messages.iter()
+            .filter(|x| x.role == "context_file")
+            .flat_map(|x| serde_json::from_str::<Vec<ContextFile>>(&x.content).unwrap())
+            .collect()
+    }
+
+    async fn workspace_with_big_file(tmp_dir: &tempfile::TempDir) -> (Arc<ARwLock<GlobalContext>>, String) {
+        // big.py has 200 lines, x_1 = 1 .. x_200 = 200, it's the only file in the workspace
+        let path = tmp_dir.path().join("big.py");
+        let text: String = (1..=200).map(|i| format!("x_{} = {}\n", i, i)).collect();
+        std::fs::write(&path, text).unwrap();
+        let gcx = create_global_context_for_tests(tmp_dir.path().join("cache")).await;
+        gcx.read().await.documents_state.workspace_files.lock().unwrap().push(Url::from_file_path(&path).unwrap());
+        *gcx.read().await.documents_state.cache_dirty.lock().await = true;
+        (gcx, path.to_string_lossy().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_implicit_context() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (gcx, path) = workspace_with_big_file(&tmp_dir).await;
+
+        // 64 tokens for context, the question takes 4, each line takes 3: 20 lines around the cursor
+        let files = run_chat(gcx.clone(), RESERVE_FOR_QUESTION_AND_FOLLOWUP + 64, "why is it slow", &path, "").await;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_name, path);
+        assert!(files[0].line1 <= 100 && 100 <= files[0].line2, "{}-{}", files[0].line1, files[0].line2);
+        assert!(files[0].line2 - files[0].line1 + 1 <= 20, "{}-{}", files[0].line1, files[0].line2);
+
+        // an at-command in the question says what to look at, the cursor doesn't add anything
+        let files = run_chat(gcx.clone(), RESERVE_FOR_QUESTION_AND_FOLLOWUP + 64, "@file big.py:1-3 why is it slow", &path, "").await;
+        assert_eq!(files.len(), 1);
+        assert_eq!((files[0].line1, files[0].line2), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn test_pins_need_chat_id() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (gcx, path) = workspace_with_big_file(&tmp_dir).await;
+        {
+            let pins_arc = gcx.read().await.context_pins.clone();
+            let mut pins_locked = pins_arc.lock().await;
+            let pin = ContextPin { file_name: path.clone(), line1: 150, line2: 152, ..Default::default() };
+            pins_locked.insert("".to_string(), vec![pin.clone()]);
+            pins_locked.insert("chat-1".to_string(), vec![pin]);
+        }
+
+        // the code actions and other internal chats post without chat_id, they get no pins
+        let files = run_chat(gcx.clone(), RESERVE_FOR_QUESTION_AND_FOLLOWUP + 64, "@file big.py:1-3 why is it slow", &path, "").await;
+        assert_eq!(files.iter().map(|x| (x.line1, x.line2)).collect::<Vec<_>>(), vec![(1, 3)]);
+
+        let files = run_chat(gcx.clone(), RESERVE_FOR_QUESTION_AND_FOLLOWUP + 64, "@file big.py:1-3 why is it slow", &path, "chat-1").await;
+        assert!(files.iter().any(|x| (x.line1, x.line2) == (150, 152)), "{:?}", files.iter().map(|x| (x.line1, x.line2)).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,173 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock as ARwLock;
+use tracing::info;
+
+use crate::background_tasks::{switch_ast, switch_vecdb};
+use crate::completion_cache::CompletionCache;
+use crate::global_context::GlobalContext;
+
+
+// The part of CommandLine that plugin settings can change without restarting the binary.
+// The api key is write-only, it's not sent back.
+#[derive(Debug, Serialize, Clone)]
+pub struct Settings {
+    pub address_url: String,
+    pub ast: bool,
+    pub vecdb: bool,
+    pub basic_telemetry: bool,
+    pub snippet_telemetry: bool,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct SettingsPatch {
+    #[serde(default, alias = "addressUrl")]
+    pub address_url: Option<String>,
+    #[serde(default, alias = "apiKey")]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub ast: Option<bool>,
+    #[serde(default)]
+    pub vecdb: Option<bool>,
+    #[serde(default, alias = "basicTelemetry")]
+    pub basic_telemetry: Option<bool>,
+    #[serde(default, alias = "snippetTelemetry")]
+    pub snippet_telemetry: Option<bool>,
+}
+
+impl SettingsPatch {
+    pub fn changes_endpoint(&self) -> bool {
+        self.address_url.is_some() || self.api_key.is_some()
+    }
+}
+
+pub async fn get_settings(gcx: Arc<ARwLock<GlobalContext>>) -> Settings {
+    let gcx_locked = gcx.read().await;
+    let cmdline = &gcx_locked.cmdline;
+    Settings {
+        address_url: cmdline.address_url.clone(),
+        ast: cmdline.ast,
+        vecdb: cmdline.vecdb,
+        basic_telemetry: cmdline.basic_telemetry,
+        snippet_telemetry: cmdline.snippet_telemetry,
+    }
+}
+
+pub async fn apply_settings(gcx: Arc<ARwLock<GlobalContext>>, patch: SettingsPatch) -> Result<Settings, String> {
+    let (ast_to, vecdb_to) = {
+        let mut gcx_locked = gcx.write().await;
+        let cmdline = &mut gcx_locked.cmdline;
+        let mut endpoint_changed = false;
+        if let Some(address_url) = patch.address_url.filter(|x| *x != cmdline.address_url) {
+            info!("settings: address_url {:?}", address_url);
+            cmdline.address_url = address_url;
+            endpoint_changed = true;
+        }
+        if let Some(api_key) = patch.api_key.filter(|x| *x != cmdline.api_key) {
+            info!("settings: api_key changed");
+            cmdline.api_key = api_key;
+            endpoint_changed = true;
+        }
+        // the telemetry tasks read these every time they send
+        if let Some(basic_telemetry) = patch.basic_telemetry {
+            cmdline.basic_telemetry = basic_telemetry;
+        }
+        if let Some(snippet_telemetry) = patch.snippet_telemetry {
+            cmdline.snippet_telemetry = snippet_telemetry;
+        }
+        let ast_to = patch.ast.filter(|x| *x != cmdline.ast);
+        let vecdb_to = patch.vecdb.filter(|x| *x != cmdline.vecdb);
+        cmdline.ast = patch.ast.unwrap_or(cmdline.ast);
+        cmdline.vecdb = patch.vecdb.unwrap_or(cmdline.vecdb);
+        let vecdb_running = cmdline.vecdb;
+
+        if endpoint_changed {
+            // another server might have other models, everything that came from the old one goes
+            gcx_locked.caps = None;
+            gcx_locked.caps_last_attempted_ts = 0;
+            gcx_locked.caps_last_error = String::new();
+            gcx_locked.tokenizer_map.clear();
+            *gcx_locked.completions_cache.write().unwrap() = CompletionCache::new();
+        }
+        // vecdb holds the key it was started with, and the embedding model comes from caps
+        (ast_to, vecdb_to.or(if endpoint_changed && vecdb_running { Some(true) } else { None }))
+    };
+
+    if let Some(enabled) = ast_to {
+        if let Err(e) = switch_ast(gcx.clone(), enabled).await {
+            gcx.write().await.cmdline.ast = false;
+            return Err(format!("cannot switch AST on: {}", e));
+        }
+    }
+    if let Some(enabled) = vecdb_to {
+        switch_vecdb(gcx.clone(), enabled).await;
+    }
+    Ok(get_settings(gcx).await)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock as StdRwLock;
+
+    use super::*;
+    use crate::caps::CodeAssistantCaps;
+    use crate::global_context::{create_global_context_for_tests, words_tokenizer_for_tests};
+
+    #[tokio::test]
+    async fn test_apply_settings_endpoint_resets_caps() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let gcx = create_global_context_for_tests(tmp_dir.path().to_path_buf()).await;
+        let set_caps = || async {
+            let mut gcx_locked = gcx.write().await;
+            gcx_locked.caps = Some(Arc::new(StdRwLock::new(CodeAssistantCaps::default())));
+            gcx_locked.caps_last_error = "caps fetch failed".to_string();
+            gcx_locked.tokenizer_map.insert("model".to_string(), words_tokenizer_for_tests());
+        };
+
+        // the same address is not a change
+        set_caps().await;
+        let settings = apply_settings(gcx.clone(), SettingsPatch { address_url: Some("http://127.0.0.1:1".to_string()), ..Default::default() }).await.unwrap();
+        assert_eq!(settings.address_url, "http://127.0.0.1:1");
+        assert!(gcx.read().await.caps.is_some());
+
+        let settings = apply_settings(gcx.clone(), SettingsPatch { address_url: Some("http://127.0.0.1:2".to_string()), ..Default::default() }).await.unwrap();
+        assert_eq!(settings.address_url, "http://127.0.0.1:2");
+        {
+            let gcx_locked = gcx.read().await;
+            assert!(gcx_locked.caps.is_none());
+            assert!(gcx_locked.caps_last_error.is_empty());
+            assert!(gcx_locked.tokenizer_map.is_empty());
+        }
+
+        set_caps().await;
+        apply_settings(gcx.clone(), SettingsPatch { api_key: Some("new-key".to_string()), ..Default::default() }).await.unwrap();
+        assert_eq!(gcx.read().await.cmdline.api_key, "new-key");
+        assert!(gcx.read().await.caps.is_none());
+        assert!(gcx.read().await.tokenizer_map.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_apply_settings_switches_ast_and_vecdb() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let gcx = create_global_context_for_tests(tmp_dir.path().to_path_buf()).await;
+        let ast_module = gcx.read().await.ast_module.clone();
+        assert!(ast_module.lock().await.is_none());
+
+        let settings = apply_settings(gcx.clone(), SettingsPatch { ast: Some(true), ..Default::default() }).await.unwrap();
+        assert!(settings.ast);
+        assert!(ast_module.lock().await.is_some());
+        let settings = apply_settings(gcx.clone(), SettingsPatch { ast: Some(false), ..Default::default() }).await.unwrap();
+        assert!(!settings.ast);
+        assert!(ast_module.lock().await.is_none());
+
+        // vecdb starts once caps tell the embedding model, the settings only say it should run
+        let settings = apply_settings(gcx.clone(), SettingsPatch { vecdb: Some(true), ..Default::default() }).await.unwrap();
+        assert!(settings.vecdb);
+        let settings = apply_settings(gcx.clone(), SettingsPatch { vecdb: Some(false), basic_telemetry: Some(false), ..Default::default() }).await.unwrap();
+        assert!(!settings.vecdb);
+        assert!(!settings.basic_telemetry);
+        assert!(gcx.read().await.vec_db.lock().await.is_none());
+    }
+}
@@ -145,6 +145,22 @@ toolbox_commands:
     - role: "user"
       content: "@file %CURRENT_FILE%:%CURSOR_LINE%\nRewrite this specific code block into a very inefficient and cryptic one, but still correct. Rename variables to misleading gibberish. Add unnecessary complexity. Make O(N) worse. Don't forget about bad formatting and random spaces.\n\n```\n%CODE_SELECTION%```\n"
 
+# Your own at-commands, they appear in the chat completion next to the built-in ones.
+# A macro expands to built-in at-commands, %ARGS% is replaced with the arguments the user typed.
+# An executable gets {"command", "args", "query", "top_n", "workspace_folders"} as json on stdin, and
+# should print a json list of {"file_name", "file_content", "line1", "line2", "usefulness"} to stdout.
+# Only the programs given to refact-lsp with --allow-executable on the command line can run, this file can't allow them.
+#
+# custom_at_commands:
+#   "@explain-symbol":
+#     description: "Definition and usages of a symbol"
+#     params: ["symbol"]
+#     expand_to: "@definition %ARGS%\n@references %ARGS%"
+#   "@ticket":
+#     description: "Text of an issue from the tracker"
+#     params: ["ticket_id"]
+#     executable: ["/usr/local/bin/ticket-context", "--json"]
+
 
 
 # To help you write by analogy, the default config as was compiled-in at the time of the first run of refact-lsp:
@@ -2,7 +2,11 @@ use serde_yaml::Value;
 use serde_yaml;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::RwLock as ARwLock;
 use crate::call_validation::ChatMessage;
+use crate::global_context::GlobalContext;
 use std::io::Write;
 
 
@@ -10,6 +14,8 @@ use std::io::Write;
 pub struct ToolboxConfig {
     pub system_prompts: HashMap<String, SystemPrompt>,
     pub toolbox_commands: HashMap<String, ToolboxCommand>,
+    #[serde(default)]
+    pub custom_at_commands: HashMap<String, CustomAtCommand>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -31,6 +37,24 @@ pub struct ToolboxCommand {
     pub insert_at_cursor: bool,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CustomAtCommand {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub params: Vec<String>,      // "file_path", "symbol" or any other name for free text
+    #[serde(default)]
+    pub expand_to: String,        // a macro: other at-commands, %ARGS% is replaced with the arguments
+    #[serde(default)]
+    pub executable: Vec<String>,  // or a local program with its arguments, must be allowed by --allow-executable
+}
+
+#[derive(Default)]
+pub struct ToolboxConfigCache {
+    mtime: Option<SystemTime>,
+    config: Option<Result<Arc<ToolboxConfig>, String>>,
+}
+
 fn _extract_mapping_values(mapping: &Option<&serde_yaml::Mapping>, variables: &mut HashMap<String, String>)
 {
     if let Some(mapping) = mapping {
@@ -88,6 +112,7 @@ fn _load_and_mix_with_users_config(user_yaml: &str) -> Result<ToolboxConfig, Str
 
     work_config.toolbox_commands.extend(user_config.toolbox_commands.iter().map(|(k, v)| (k.clone(), v.clone())));
     work_config.system_prompts.extend(user_config.system_prompts.iter().map(|(k, v)| (k.clone(), v.clone())));
+    work_config.custom_at_commands.extend(user_config.custom_at_commands.iter().map(|(k, v)| (k.clone(), v.clone())));
     Ok(work_config)
 }
 
@@ -114,6 +139,29 @@ pub fn load_customization_high_level(cache_dir: std::path::PathBuf) -> Result<To
     _load_and_mix_with_users_config(&user_config_text).map_err(|e| e.to_string())
 }
 
+pub async fn load_customization_cached(global_context: Arc<ARwLock<GlobalContext>>) -> Result<Arc<ToolboxConfig>, String> {
+    let (cache_dir, cache_arc) = {
+        let gcx_locked = global_context.read().await;
+        (gcx_locked.cache_dir.clone(), gcx_locked.toolbox_config_cache.clone())
+    };
+    let user_config_path = cache_dir.join("customization.yaml");
+    let mtime_of = |path: &std::path::PathBuf| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut cache_locked = cache_arc.lock().await;
+    if let Some(config) = &cache_locked.config {
+        if cache_locked.mtime.is_some() && cache_locked.mtime == mtime_of(&user_config_path) {
+            return config.clone();
+        }
+    }
+    let config = tokio::task::spawn_blocking(move || load_customization_high_level(cache_dir))
+        .await
+        .map_err(|e| format!("loading customization.yaml panicked: {}", e))?
+        .map(Arc::new);
+    // taken after loading, the first load creates the file
+    cache_locked.mtime = mtime_of(&user_config_path);
+    cache_locked.config = Some(config.clone());
+    config
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,4 +170,33 @@ mod tests {
     fn is_compiled_in_toolbox_valid_toml() {
         let _config = _load_and_mix_with_users_config(crate::toolbox::toolbox_compiled_in::COMPILED_IN_INITIAL_USER_YAML);
     }
+
+    #[test]
+    fn custom_at_commands_are_mixed_in() {
+        let user_yaml = r#"
+system_prompts: {}
+toolbox_commands: {}
+custom_at_commands:
+  "@explain-symbol":
+    params: ["symbol"]
+    expand_to: "@definition %ARGS%\n@references %ARGS%"
+  "@jira":
+    params: ["ticket"]
+    executable: ["jira-context", "--json"]
+"#;
+        let config = _load_and_mix_with_users_config(user_yaml).unwrap();
+        assert!(!config.toolbox_commands.is_empty());
+        assert_eq!(config.custom_at_commands["@explain-symbol"].expand_to, "@definition %ARGS%\n@references %ARGS%");
+        assert_eq!(config.custom_at_commands["@jira"].executable, vec!["jira-context", "--json"]);
+    }
+
+    #[tokio::test]
+    async fn customization_is_loaded_once() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let gcx = crate::global_context::create_global_context_for_tests(cache_dir.path().to_path_buf()).await;
+        let first = load_customization_cached(gcx.clone()).await.unwrap();
+        assert!(cache_dir.path().join("customization.yaml").exists());
+        let second = load_customization_cached(gcx.clone()).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
 }
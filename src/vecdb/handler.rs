@@ -538,9 +538,8 @@ impl VecDBHandler {
                 info!("Error while deleting from cache table: {:?}", err);
             }
         }
-        // valerii: In documentation I found no way to preprocess strings to prevent SQL injections
         match self.data_table.delete(
-            format!("(file_path = \"{}\")", file_path_str).as_str()  // TODO: Prevent a possible sql injection here
+            format!("(file_path = {})", sql_string_literal(file_path_str)).as_str()
         ).await {
             Ok(_) => {}
             Err(err) => {
@@ -549,6 +548,60 @@ impl VecDBHandler {
         }
     }
 
+    pub async fn rename(&mut self, old_path: &PathBuf, new_path: &PathBuf) {
+        // the same windows of text under another path, the embeddings are reused and not requested again
+        let old_path_str = match old_path.to_str() {
+            None => {
+                info!("File path is not a string");
+                return;
+            }
+            Some(res) => res
+        };
+        let batches: Vec<RecordBatch> = match self.data_table
+            .filter(format!("(file_path = {})", sql_string_literal(old_path_str)))
+            .execute()
+            .await {
+            Ok(stream) => match stream.try_collect::<Vec<_>>().await {
+                Ok(batches) => batches,
+                Err(err) => {
+                    info!("Error while reading records to rename: {:?}", err);
+                    return;
+                }
+            },
+            Err(err) => {
+                info!("Error while reading records to rename: {:?}", err);
+                return;
+            }
+        };
+        let mut records: Vec<Record> = vec![];
+        for rec_batch in batches {
+            match VecDBHandler::parse_table_iter(rec_batch, true, None) {
+                Ok(res) => records.extend(res),
+                Err(err) => info!("Error while parsing records to rename: {:?}", err),
+            }
+        }
+        for record in records.iter_mut() {
+            record.file_path = new_path.clone();
+        }
+        self.remove(new_path).await;
+        self.remove(old_path).await;
+        let records_count = records.len();
+        match self.add_or_update(records, true).await {
+            Ok(_) => {}
+            Err(err) => info!("Error while adding renamed records: {:?}", err),
+        }
+        {
+            let mut indexed_file_paths = self.indexed_file_paths.lock().await;
+            indexed_file_paths.retain(|x| x != old_path && x != new_path);
+            if records_count > 0 {
+                indexed_file_paths.push(new_path.clone());
+            }
+        }
+        info!("renamed {} records {} -> {}", records_count,
+            crate::nicer_logs::last_n_chars(&old_path.display().to_string(), 30),
+            crate::nicer_logs::last_n_chars(&new_path.display().to_string(), 30));
+    }
+
     pub async fn create_index(&mut self) -> vectordb::error::Result<()> {
         let size = self.size().await.unwrap_or(0);
         if size == 0 {
@@ -694,3 +747,71 @@ impl VecDBHandler {
         Ok(())
     }
 }
+
+
+fn sql_string_literal(value: &str) -> String {
+    // lance parses the filters as SQL, a quote inside a literal is doubled, backslashes are not escapes
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(path: &str, text: &str) -> Record {
+        Record {
+            vector: Some(vec![0.5; 4]),
+            window_text: text.to_string(),
+            window_text_hash: format!("{:x}", md5::compute(text)),
+            file_path: PathBuf::from(path),
+            start_line: 0,
+            end_line: 1,
+            time_added: SystemTime::now(),
+            time_last_used: SystemTime::now(),
+            model_name: "test-model".to_string(),
+            used_counter: 0,
+            distance: 0.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let cache_dir = tempdir().unwrap();
+        let mut handler = VecDBHandler::init(&cache_dir.path().to_path_buf(), &"test-model".to_string(), 4).await.unwrap();
+        handler.add_or_update(vec![
+            record("/tmp/vecdb/old.py", "def f():\n    pass"),
+            record("/tmp/vecdb/old.py", "def g():\n    pass"),
+            record("/tmp/vecdb/other.py", "x = 1"),
+        ], true).await.unwrap();
+
+        handler.rename(&PathBuf::from("/tmp/vecdb/old.py"), &PathBuf::from("/tmp/vecdb/new.py")).await;
+        let mut file_paths = handler.select_all_file_paths().await;
+        file_paths.sort();
+        assert_eq!(file_paths, vec![PathBuf::from("/tmp/vecdb/new.py"), PathBuf::from("/tmp/vecdb/other.py")]);
+        // the same records, nothing to vectorize again
+        assert_eq!(handler.size().await.unwrap(), 3);
+        assert!(handler.contains(&format!("{:x}", md5::compute("def f():\n    pass"))));
+        assert_eq!(*handler.get_indexed_file_paths().await.lock().await, vec![PathBuf::from("/tmp/vecdb/new.py")]);
+    }
+
+    #[tokio::test]
+    async fn test_paths_with_quotes() {
+        let cache_dir = tempdir().unwrap();
+        let mut handler = VecDBHandler::init(&cache_dir.path().to_path_buf(), &"test-model".to_string(), 4).await.unwrap();
+        let quoted = r#"/tmp/vecdb/it's "x") or (1 = 1.py"#;
+        handler.add_or_update(vec![
+            record(quoted, "def f():\n    pass"),
+            record("/tmp/vecdb/other.py", "x = 1"),
+        ], true).await.unwrap();
+
+        // the quotes stay inside the literal, the filter matches the one file and nothing else
+        handler.rename(&PathBuf::from(quoted), &PathBuf::from("/tmp/vecdb/new.py")).await;
+        let mut file_paths = handler.select_all_file_paths().await;
+        file_paths.sort();
+        assert_eq!(file_paths, vec![PathBuf::from("/tmp/vecdb/new.py"), PathBuf::from("/tmp/vecdb/other.py")]);
+        handler.remove(&PathBuf::from(r"C:\vecdb\it's.py")).await;
+        handler.remove(&PathBuf::from("/tmp/vecdb/new.py")).await;
+        assert_eq!(handler.select_all_file_paths().await, vec![PathBuf::from("/tmp/vecdb/other.py")]);
+    }
+}
@@ -1,5 +1,6 @@
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
 use std::sync::RwLock as StdRwLock;
 use tokio::sync::RwLock as ARwLock;
 use tokio::sync::Mutex as AMutex;
@@ -14,6 +15,7 @@ use crate::background_tasks::BackgroundTasksHolder;
 use crate::fetch_embedding;
 use crate::files_in_jsonl::files_in_jsonl;
 use crate::files_in_workspace::DocumentInfo;
+use crate::files_priority::FilesPriority;
 use crate::vecdb::handler::VecDBHandler;
 use crate::vecdb::vectorizer_service::FileVectorizerService;
 use crate::vecdb::structs::{SearchResult, VecdbSearch, VecDbStatus, VecdbConstants};
@@ -75,9 +77,9 @@ async fn create_vecdb(
 ) -> Result<(), String> {
     info!("vecdb: attempting to launch");
 
-    let (cache_dir, cmdline) = {
+    let (cache_dir, cmdline, files_priority) = {
         let gcx_locked = global_context.read().await;
-        (gcx_locked.cache_dir.clone(), gcx_locked.cmdline.clone())
+        (gcx_locked.cache_dir.clone(), gcx_locked.cmdline.clone(), gcx_locked.documents_state.files_priority.clone())
     };
     let base_dir: PathBuf = match cmdline.vecdb_forced_path.as_str() {
         "" => cache_dir,
@@ -87,6 +89,7 @@ async fn create_vecdb(
         &base_dir,
         cmdline.clone(),
         constants,
+        files_priority,
     ).await {
         Ok(res) => Some(res),
         Err(err) => {
@@ -204,6 +207,7 @@ impl VecDb {
         cache_dir: &PathBuf,
         cmdline: CommandLine,
         constants: VecdbConstants,
+        files_priority: Arc<StdMutex<FilesPriority>>,
     ) -> Result<VecDb, String> {
         let handler = match VecDBHandler::init(cache_dir, &constants.model_name, constants.embedding_size).await {
             Ok(res) => res,
@@ -212,6 +216,7 @@ impl VecDb {
         let vecdb_handler = Arc::new(AMutex::new(handler));
         let vectorizer_service = Arc::new(AMutex::new(FileVectorizerService::new(
             vecdb_handler.clone(),
+            files_priority,
             constants.clone(),
             cmdline.api_key.clone(),
         ).await));
@@ -237,6 +242,10 @@ impl VecDb {
         self.vecdb_handler.lock().await.remove(file_path).await;
     }
 
+    pub async fn rename_file(&self, old_path: &PathBuf, new_path: &PathBuf) {
+        self.vecdb_handler.lock().await.rename(old_path, new_path).await;
+    }
+
     pub async fn get_status(&self) -> Result<VecDbStatus, String> {
         self.vectorizer_service.lock().await.status().await
     }
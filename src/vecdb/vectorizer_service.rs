@@ -2,6 +2,7 @@ use std::collections::{HashMap, VecDeque};
 use std::io::Write;
 use std::ops::Div;
 use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
 use std::time::SystemTime;
 
 use tokio::sync::Mutex as AMutex;
@@ -12,6 +13,7 @@ use tracing::info;
 use crate::ast::file_splitter::AstBasedFileSplitter;
 use crate::fetch_embedding::try_get_embedding;
 use crate::files_in_workspace::DocumentInfo;
+use crate::files_priority::FilesPriority;
 use crate::vecdb::handler::VecDBHandler;
 use crate::vecdb::structs::{Record, SplitResult, VecdbConstants, VecDbStatus};
 
@@ -21,6 +23,7 @@ pub struct FileVectorizerService {
     output_queue: Arc<AMutex<VecDeque<DocumentInfo>>>,
     vecdb_handler: Arc<AMutex<VecDBHandler>>,
     status: Arc<AMutex<VecDbStatus>>,
+    files_priority: Arc<StdMutex<FilesPriority>>,
     constants: VecdbConstants,
     api_key: String,
 }
@@ -76,6 +79,7 @@ async fn vectorize_thread(
     queue: Arc<AMutex<VecDeque<DocumentInfo>>>,
     vecdb_handler_ref: Arc<AMutex<VecDBHandler>>,
     status: Arc<AMutex<VecDbStatus>>,
+    files_priority: Arc<StdMutex<FilesPriority>>,
     constants: VecdbConstants,
     api_key: String,
     max_concurrent_tasks: usize,
@@ -83,12 +87,21 @@ async fn vectorize_thread(
     let semaphore = Arc::new(Semaphore::new(max_concurrent_tasks));
     let mut reported_unprocessed: usize = 0;
     let mut reported_vecdb_complete: bool = false;
+    let mut sorted_generation: u64 = u64::MAX;
+    let mut sorted_len: usize = 0;
 
     loop {
         let (doc_maybe, unprocessed_files_count) = {
             let mut queue_locked = queue.lock().await;
             let queue_len = queue_locked.len();
             if queue_len > 0 {
+                // sort again only if the user switched files or something was added
+                let files_priority_locked = files_priority.lock().unwrap();
+                if files_priority_locked.generation() != sorted_generation || queue_len > sorted_len {
+                    files_priority_locked.sort_docs(queue_locked.make_contiguous());
+                    sorted_generation = files_priority_locked.generation();
+                }
+                sorted_len = queue_len - 1;
                 (Some(queue_locked.pop_front().unwrap()), queue_len)
             } else {
                 (None, 0)
@@ -235,6 +248,7 @@ async fn cleanup_thread(vecdb_handler: Arc<AMutex<VecDBHandler>>) {
 impl FileVectorizerService {
     pub async fn new(
         vecdb_handler: Arc<AMutex<VecDBHandler>>,
+        files_priority: Arc<StdMutex<FilesPriority>>,
         constants: VecdbConstants,
         api_key: String,
     ) -> Self {
@@ -253,6 +267,7 @@ impl FileVectorizerService {
             output_queue: output_queue.clone(),
             vecdb_handler: vecdb_handler.clone(),
             status: status.clone(),
+            files_priority,
             constants,
             api_key,
         }
@@ -274,6 +289,7 @@ impl FileVectorizerService {
                 self.output_queue.clone(),
                 self.vecdb_handler.clone(),
                 self.status.clone(),
+                self.files_priority.clone(),
                 self.constants.clone(),
                 self.api_key.clone(),
                 4,